@@ -374,6 +374,7 @@ impl hal::Instance for Instance {
                 buffer_image_granularity: 1,
                 non_coherent_atom_size: 1,               // TODO
                 max_sampler_anisotropy: 16.,
+                line_width_range: [1.0, 1.0],
                 optimal_buffer_copy_offset_alignment: 1,                                      // TODO
                 optimal_buffer_copy_pitch_alignment: 1,                                       // TODO
                 min_vertex_input_binding_stride_alignment: 1,
@@ -0,0 +1,161 @@
+//! Generates the wrappers logging the GL calls, with the `call-log` feature.
+//!
+//! There is one wrapper per command of the registry `gfx_gl` is generated
+//! from. Each one logs its arguments, then forwards them to the function
+//! loaded from the driver, see `src/call_log.rs`.
+
+#[cfg(feature = "call-log")]
+extern crate gl_generator;
+
+fn main() {
+    #[cfg(feature = "call-log")]
+    call_log::generate();
+}
+
+#[cfg(feature = "call-log")]
+mod call_log {
+    use std::env;
+    use std::fs::File;
+    use std::io::{self, Write};
+    use std::path::Path;
+
+    use gl_generator::{Api, Fallbacks, Profile, Registry};
+
+    /// Category of a command, the name of a `CallCategories` flag.
+    fn category(name: &str) -> &'static str {
+        let starts = |prefixes: &[&str]| prefixes.iter().any(|p| name.starts_with(p));
+        if starts(&["Draw", "MultiDraw", "Dispatch", "Clear"]) && !name.contains("Data") {
+            "DRAW"
+        } else if starts(&["Bind", "UseProgram", "ActiveTexture"]) {
+            "BIND"
+        } else if starts(&["Get", "Is", "Check"]) {
+            "QUERY"
+        } else if starts(&["Uniform", "ProgramUniform"]) {
+            "UNIFORM"
+        } else if name.contains("Shader") || name.contains("Program") {
+            "SHADER"
+        } else if starts(&["Finish", "MemoryBarrier"]) || name == "Flush" || name.contains("Sync") {
+            "SYNC"
+        } else if starts(&[
+            "Gen",
+            "Create",
+            "Delete",
+            "Tex",
+            "Buffer",
+            "NamedBuffer",
+            "Clear",
+            "Map",
+            "Unmap",
+            "FlushMapped",
+            "Copy",
+            "Compressed",
+            "Framebuffer",
+            "NamedFramebuffer",
+            "Renderbuffer",
+            "NamedRenderbuffer",
+            "Invalidate",
+            "ReadPixels",
+            "ReadnPixels",
+            "Blit",
+        ]) {
+            "RESOURCE"
+        } else {
+            "STATE"
+        }
+    }
+
+    fn write_wrappers<W: Write>(registry: &Registry, dest: &mut W) -> io::Result<()> {
+        for cmd in &registry.cmds {
+            let name = &cmd.proto.ident;
+            let params = cmd
+                .params
+                .iter()
+                .map(|param| format!("{}: {}", param.ident, param.ty))
+                .collect::<Vec<_>>();
+            let idents = cmd
+                .params
+                .iter()
+                .map(|param| &*param.ident)
+                .collect::<Vec<_>>();
+            let types = cmd
+                .params
+                .iter()
+                .map(|param| &*param.ty)
+                .collect::<Vec<_>>();
+            let format = vec!["{:?}"; cmd.params.len()].join(", ");
+            writeln!(
+                dest,
+                "static REAL_{name}: AtomicUsize = AtomicUsize::new(0);
+#[allow(non_snake_case, unused_parens)]
+extern \"system\" fn {name}({params}) -> {ret} {{
+    if is_logged(CallCategories::{category}) {{
+        trace!(target: TARGET, \"{symbol}({format})\"{comma}{idents});
+    }}
+    let real = REAL_{name}.load(Ordering::Relaxed);
+    unsafe {{ mem::transmute::<_, extern \"system\" fn({types}) -> {ret}>(real)({idents}) }}
+}}",
+                name = name,
+                params = params.join(", "),
+                ret = cmd.proto.ty,
+                category = category(name),
+                symbol = format!("gl{}", name),
+                format = format,
+                comma = if idents.is_empty() { "" } else { ", " },
+                idents = idents.join(", "),
+                types = types.join(", "),
+            )?;
+        }
+
+        writeln!(
+            dest,
+            "/// Wrap a function loaded from the driver, keeping it to forward the
+/// calls to.
+pub(crate) fn wrap(symbol: &str, ptr: *const c_void) -> *const c_void {{
+    if ptr.is_null() {{
+        return ptr;
+    }}
+    let (real, wrapper) = match symbol {{"
+        )?;
+        for cmd in &registry.cmds {
+            let name = &cmd.proto.ident;
+            let symbols = Some(name)
+                .into_iter()
+                .chain(registry.aliases.get(name).into_iter().flatten())
+                .map(|name| format!("\"gl{}\"", name))
+                .collect::<Vec<_>>();
+            writeln!(
+                dest,
+                "        {} => (&REAL_{name}, {name} as *const c_void),",
+                symbols.join(" | "),
+                name = name,
+            )?;
+        }
+        writeln!(
+            dest,
+            "        _ => return ptr,
+    }};
+    real.store(ptr as usize, Ordering::Relaxed);
+    wrapper
+}}"
+        )
+    }
+
+    pub fn generate() {
+        let out_dir = env::var("OUT_DIR").unwrap();
+        let mut file = File::create(&Path::new(&out_dir).join("call_log_wrappers.rs")).unwrap();
+
+        // Same registry as `gfx_gl`.
+        let registry = Registry::new(
+            Api::Gl,
+            (4, 5),
+            Profile::Core,
+            Fallbacks::All,
+            [
+                "GL_EXT_texture_filter_anisotropic",
+                "GL_ARB_draw_buffers_blend",
+                "GL_ARB_program_interface_query",
+            ],
+        );
+        write_wrappers(&registry, &mut file).unwrap();
+    }
+}
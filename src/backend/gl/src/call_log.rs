@@ -0,0 +1,69 @@
+//! Logging of the GL calls, with the `call-log` feature.
+//!
+//! Every function of the GL table is wrapped when loading it, the calls of
+//! the categories selected by `DeviceOptions::call_log` are logged with
+//! their arguments at the `trace` level, under the `gfx_backend_gl::calls`
+//! target. This helps diagnosing issues on platforms where tools like
+//! apitrace or RenderDoc aren't available.
+//!
+//! Extension functions loaded separately from the GL table aren't logged.
+//! The wrappers forward to the functions loaded by the last adapter
+//! created, so only a single adapter should be used with this feature.
+
+use std::mem;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[allow(unused_imports)]
+use crate::gl::types;
+
+const TARGET: &str = "gfx_backend_gl::calls";
+
+bitflags! {
+    /// Categories of GL calls to log.
+    pub struct CallCategories: u32 {
+        /// Draws, dispatches and clears.
+        const DRAW = 0x1;
+        /// Binds of objects to the context.
+        const BIND = 0x2;
+        /// Fixed-function state changes.
+        const STATE = 0x4;
+        /// Uniform updates.
+        const UNIFORM = 0x8;
+        /// Creation, update and destruction of buffers, textures,
+        /// framebuffers and other objects.
+        const RESOURCE = 0x10;
+        /// Shader and program management.
+        const SHADER = 0x20;
+        /// Fences, barriers, flushes and waits.
+        const SYNC = 0x40;
+        /// Queries of the state, of errors and of objects.
+        const QUERY = 0x80;
+    }
+}
+
+impl Default for CallCategories {
+    fn default() -> Self {
+        CallCategories::empty()
+    }
+}
+
+static CATEGORIES: AtomicUsize = AtomicUsize::new(0);
+
+/// Select the categories of calls to log, replacing the previous ones.
+pub(crate) fn set_categories(categories: CallCategories) {
+    CATEGORIES.store(categories.bits() as usize, Ordering::Relaxed);
+}
+
+#[inline]
+fn is_logged(category: CallCategories) -> bool {
+    CATEGORIES.load(Ordering::Relaxed) & category.bits() as usize != 0
+}
+
+// Types referenced by the wrappers, the same as in the `gfx_gl` bindings.
+#[allow(unused_imports)]
+mod __gl_imports {
+    pub use std::os::raw;
+}
+
+include!(concat!(env!("OUT_DIR"), "/call_log_wrappers.rs"));
@@ -2,12 +2,13 @@
 
 use crate::gl;
 
-use crate::hal::format::ChannelType;
+use crate::hal::backend::FastHashMap;
+use crate::hal::format::{ChannelType, Format};
 use crate::hal::range::RangeArg;
-use crate::hal::{self, buffer, command, image, memory, pass, pso, query, ColorSlot};
+use crate::hal::{self, buffer, command, format, image, memory, pass, pso, query, ColorSlot};
 
 use crate::pool::{self, BufferMemory};
-use crate::{native as n, Backend};
+use crate::{native as n, state, Backend};
 
 use std::borrow::Borrow;
 use std::ops::Range;
@@ -25,6 +26,7 @@ use std::{mem, slice};
 
 /// The place of some data in a buffer.
 #[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "trace", derive(Serialize, Deserialize))]
 pub struct BufferSlice {
     pub offset: u32,
     pub size: u32,
@@ -51,6 +53,7 @@ impl BufferSlice {
 
 ///
 #[derive(Debug)]
+#[cfg_attr(feature = "trace", derive(Serialize, Deserialize))]
 pub enum Command {
     Dispatch(hal::WorkGroupCount),
     DispatchIndirect(gl::types::GLuint, buffer::Offset),
@@ -66,6 +69,8 @@ pub enum Command {
         index_buffer_offset: buffer::Offset,
         base_vertex: hal::VertexOffset,
         instances: Range<hal::InstanceCount>,
+        // Smallest and largest index read, if known.
+        index_bounds: Option<(u32, u32)>,
     },
     BindIndexBuffer(gl::types::GLuint),
     //BindVertexBuffers(BufferSlice),
@@ -76,6 +81,11 @@ pub enum Command {
     },
     SetScissors(u32, BufferSlice),
     SetBlendColor(pso::ColorValue),
+    SetDepthBounds(Range<f32>),
+    /// Enable the sRGB encoding of the color written to sRGB attachments.
+    SetFramebufferSrgb(bool),
+    SetLineWidth(f32),
+    SetLineSmooth(bool),
 
     /// Clear floating-point color drawbuffer of bound framebuffer.
     ClearBufferColorF(DrawBuffer, [f32; 4]),
@@ -94,21 +104,53 @@ pub enum Command {
 
     BindFrameBuffer(FrameBufferTarget, n::FrameBuffer),
     BindTargetView(FrameBufferTarget, AttachmentPoint, n::ImageView),
-    SetDrawColorBuffers(usize),
+    /// Resolve a color attachment of the framebuffer bound for drawing to
+    /// one of its resolve attachments, given by index.
+    ResolveAttachment(n::FrameBuffer, pass::AttachmentId, pass::AttachmentId),
+    /// Discard the content of attachments of the framebuffer bound for
+    /// drawing. The buffer slice contains a list of `GLenum`.
+    InvalidateAttachments(BufferSlice),
     SetPatchSize(gl::types::GLint),
     BindProgram(gl::types::GLuint),
-    BindBlendSlot(ColorSlot, pso::ColorBlendDesc),
-    BindAttribute(
-        n::AttributeDesc,
-        gl::types::GLuint,
-        gl::types::GLsizei,
-        gl::types::GLuint,
-    ),
+    SetRasterizer(state::RasterizerState),
+    SetDepthState(state::DepthState),
+    SetStencilState(state::StencilState),
+    BindBlendSlot(ColorSlot, state::BlendSlotState),
+    /// Bind the vertex array of a vertex layout, pointing its attributes
+    /// to the vertex buffers stored in the buffer slice.
+    BindVertexArray {
+        layout: n::VertexLayoutId,
+        buffers: BufferSlice,
+    },
     //UnbindAttribute(n::AttributeDesc),
-    CopyBufferToBuffer(n::RawBuffer, n::RawBuffer, command::BufferCopy),
-    CopyBufferToTexture(n::RawBuffer, n::Texture, command::BufferImageCopy),
+    /// Copy between buffers, along with the target of the destination, to
+    /// write it from a host copy of the source if the context can't copy.
+    CopyBufferToBuffer(
+        n::RawBuffer,
+        n::RawBuffer,
+        BufferTarget,
+        command::BufferCopy,
+    ),
+    /// Write the data stored in the buffer slice to a buffer.
+    UpdateBuffer {
+        buffer: n::RawBuffer,
+        // Target the buffer was created for, to write it on contexts
+        // without pixel buffers.
+        target: BufferTarget,
+        offset: buffer::Offset,
+        data: BufferSlice,
+    },
+    /// Copy from a buffer to a texture, along with the format of the
+    /// texture, which the texels of the buffer are in.
+    CopyBufferToTexture(n::RawBuffer, n::Texture, Format, command::BufferImageCopy),
     CopyBufferToSurface(n::RawBuffer, n::Surface, command::BufferImageCopy),
-    CopyTextureToBuffer(n::Texture, n::RawBuffer, command::BufferImageCopy),
+    CopyTextureToBuffer(
+        n::Texture,
+        n::RawBuffer,
+        BufferTarget,
+        Format,
+        command::BufferImageCopy,
+    ),
     CopySurfaceToBuffer(n::Surface, n::RawBuffer, command::BufferImageCopy),
     CopyImageToTexture(n::ImageKind, n::Texture, command::ImageCopy),
     CopyImageToSurface(n::ImageKind, n::Surface, command::ImageCopy),
@@ -121,10 +163,23 @@ pub enum Command {
         gl::types::GLsizeiptr,
     ),
     BindTexture(gl::types::GLenum, n::Texture),
+    BindImageTexture {
+        unit: gl::types::GLuint,
+        texture: n::Texture,
+        level: image::Level,
+        layer: Option<image::Layer>,
+        access: gl::types::GLenum,
+        format: gl::types::GLenum,
+    },
     BindSampler(gl::types::GLuint, n::Texture),
     SetTextureSamplerSettings(gl::types::GLuint, n::Texture, image::SamplerInfo),
+    /// Make the attachments written by the previous draws visible to the
+    /// textures sampling them, for input attachments read without
+    /// framebuffer fetch.
+    TextureBarrier,
 }
 
+pub type BufferTarget = gl::types::GLenum;
 pub type FrameBufferTarget = gl::types::GLenum;
 pub type AttachmentPoint = gl::types::GLenum;
 pub type DrawBuffer = gl::types::GLint;
@@ -149,8 +204,12 @@ struct Cache {
     primitive: Option<gl::types::GLenum>,
     // Active index type, set by the current index buffer.
     index_type: Option<hal::IndexType>,
+    // Range of the values in the current index buffer, if known.
+    index_bounds: Option<Range<u32>>,
     // Stencil reference values (front, back).
     stencil_ref: Option<(pso::StencilValue, pso::StencilValue)>,
+    // Stencil test and culled faces of the active pipeline.
+    stencil_test: Option<(pso::StencilTest, pso::Face)>,
     // Blend color.
     blend_color: Option<pso::ColorValue>,
     ///
@@ -163,13 +222,24 @@ struct Cache {
     // Active program name.
     program: Option<gl::types::GLuint>,
     // Blend per attachment.
-    blend_targets: Option<Vec<Option<pso::ColorBlendDesc>>>,
+    blend_targets: Option<Vec<Option<state::BlendSlotState>>>,
     // Maps bound vertex buffer offset (index) to handle.
     vertex_buffers: Vec<gl::types::GLuint>,
-    // Active vertex buffer descriptions.
-    vertex_buffer_descs: Vec<Option<pso::VertexBufferDesc>>,
-    // Active attributes.
-    attributes: Vec<n::AttributeDesc>,
+    // Vertex layout of the active pipeline.
+    vertex_layout: Option<n::VertexLayoutId>,
+    // Vertex buffer binding of each attribute of the active vertex layout.
+    vertex_bindings: Vec<gl::types::GLuint>,
+    // Textures bound to each texture unit by descriptor sets.
+    textures: FastHashMap<gl::types::GLuint, n::Texture>,
+    // Sampler states assigned to each texture unit, used when sampler
+    // objects are not available.
+    sampler_infos: FastHashMap<gl::types::GLuint, image::SamplerInfo>,
+    // Color attachment formats and number of descriptor sets of the active
+    // graphics pipeline, only tracked for the validation.
+    color_formats: Option<Vec<Option<format::Format>>>,
+    num_sets: usize,
+    // Mask of the descriptor sets bound, only tracked for the validation.
+    bound_sets: u64,
 }
 
 impl Cache {
@@ -177,7 +247,9 @@ impl Cache {
         Cache {
             primitive: None,
             index_type: None,
+            index_bounds: None,
             stencil_ref: None,
+            stencil_test: None,
             blend_color: None,
             framebuffer: None,
             error_state: false,
@@ -185,8 +257,13 @@ impl Cache {
             program: None,
             blend_targets: None,
             vertex_buffers: Vec::new(),
-            vertex_buffer_descs: Vec::new(),
-            attributes: Vec::new(),
+            vertex_layout: None,
+            vertex_bindings: Vec::new(),
+            textures: FastHashMap::default(),
+            sampler_infos: FastHashMap::default(),
+            color_formats: None,
+            num_sets: 0,
+            bound_sets: 0,
         }
     }
 }
@@ -235,6 +312,8 @@ pub struct RawCommandBuffer {
     cur_subpass: usize,
 
     limits: Limits,
+    // Check the commands when recording them, see `DeviceOptions::validation`.
+    validation: bool,
     active_attribs: usize,
 }
 
@@ -242,6 +321,7 @@ impl RawCommandBuffer {
     pub(crate) fn new(
         fbo: Option<n::FrameBuffer>,
         limits: Limits,
+        validation: bool,
         memory: Arc<Mutex<BufferMemory>>,
     ) -> Self {
         let (id, individual_reset) = {
@@ -275,6 +355,7 @@ impl RawCommandBuffer {
             pass_cache: None,
             cur_subpass: !0,
             limits,
+            validation,
             active_attribs: 0,
         }
     }
@@ -288,6 +369,27 @@ impl RawCommandBuffer {
         self.cur_subpass = !0;
     }
 
+    /// Hint the range of the values stored in the bound index buffer, until
+    /// another index buffer is bound.
+    ///
+    /// Following indexed draws pass it to `glDrawRangeElements`, which lets
+    /// older drivers avoid scanning the indices. The values must all lie
+    /// within `bounds`, not accounting for the base vertex.
+    pub fn set_index_bounds(&mut self, bounds: Range<u32>) {
+        if bounds.start < bounds.end {
+            self.cache.index_bounds = Some(bounds);
+        }
+    }
+
+    /// Enable or disable the antialiasing of lines, until changed again.
+    ///
+    /// Smoothed lines get a coverage computed in their alpha, so blending
+    /// has to be enabled for them to look smooth. Ignored on GLES, which
+    /// doesn't support it.
+    pub fn set_line_smooth(&mut self, enable: bool) {
+        self.push_cmd(Command::SetLineSmooth(enable));
+    }
+
     fn push_cmd(&mut self, cmd: Command) {
         push_cmd_internal(&self.id, &mut self.memory, &mut self.buf, cmd);
     }
@@ -320,7 +422,7 @@ impl RawCommandBuffer {
         slice
     }
 
-    fn update_blend_targets(&mut self, blend_targets: &Vec<pso::ColorBlendDesc>) {
+    fn update_blend_targets(&mut self, blend_targets: &Vec<state::BlendSlotState>) {
         let max_blend_slots = blend_targets.len();
 
         if max_blend_slots > 0 {
@@ -364,47 +466,210 @@ impl RawCommandBuffer {
     }
 
     pub(crate) fn bind_attributes(&mut self) {
-        let Cache {
-            ref attributes,
-            ref vertex_buffers,
-            ref vertex_buffer_descs,
-            ..
-        } = self.cache;
+        let layout = match self.cache.vertex_layout {
+            Some(layout) => layout,
+            None => return,
+        };
+        let buffers = {
+            let Cache {
+                ref vertex_buffers,
+                ref vertex_bindings,
+                ..
+            } = self.cache;
+            vertex_bindings
+                .iter()
+                .map(|&binding| match vertex_buffers.get(binding as usize) {
+                    Some(&buffer) => buffer,
+                    None => {
+                        error!("No vertex buffer bound at {}", binding);
+                        0
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+        let buffers = self.add(&buffers);
+        self.push_cmd(Command::BindVertexArray { layout, buffers });
+    }
 
-        for attribute in attributes {
-            let binding = attribute.binding as usize;
+    fn bind_descriptor_sets<I>(&mut self, layout: &n::PipelineLayout, first_set: usize, sets: I)
+    where
+        I: IntoIterator,
+        I::Item: Borrow<n::DescriptorSet>,
+    {
+        let mut set = first_set as _;
+        let drd = &*layout.desc_remap_data.read().unwrap();
+        // Bindings which aren't used by any of the shaders compiled against
+        // this layout don't have a unit assigned, just skip them.
+        let units = |btype, set, binding| drd.get_binding(btype, set, binding).unwrap_or(&[]);
 
-            if vertex_buffers.len() <= binding {
-                error!("No vertex buffer bound at {}", binding);
+        for desc_set in sets {
+            let desc_set = desc_set.borrow();
+            let bindings = desc_set.bindings.lock().unwrap();
+            for &(binding, ref buffer) in &bindings.uniform_buffers {
+                for &unit in units(n::BindingTypes::UniformBuffers, set, binding) {
+                    self.push_cmd(Command::BindBufferRange(
+                        gl::UNIFORM_BUFFER,
+                        unit,
+                        buffer.buffer,
+                        buffer.offset,
+                        buffer.size,
+                    ))
+                }
             }
-
-            let handle = vertex_buffers[binding];
-
-            match vertex_buffer_descs.get(binding) {
-                Some(&Some(desc)) => {
-                    push_cmd_internal(
-                        &self.id,
-                        &mut self.memory,
-                        &mut self.buf,
-                        Command::BindAttribute(
-                            attribute.clone(),
-                            handle,
-                            desc.stride as _,
-                            desc.rate.as_uint() as u32,
-                        ),
-                    );
+            for &(binding, texture) in &bindings.textures {
+                for &unit in units(n::BindingTypes::Images, set, binding) {
+                    self.push_cmd(Command::BindTexture(unit, texture));
+                    self.cache.textures.insert(unit, texture);
+                    if let Some(sinfo) = self.cache.sampler_infos.get(&unit).cloned() {
+                        self.push_cmd(Command::SetTextureSamplerSettings(unit, texture, sinfo));
+                    }
+                }
+            }
+            for &(binding, ref image) in &bindings.storage_images {
+                for &unit in units(n::BindingTypes::StorageImages, set, binding) {
+                    self.push_cmd(Command::BindImageTexture {
+                        unit,
+                        texture: image.texture,
+                        level: image.level,
+                        layer: image.layer,
+                        // Storage images are always in the `General` layout,
+                        // shaders may both read and write them.
+                        access: gl::READ_WRITE,
+                        format: image.format,
+                    })
                 }
-                _ => error!("No vertex buffer description bound at {}", binding),
             }
+            for &(binding, sampler) in &bindings.samplers {
+                for &unit in units(n::BindingTypes::Images, set, binding) {
+                    self.push_cmd(Command::BindSampler(unit, sampler))
+                }
+            }
+            for &(binding, ref sinfo) in &bindings.sampler_infos {
+                // Without sampler objects the sampler state lives in the
+                // texture, so it can only be applied once we know which
+                // texture ends up on the unit. The image and the sampler
+                // may come from different sets, hence the tracking across
+                // calls.
+                //
+                // TODO: Check that other samplers aren't using the same
+                // texture unless they have identical properties.
+                for &unit in units(n::BindingTypes::Images, set, binding) {
+                    self.cache.sampler_infos.insert(unit, sinfo.clone());
+                    if let Some(&texture) = self.cache.textures.get(&unit) {
+                        self.push_cmd(Command::SetTextureSamplerSettings(
+                            unit,
+                            texture,
+                            sinfo.clone(),
+                        ));
+                    }
+                }
+            }
+            if self.validation && set < 64 {
+                self.cache.bound_sets |= 1 << set;
+            }
+            set += 1;
+        }
+    }
+
+    /// Check the state a draw is recorded with, if the validation is enabled.
+    ///
+    /// Invalid draws are still recorded, the errors only point to the
+    /// command at fault.
+    fn validate_draw(&mut self, command: &str) {
+        if !self.validation {
+            return;
+        }
+        let state = match self.pass_cache {
+            Some(ref state) => state,
+            None => {
+                error!("`{}` recorded outside of a render pass", command);
+                self.cache.error_state = true;
+                return;
+            }
+        };
+        let mut valid = true;
+
+        let subpass = &state.render_pass.subpasses[self.cur_subpass];
+        let pass_formats = subpass
+            .color_attachments
+            .iter()
+            .map(|&id| state.render_pass.attachments[id].format)
+            .collect::<Vec<_>>();
+        if let Some(ref formats) = self.cache.color_formats {
+            if *formats != pass_formats {
+                error!(
+                    "`{}` with a pipeline created for color attachments {:?}, \
+                     incompatible with the ones of subpass {}: {:?}",
+                    command, formats, self.cur_subpass, pass_formats
+                );
+                valid = false;
+            }
+        }
+
+        let missing_sets = (0..self.cache.num_sets.min(64))
+            .filter(|&set| self.cache.bound_sets & (1 << set) == 0)
+            .collect::<Vec<_>>();
+        if !missing_sets.is_empty() {
+            error!(
+                "`{}` without binding the descriptor sets {:?} of the pipeline layout",
+                command, missing_sets
+            );
+            valid = false;
+        }
+
+        if !valid {
+            self.cache.error_state = true;
+        }
+    }
+
+    fn end_subpass(&mut self) {
+        let (framebuffer, resolves, invalidated) = {
+            let state = self.pass_cache.as_ref().unwrap();
+            let subpasses = &state.render_pass.subpasses;
+            let resolves = subpasses[self.cur_subpass].resolve_attachments.clone();
+
+            // Multisampled attachments which aren't stored can be discarded
+            // once resolved, unless later subpasses use them.
+            let invalidated = resolves
+                .iter()
+                .map(|&(src, _)| src)
+                .filter(|&src| {
+                    let ops = state.render_pass.attachments[src].ops;
+                    ops.store == pass::AttachmentStoreOp::DontCare
+                        && !subpasses[self.cur_subpass + 1..].iter().any(|subpass| {
+                            subpass.is_using(src)
+                                || subpass.resolve_attachments.iter().any(|&(id, _)| id == src)
+                        })
+                })
+                .map(|src| gl::COLOR_ATTACHMENT0 + src as gl::types::GLenum)
+                .collect::<Vec<_>>();
+
+            (state.framebuffer, resolves, invalidated)
+        };
+
+        for (src, dst) in resolves {
+            self.push_cmd(Command::ResolveAttachment(framebuffer, src, dst));
+        }
+        if !invalidated.is_empty() {
+            let attachments = self.add(&invalidated);
+            self.push_cmd(Command::InvalidateAttachments(attachments));
         }
     }
 
     fn begin_subpass(&mut self) {
         // Split processing and command recording due to borrowchk.
-        let (draw_buffers, clear_cmds) = {
+        let (srgb, draw_buffers, clear_cmds) = {
             let state = self.pass_cache.as_ref().unwrap();
             let subpass = &state.render_pass.subpasses[self.cur_subpass];
 
+            // Only sRGB attachments are encoded, but the state also applies
+            // to the default framebuffer whatever the format of the
+            // swapchain is, so enable it only if needed.
+            let srgb = subpass.draw_attachments().any(|&id| {
+                let format = state.render_pass.attachments[id].format;
+                format.map(|format| format.base_format().1) == Some(ChannelType::Srgb)
+            });
+
             // See `begin_renderpass_cache` for clearing strategy
 
             // Bind draw buffers for mapping color output locations with
@@ -417,8 +682,7 @@ impl RawCommandBuffer {
                 vec![gl::BACK_LEFT]
             } else {
                 subpass
-                    .color_attachments
-                    .iter()
+                    .draw_attachments()
                     .map(|id| gl::COLOR_ATTACHMENT0 + *id as gl::types::GLenum)
                     .collect::<Vec<_>>()
             };
@@ -487,13 +751,19 @@ impl RawCommandBuffer {
                 })
                 .collect::<Vec<_>>();
 
-            (draw_buffers, clear_cmds)
+            (srgb, draw_buffers, clear_cmds)
         };
 
         // Record commands
+        self.push_cmd(Command::SetFramebufferSrgb(srgb));
         let draw_buffers = self.add(&draw_buffers);
         self.push_cmd(Command::DrawBuffers(draw_buffers));
 
+        if !clear_cmds.is_empty() {
+            // Clears unlock the write masks, the blend slots of the next
+            // pipeline have to be bound again.
+            self.cache.blend_targets = None;
+        }
         for cmd in clear_cmds {
             self.push_cmd(cmd);
         }
@@ -565,8 +835,14 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         unimplemented!()
     }
 
-    unsafe fn update_buffer(&mut self, _buffer: &n::Buffer, _offset: buffer::Offset, _data: &[u8]) {
-        unimplemented!()
+    unsafe fn update_buffer(&mut self, buffer: &n::Buffer, offset: buffer::Offset, data: &[u8]) {
+        let data = self.add_raw(data);
+        self.push_cmd(Command::UpdateBuffer {
+            buffer: buffer.raw,
+            target: buffer.target,
+            offset,
+            data,
+        });
     }
 
     unsafe fn begin_render_pass<T>(
@@ -636,11 +912,18 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
     }
 
     unsafe fn next_subpass(&mut self, _contents: command::SubpassContents) {
-        unimplemented!()
+        self.end_subpass();
+        // Attachments written by the previous subpass may be read as input
+        // attachments in the next one.
+        self.push_cmd(Command::TextureBarrier);
+        self.cur_subpass += 1;
+        self.begin_subpass();
     }
 
     unsafe fn end_render_pass(&mut self) {
-        // TODO
+        self.end_subpass();
+        self.pass_cache = None;
+        self.cur_subpass = !0;
     }
 
     unsafe fn clear_image<T>(
@@ -661,11 +944,11 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
 
         match self.fbo {
             Some(fbo) => {
-                // TODO: reset color mask
                 // 2. ClearBuffer
+                self.cache.blend_targets = None;
                 let view = match image.kind {
                     n::ImageKind::Surface(id) => n::ImageView::Surface(id),
-                    n::ImageKind::Texture(id) => n::ImageView::Texture(id, 0), //TODO
+                    n::ImageKind::Texture(id) => n::ImageView::Texture(id, 0, image.format), //TODO
                 };
                 self.push_cmd(Command::BindFrameBuffer(gl::DRAW_FRAMEBUFFER, fbo));
                 self.push_cmd(Command::BindTargetView(
@@ -673,7 +956,8 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
                     gl::COLOR_ATTACHMENT0,
                     view,
                 ));
-                self.push_cmd(Command::SetDrawColorBuffers(1));
+                let draw_buffers = self.add(&[gl::COLOR_ATTACHMENT0]);
+                self.push_cmd(Command::DrawBuffers(draw_buffers));
 
                 match image.channel {
                     ChannelType::Unorm
@@ -748,6 +1032,7 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         }
 
         self.cache.index_type = Some(ibv.index_type);
+        self.cache.index_bounds = None;
         self.push_cmd(Command::BindIndexBuffer(ibv.buffer.raw));
     }
 
@@ -872,6 +1157,10 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         // we assembled all the pieces to set the stencil state
         // from the pipeline.
         self.cache.stencil_ref = Some((front, back));
+        if let Some((stencil, cull)) = self.cache.stencil_test {
+            let state = state::StencilState::new(&stencil, (front, back), cull);
+            self.push_cmd(Command::SetStencilState(state));
+        }
     }
 
     unsafe fn set_stencil_read_mask(&mut self, _faces: pso::Face, _value: pso::StencilValue) {
@@ -889,12 +1178,12 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         }
     }
 
-    unsafe fn set_depth_bounds(&mut self, _: Range<f32>) {
-        warn!("Depth bounds test is not supported");
+    unsafe fn set_depth_bounds(&mut self, bounds: Range<f32>) {
+        self.push_cmd(Command::SetDepthBounds(bounds));
     }
 
-    unsafe fn set_line_width(&mut self, _width: f32) {
-        unimplemented!()
+    unsafe fn set_line_width(&mut self, width: f32) {
+        self.push_cmd(Command::SetLineWidth(width));
     }
 
     unsafe fn set_depth_bias(&mut self, _depth_bias: pso::DepthBias) {
@@ -906,11 +1195,25 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
             primitive,
             patch_size,
             program,
+            rasterizer,
+            depth,
+            stencil,
+            cull_face,
             ref blend_targets,
-            ref attributes,
-            ref vertex_buffers,
+            blend_color,
+            ref depth_bounds,
+            vertex_layout,
+            ref vertex_bindings,
+            ref color_formats,
+            num_sets,
+            ..
         } = *pipeline;
 
+        if self.validation {
+            self.cache.color_formats = Some(color_formats.clone());
+            self.cache.num_sets = num_sets;
+        }
+
         if self.cache.primitive != Some(primitive) {
             self.cache.primitive = Some(primitive);
         }
@@ -927,11 +1230,28 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
             self.push_cmd(Command::BindProgram(program));
         }
 
-        self.cache.attributes = attributes.clone();
+        self.cache.vertex_layout = Some(vertex_layout);
+        self.cache.vertex_bindings = vertex_bindings.clone();
 
-        self.cache.vertex_buffer_descs = vertex_buffers.clone();
+        // Redundant state changes are filtered out by the queue, which
+        // tracks the state of the context across command buffers.
+        self.push_cmd(Command::SetRasterizer(rasterizer));
+        self.push_cmd(Command::SetDepthState(depth));
+        self.cache.stencil_test = Some((stencil, cull_face));
+        let refs = self.cache.stencil_ref.unwrap_or((0, 0));
+        self.push_cmd(Command::SetStencilState(state::StencilState::new(
+            &stencil, refs, cull_face,
+        )));
 
         self.update_blend_targets(blend_targets);
+        // Pipelines without static blend constants or depth bounds keep the
+        // dynamic ones.
+        if let Some(cv) = blend_color {
+            self.set_blend_constants(cv);
+        }
+        if let Some(ref bounds) = *depth_bounds {
+            self.set_depth_bounds(bounds.clone());
+        }
     }
 
     unsafe fn bind_graphics_descriptor_sets<I, J>(
@@ -947,97 +1267,11 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         J::Item: Borrow<command::DescriptorSetOffset>,
     {
         assert!(offsets.into_iter().next().is_none()); // TODO: offsets unsupported
-
-        let mut set = first_set as _;
-        let drd = &*layout.desc_remap_data.read().unwrap();
-
-        for desc_set in sets {
-            let desc_set = desc_set.borrow();
-            let bindings = desc_set.bindings.lock().unwrap();
-            for new_binding in &*bindings {
-                match new_binding {
-                    n::DescSetBindings::Buffer {
-                        ty: btype,
-                        binding,
-                        buffer,
-                        offset,
-                        size,
-                    } => {
-                        let btype = match btype {
-                            n::BindingTypes::UniformBuffers => gl::UNIFORM_BUFFER,
-                            n::BindingTypes::Images => panic!("Wrong desc set binding"),
-                        };
-                        for binding in drd
-                            .get_binding(n::BindingTypes::UniformBuffers, set, *binding)
-                            .unwrap()
-                        {
-                            self.push_cmd(Command::BindBufferRange(
-                                btype, *binding, *buffer, *offset, *size,
-                            ))
-                        }
-                    }
-                    n::DescSetBindings::Texture(binding, texture) => {
-                        for binding in drd
-                            .get_binding(n::BindingTypes::Images, set, *binding)
-                            .unwrap()
-                        {
-                            self.push_cmd(Command::BindTexture(*binding, *texture))
-                        }
-                    }
-                    n::DescSetBindings::Sampler(binding, sampler) => {
-                        for binding in drd
-                            .get_binding(n::BindingTypes::Images, set, *binding)
-                            .unwrap()
-                        {
-                            self.push_cmd(Command::BindSampler(*binding, *sampler))
-                        }
-                    }
-                    n::DescSetBindings::SamplerInfo(binding, sinfo) => {
-                        let mut all_txts = drd
-                            .get_binding(n::BindingTypes::Images, set, *binding)
-                            .unwrap()
-                            .into_iter()
-                            .flat_map(|binding| {
-                                bindings.iter().filter_map(move |b| {
-                                    if let n::DescSetBindings::Texture(b, t) = b {
-                                        let nbs =
-                                            drd.get_binding(n::BindingTypes::Images, set, *b)?;
-                                        if nbs.contains(binding) {
-                                            Some((*binding, *t))
-                                        } else {
-                                            None
-                                        }
-                                    } else {
-                                        None
-                                    }
-                                })
-                            })
-                            .collect::<Vec<_>>();
-
-                        // TODO: Check that other samplers aren't using the same
-                        // textures as in `all_txts` unless all the bindings of that
-                        // texture are gonna be unbound or the two samplers have
-                        // identical properties.
-
-                        all_txts.sort_unstable_by(|a, b| a.1.cmp(&b.1));
-                        all_txts.dedup_by(|a, b| a.1 == b.1);
-
-                        for (binding, txt) in all_txts {
-                            self.push_cmd(Command::SetTextureSamplerSettings(
-                                binding,
-                                txt,
-                                sinfo.clone(),
-                            ))
-                        }
-                    }
-                }
-            }
-            set += 1;
-        }
+        self.bind_descriptor_sets(layout, first_set, sets);
     }
 
     unsafe fn bind_compute_pipeline(&mut self, pipeline: &n::ComputePipeline) {
-        let n::ComputePipeline { program } = *pipeline;
+        let n::ComputePipeline { program, .. } = *pipeline;
 
         if self.cache.program != Some(program) {
             self.cache.program = Some(program);
@@ -1047,17 +1281,18 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
 
     unsafe fn bind_compute_descriptor_sets<I, J>(
         &mut self,
-        _layout: &n::PipelineLayout,
-        _first_set: usize,
-        _sets: I,
-        _offsets: J,
+        layout: &n::PipelineLayout,
+        first_set: usize,
+        sets: I,
+        offsets: J,
     ) where
         I: IntoIterator,
         I::Item: Borrow<n::DescriptorSet>,
         J: IntoIterator,
         J::Item: Borrow<command::DescriptorSetOffset>,
     {
-        // TODO
+        assert!(offsets.into_iter().next().is_none()); // TODO: offsets unsupported
+        self.bind_descriptor_sets(layout, first_set, sets);
     }
 
     unsafe fn dispatch(&mut self, count: hal::WorkGroupCount) {
@@ -1077,7 +1312,7 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
 
         for region in regions {
             let r = region.borrow().clone();
-            let cmd = Command::CopyBufferToBuffer(src.raw, dst.raw, r);
+            let cmd = Command::CopyBufferToBuffer(src.raw, dst.raw, dst.target, r);
             self.push_cmd(cmd);
         }
 
@@ -1129,7 +1364,7 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
             let r = region.borrow().clone();
             let cmd = match dst.kind {
                 n::ImageKind::Surface(s) => Command::CopyBufferToSurface(src.raw, s, r),
-                n::ImageKind::Texture(t) => Command::CopyBufferToTexture(src.raw, t, r),
+                n::ImageKind::Texture(t) => Command::CopyBufferToTexture(src.raw, t, dst.format, r),
             };
             self.push_cmd(cmd);
         }
@@ -1155,7 +1390,9 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
             let r = region.borrow().clone();
             let cmd = match src.kind {
                 n::ImageKind::Surface(s) => Command::CopySurfaceToBuffer(s, dst.raw, r),
-                n::ImageKind::Texture(t) => Command::CopyTextureToBuffer(t, dst.raw, r),
+                n::ImageKind::Texture(t) => {
+                    Command::CopyTextureToBuffer(t, dst.raw, dst.target, src.format, r)
+                }
             };
             self.push_cmd(cmd);
         }
@@ -1170,6 +1407,7 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         vertices: Range<hal::VertexCount>,
         instances: Range<hal::InstanceCount>,
     ) {
+        self.validate_draw("draw");
         self.bind_attributes();
 
         match self.cache.primitive {
@@ -1193,6 +1431,7 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         base_vertex: hal::VertexOffset,
         instances: Range<hal::InstanceCount>,
     ) {
+        self.validate_draw("draw_indexed");
         self.bind_attributes();
 
         let (start, index_type) = match self.cache.index_type {
@@ -1213,6 +1452,11 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
                     index_buffer_offset: start as _,
                     base_vertex,
                     instances,
+                    index_bounds: self
+                        .cache
+                        .index_bounds
+                        .as_ref()
+                        .map(|bounds| (bounds.start, bounds.end - 1)),
                 });
             }
             None => {
@@ -1277,7 +1521,7 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         _offset: u32,
         _constants: &[u32],
     ) {
-        unimplemented!()
+        unimplemented!("Push constants aren't emulated")
     }
 
     unsafe fn push_compute_constants(
@@ -1286,7 +1530,7 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         _offset: u32,
         _constants: &[u32],
     ) {
-        unimplemented!()
+        unimplemented!("Push constants aren't emulated")
     }
 
     unsafe fn execute_commands<'a, T, I>(&mut self, _buffers: I)
@@ -56,19 +56,96 @@ pub fn buffer_usage_to_gl_target(usage: buffer::Usage) -> Option<t::GLenum> {
     }
 }
 
-pub fn primitive_to_gl_primitive(primitive: Primitive) -> t::GLenum {
-    match primitive {
+/// Primitive topology of the draws.
+///
+/// Adjacency topologies come along geometry shaders, `None` is returned for
+/// them if `adjacency` isn't supported.
+pub fn primitive_to_gl_primitive(primitive: Primitive, adjacency: bool) -> Option<t::GLenum> {
+    Some(match primitive {
         Primitive::PointList => gl::POINTS,
         Primitive::LineList => gl::LINES,
         Primitive::LineStrip => gl::LINE_STRIP,
         Primitive::TriangleList => gl::TRIANGLES,
         Primitive::TriangleStrip => gl::TRIANGLE_STRIP,
-        Primitive::LineListAdjacency => gl::LINES_ADJACENCY,
-        Primitive::LineStripAdjacency => gl::LINE_STRIP_ADJACENCY,
-        Primitive::TriangleListAdjacency => gl::TRIANGLES_ADJACENCY,
-        Primitive::TriangleStripAdjacency => gl::TRIANGLE_STRIP_ADJACENCY,
+        Primitive::LineListAdjacency if adjacency => gl::LINES_ADJACENCY,
+        Primitive::LineStripAdjacency if adjacency => gl::LINE_STRIP_ADJACENCY,
+        Primitive::TriangleListAdjacency if adjacency => gl::TRIANGLES_ADJACENCY,
+        Primitive::TriangleStripAdjacency if adjacency => gl::TRIANGLE_STRIP_ADJACENCY,
         Primitive::PatchList(_) => gl::PATCHES,
-    }
+        Primitive::LineListAdjacency
+        | Primitive::LineStripAdjacency
+        | Primitive::TriangleListAdjacency
+        | Primitive::TriangleStripAdjacency => return None,
+    })
+}
+
+/// Format of a texture level bound to an image unit for load/store access.
+pub fn format_to_gl_image_format(format: Format) -> Option<gl::types::GLenum> {
+    use crate::hal::format::Format::*;
+    let format = match format {
+        R8Unorm => gl::R8,
+        R8Snorm => gl::R8_SNORM,
+        R8Uint => gl::R8UI,
+        R8Sint => gl::R8I,
+        Rg8Unorm => gl::RG8,
+        Rg8Snorm => gl::RG8_SNORM,
+        Rg8Uint => gl::RG8UI,
+        Rg8Sint => gl::RG8I,
+        // sRGB formats can't be used for load/store, so we access the
+        // data through the compatible linear format instead.
+        Rgba8Unorm | Rgba8Srgb => gl::RGBA8,
+        Rgba8Snorm => gl::RGBA8_SNORM,
+        Rgba8Uint => gl::RGBA8UI,
+        Rgba8Sint => gl::RGBA8I,
+        R16Unorm => gl::R16,
+        R16Uint => gl::R16UI,
+        R16Sint => gl::R16I,
+        R16Sfloat => gl::R16F,
+        Rg16Unorm => gl::RG16,
+        Rg16Uint => gl::RG16UI,
+        Rg16Sint => gl::RG16I,
+        Rg16Sfloat => gl::RG16F,
+        Rgba16Unorm => gl::RGBA16,
+        Rgba16Uint => gl::RGBA16UI,
+        Rgba16Sint => gl::RGBA16I,
+        Rgba16Sfloat => gl::RGBA16F,
+        R32Uint => gl::R32UI,
+        R32Sint => gl::R32I,
+        R32Sfloat => gl::R32F,
+        Rg32Uint => gl::RG32UI,
+        Rg32Sint => gl::RG32I,
+        Rg32Sfloat => gl::RG32F,
+        Rgba32Uint => gl::RGBA32UI,
+        Rgba32Sint => gl::RGBA32I,
+        Rgba32Sfloat => gl::RGBA32F,
+        A2b10g10r10Unorm => gl::RGB10_A2,
+        A2b10g10r10Uint => gl::RGB10_A2UI,
+        B10g11r11Ufloat => gl::R11F_G11F_B10F,
+        _ => return None,
+    };
+
+    Some(format)
+}
+
+/// Sized internal format of the textures and renderbuffers of an image
+/// format, with the format and type of its texels in client memory.
+pub fn format_to_gl_texture_format(
+    format: Format,
+) -> Option<(gl::types::GLenum, gl::types::GLenum, gl::types::GLenum)> {
+    use crate::hal::format::Format::*;
+    let format = match format {
+        R8Unorm => (gl::R8, gl::RED, gl::UNSIGNED_BYTE),
+        Rg8Unorm => (gl::RG8, gl::RG, gl::UNSIGNED_BYTE),
+        Rgba8Unorm => (gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE),
+        Rgba8Srgb => (gl::SRGB8_ALPHA8, gl::RGBA, gl::UNSIGNED_BYTE),
+        R16Sfloat => (gl::R16F, gl::RED, gl::HALF_FLOAT),
+        Rgba16Sfloat => (gl::RGBA16F, gl::RGBA, gl::HALF_FLOAT),
+        R32Sfloat => (gl::R32F, gl::RED, gl::FLOAT),
+        Rgba32Sfloat => (gl::RGBA32F, gl::RGBA, gl::FLOAT),
+        R32Uint => (gl::R32UI, gl::RED_INTEGER, gl::UNSIGNED_INT),
+        _ => return None,
+    };
+    Some(format)
 }
 
 pub fn format_to_gl_format(
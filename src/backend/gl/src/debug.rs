@@ -0,0 +1,124 @@
+//! Driver debug output, routed to the `log` crate.
+//!
+//! Debug contexts supporting `KHR_debug` report errors, undefined behavior
+//! and performance warnings through a message callback. Messages are logged
+//! under the `gfx_backend_gl::debug` target, with the level derived from the
+//! message severity and type.
+
+use std::ffi::CStr;
+use std::os::raw::c_void;
+use std::ptr;
+
+use log::Level;
+
+use crate::gl::{self, types::*};
+use crate::info::{self, Info, Requirement};
+use crate::GlContainer;
+
+const TARGET: &str = "gfx_backend_gl::debug";
+
+fn source_name(source: GLenum) -> &'static str {
+    match source {
+        gl::DEBUG_SOURCE_API => "API",
+        gl::DEBUG_SOURCE_WINDOW_SYSTEM => "Window System",
+        gl::DEBUG_SOURCE_SHADER_COMPILER => "Shader Compiler",
+        gl::DEBUG_SOURCE_THIRD_PARTY => "Third Party",
+        gl::DEBUG_SOURCE_APPLICATION => "Application",
+        _ => "Other",
+    }
+}
+
+fn type_name(ty: GLenum) -> &'static str {
+    match ty {
+        gl::DEBUG_TYPE_ERROR => "Error",
+        gl::DEBUG_TYPE_DEPRECATED_BEHAVIOR => "Deprecated Behavior",
+        gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR => "Undefined Behavior",
+        gl::DEBUG_TYPE_PORTABILITY => "Portability",
+        gl::DEBUG_TYPE_PERFORMANCE => "Performance",
+        gl::DEBUG_TYPE_MARKER => "Marker",
+        gl::DEBUG_TYPE_PUSH_GROUP => "Push Group",
+        gl::DEBUG_TYPE_POP_GROUP => "Pop Group",
+        _ => "Other",
+    }
+}
+
+/// Log level of a message, errors are always reported as such regardless
+/// of the severity assigned by the driver.
+fn level(ty: GLenum, severity: GLenum) -> Level {
+    match (ty, severity) {
+        (gl::DEBUG_TYPE_ERROR, _) | (_, gl::DEBUG_SEVERITY_HIGH) => Level::Error,
+        (gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR, _) | (_, gl::DEBUG_SEVERITY_MEDIUM) => Level::Warn,
+        (_, gl::DEBUG_SEVERITY_LOW) => Level::Info,
+        _ => Level::Debug,
+    }
+}
+
+extern "system" fn callback(
+    source: GLenum,
+    ty: GLenum,
+    id: GLuint,
+    severity: GLenum,
+    length: GLsizei,
+    message: *const GLchar,
+    _user_param: *mut c_void,
+) {
+    let level = level(ty, severity);
+    if !log_enabled!(target: TARGET, level) {
+        return;
+    }
+    // The message is null terminated, but the length is cheaper to trust.
+    let message = if length >= 0 {
+        let bytes = unsafe { std::slice::from_raw_parts(message as *const u8, length as usize) };
+        String::from_utf8_lossy(bytes)
+    } else {
+        unsafe { CStr::from_ptr(message) }.to_string_lossy()
+    };
+    log!(
+        target: TARGET,
+        level,
+        "[{}] [{}] ({:#x}) {}",
+        source_name(source),
+        type_name(ty),
+        id,
+        message.trim_end_matches('\0')
+    );
+}
+
+/// Register the debug message callback, if the context was created with the
+/// debug flag and supports `KHR_debug`.
+///
+/// Notifications are disabled in the driver if the debug log level is not
+/// enabled at registration time, as they are usually very frequent.
+pub(crate) fn register(gl: &GlContainer, info: &Info) {
+    use self::Requirement::*;
+    if !info.is_supported(&[Core(4, 3), Es(3, 2), Ext("GL_KHR_debug")])
+        || !gl.DebugMessageCallback.is_loaded()
+    {
+        return;
+    }
+    let flags = info::get_usize(gl, gl::CONTEXT_FLAGS).unwrap_or(0) as GLenum;
+    if flags & gl::CONTEXT_FLAG_DEBUG_BIT == 0 {
+        return;
+    }
+
+    unsafe {
+        gl.Enable(gl::DEBUG_OUTPUT);
+        if cfg!(debug_assertions) {
+            // Report messages on the thread and within the call raising them.
+            gl.Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+        }
+        gl.DebugMessageCallback(callback, ptr::null());
+
+        if !log_enabled!(target: TARGET, Level::Debug) {
+            gl.DebugMessageControl(
+                gl::DONT_CARE,
+                gl::DONT_CARE,
+                gl::DEBUG_SEVERITY_NOTIFICATION,
+                0,
+                ptr::null(),
+                gl::FALSE,
+            );
+        }
+    }
+    info!("Registered the debug message callback");
+}
@@ -1,15 +1,19 @@
 use std::borrow::Borrow;
 use std::cell::Cell;
-use std::iter::repeat;
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::iter::{self, repeat};
 use std::ops::Range;
-use std::sync::{Arc, Mutex, RwLock};
+use std::os::raw::{c_int, c_void};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::time::Instant;
 use std::{mem, ptr, slice};
 
 use crate::gl::types::{GLenum, GLfloat, GLint};
 use crate::{gl, GlContainer};
 
 use crate::hal::backend::FastHashMap;
-use crate::hal::format::{Format, Swizzle};
+use crate::hal::format::{ChannelType, Format, Swizzle};
 use crate::hal::pool::CommandPoolCreateFlags;
 use crate::hal::queue::QueueFamilyId;
 use crate::hal::range::RangeArg;
@@ -19,8 +23,221 @@ use spirv_cross::{glsl, spirv, ErrorCode as SpirvErrorCode};
 
 use crate::info::LegacyFeatures;
 use crate::pool::{BufferMemory, OwnedBuffer, RawCommandPool};
-use crate::{conv, native as n, state};
-use crate::{Backend as B, Share, Starc, Surface, Swapchain};
+#[cfg(feature = "renderdoc")]
+use crate::renderdoc::RenderDoc;
+use crate::{conv, ext, native as n, state, worker};
+use crate::{Backend as B, FrameStatistics, Share, Starc, Surface, Swapchain};
+
+/// Longest time spent blocking on a single fence when waiting for any of several fences.
+const ANY_FENCE_SLICE_NS: u64 = 1_000_000;
+
+/// Sampler objects shared by all the samplers created with the same info.
+#[derive(Debug, Default)]
+pub(crate) struct SamplerCache {
+    names: FastHashMap<i::SamplerInfo, n::Sampler>,
+    // Info and number of references of each sampler object.
+    refs: FastHashMap<n::Sampler, (i::SamplerInfo, usize)>,
+}
+
+impl SamplerCache {
+    /// Add a reference to the sampler object matching the info, if any.
+    fn acquire(&mut self, info: &i::SamplerInfo) -> Option<n::Sampler> {
+        let name = *self.names.get(info)?;
+        self.refs.get_mut(&name).unwrap().1 += 1;
+        Some(name)
+    }
+
+    fn insert(&mut self, info: i::SamplerInfo, name: n::Sampler) {
+        self.names.insert(info.clone(), name);
+        self.refs.insert(name, (info, 1));
+    }
+
+    /// Remove a reference to the sampler object, returns `true` if it was
+    /// the last one and the object needs to be deleted.
+    fn release(&mut self, name: n::Sampler) -> bool {
+        let last = match self.refs.get_mut(&name) {
+            Some(&mut (_, ref mut count)) => {
+                *count -= 1;
+                *count == 0
+            }
+            // Not created through the cache.
+            None => return true,
+        };
+        if last {
+            let (info, _) = self.refs.remove(&name).unwrap();
+            self.names.remove(&info);
+        }
+        last
+    }
+}
+
+/// Descriptor bindings of the uniforms and uniform blocks of a program, by
+/// name, when explicit layouts are not supported in the shaders.
+///
+/// Members of emulated uniform blocks are listed instead of the blocks,
+/// along with their offset in the uniform buffer.
+type NameBindingMap = FastHashMap<String, (n::BindingTypes, pso::DescriptorBinding, u32)>;
+
+/// Prefix of the names given to the uniforms emulating uniform blocks.
+const UNIFORM_BLOCK_PREFIX: &str = "GFX_HAL_UNIFORM_BLOCK_";
+
+/// Prefix of the names given to the subpass inputs read with framebuffer
+/// fetch, and to the fragment outputs declared to fetch from.
+const INPUT_ATTACHMENT_PREFIX: &str = "GFX_HAL_INPUT_ATTACHMENT_";
+const FRAMEBUFFER_OUTPUT_PREFIX: &str = "GFX_HAL_FRAMEBUFFER_";
+
+/// Built-ins the color attachments are fetched through.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FramebufferFetchKind {
+    /// `inout` fragment outputs, or `gl_LastFragData` in shading languages
+    /// without user-defined outputs.
+    Ext { legacy: bool },
+    /// `gl_LastFragColorARM`, holding the first color attachment.
+    Arm,
+}
+
+/// Subpass input of a fragment shader read with framebuffer fetch.
+struct FramebufferFetch {
+    // Id of the variable in the SPIR-V module.
+    id: u32,
+    name: String,
+    // Fragment output location of the attachment.
+    location: usize,
+}
+
+/// Type of the fragment outputs fetching from an attachment.
+fn fetch_type(format: Option<Format>) -> &'static str {
+    match format.map(|format| format.base_format().1) {
+        Some(ChannelType::Uint) => "uvec4",
+        Some(ChannelType::Sint) => "ivec4",
+        _ => "vec4",
+    }
+}
+
+/// Make the fragment output at `location` an `inout` one, declaring it if
+/// the shader doesn't write it, and return its name.
+fn inout_output(
+    lines: &mut [String],
+    declarations: &mut Vec<String>,
+    location: usize,
+    ty: &str,
+) -> String {
+    let layout = format!("layout(location = {}) ", location);
+    let output = lines.iter_mut().find(|line| {
+        line.starts_with(&layout) && (line.contains(" out ") || line.contains(" inout "))
+    });
+    if let Some(line) = output {
+        if !line.contains(" inout ") {
+            *line = line.replacen(" out ", " inout ", 1);
+        }
+        return line
+            .trim_end_matches(';')
+            .rsplit(' ')
+            .next()
+            .unwrap()
+            .to_string();
+    }
+    let name = format!("{}{}", FRAMEBUFFER_OUTPUT_PREFIX, location);
+    let declaration = format!("{}inout highp {} {};", layout, ty, name);
+    if !declarations.contains(&declaration) {
+        declarations.push(declaration);
+    }
+    name
+}
+
+/// Rewrite the GLSL translated from a fragment shader to read subpass
+/// inputs with framebuffer fetch.
+///
+/// SPIRV-Cross declares the inputs as textures read at the fragment
+/// coordinates. They become globals, loaded from the fetched colors at the
+/// start of `main`. `fetched_outputs` lists the locations and types of the
+/// outputs bound to input attachments only, which are declared `inout` and
+/// never written, preserving the attachments.
+fn apply_framebuffer_fetch(
+    glsl: &str,
+    kind: FramebufferFetchKind,
+    fetches: &[FramebufferFetch],
+    fetched_outputs: &[(usize, &str)],
+) -> String {
+    let mut lines = glsl.lines().map(String::from).collect::<Vec<_>>();
+    let mut declarations = Vec::new();
+    let mut loads = Vec::new();
+
+    for fetch in fetches {
+        let suffix = format!(" {};", fetch.name);
+        let ty = match lines
+            .iter_mut()
+            .find(|line| line.contains("sampler2D") && line.ends_with(&suffix))
+        {
+            Some(line) => {
+                let ty = if line.contains("usampler2D") {
+                    "uvec4"
+                } else if line.contains("isampler2D") {
+                    "ivec4"
+                } else {
+                    "vec4"
+                };
+                *line = format!("highp {} {};", ty, fetch.name);
+                ty
+            }
+            None => continue,
+        };
+        let texel_fetch = format!("texelFetch({}, ivec2(gl_FragCoord.xy), 0)", fetch.name);
+        for line in &mut lines {
+            if line.contains(&texel_fetch) {
+                *line = line.replace(&texel_fetch, &fetch.name);
+            }
+        }
+        let source = match kind {
+            FramebufferFetchKind::Ext { legacy: false } => {
+                inout_output(&mut lines, &mut declarations, fetch.location, ty)
+            }
+            FramebufferFetchKind::Ext { legacy: true } => {
+                format!("gl_LastFragData[{}]", fetch.location)
+            }
+            FramebufferFetchKind::Arm => "gl_LastFragColorARM".to_string(),
+        };
+        loads.push(format!("    {} = {};", fetch.name, source));
+    }
+    if kind == (FramebufferFetchKind::Ext { legacy: false }) {
+        for &(location, ty) in fetched_outputs {
+            inout_output(&mut lines, &mut declarations, location, ty);
+        }
+    }
+
+    if let Some(main) = lines.iter().position(|line| line == "void main()") {
+        // Skip the opening brace.
+        lines.splice(main + 2..main + 2, loads);
+        lines.splice(main..main, declarations);
+    }
+    let extension = match kind {
+        FramebufferFetchKind::Ext { .. } => "GL_EXT_shader_framebuffer_fetch",
+        FramebufferFetchKind::Arm => "GL_ARM_shader_framebuffer_fetch",
+    };
+    lines.insert(1, format!("#extension {} : require", extension));
+
+    let mut glsl = lines.join("\n");
+    glsl.push('\n');
+    glsl
+}
+
+/// Shader of a program, before compilation.
+enum ShaderSource {
+    Raw(n::Shader),
+    Glsl(String),
+}
+
+/// Program whose linking was started by `link_program`.
+struct PendingProgram {
+    name: n::Program,
+    // Compiled shaders, checked for errors and deleted once linked.
+    shaders: Vec<n::Shader>,
+    // Set if the program was loaded from the program cache.
+    from_cache: bool,
+    // Key to store the program binary under, once linked.
+    cache_key: Option<u64>,
+    name_binding_map: NameBindingMap,
+}
 
 /// Emit error during shader module creation. Used if we don't expect an error
 /// but might panic due to an exception in SPIRV-Cross.
@@ -32,19 +249,23 @@ fn gen_unexpected_error(err: SpirvErrorCode) -> d::ShaderError {
     d::ShaderError::CompilationFailed(msg)
 }
 
-fn get_shader_iv(gl: &GlContainer, name: n::Shader, query: GLenum) -> gl::types::GLint {
+pub(crate) fn get_shader_iv(gl: &GlContainer, name: n::Shader, query: GLenum) -> gl::types::GLint {
     let mut iv = 0;
     unsafe { gl.GetShaderiv(name, query, &mut iv) };
     iv
 }
 
-fn get_program_iv(gl: &GlContainer, name: n::Program, query: GLenum) -> gl::types::GLint {
+pub(crate) fn get_program_iv(
+    gl: &GlContainer,
+    name: n::Program,
+    query: GLenum,
+) -> gl::types::GLint {
     let mut iv = 0;
     unsafe { gl.GetProgramiv(name, query, &mut iv) };
     iv
 }
 
-fn get_shader_log(gl: &GlContainer, name: n::Shader) -> String {
+pub(crate) fn get_shader_log(gl: &GlContainer, name: n::Shader) -> String {
     let mut length = get_shader_iv(gl, name, gl::INFO_LOG_LENGTH);
     if length > 0 {
         let mut log = String::with_capacity(length as usize);
@@ -89,7 +310,11 @@ fn create_fbo_internal(share: &Starc<Share>) -> Option<gl::types::GLuint> {
         let gl = &share.context;
         let mut name = 0 as n::FrameBuffer;
         unsafe {
-            gl.GenFramebuffers(1, &mut name);
+            if share.private_caps.direct_state_access {
+                gl.CreateFramebuffers(1, &mut name);
+            } else {
+                gl.GenFramebuffers(1, &mut name);
+            }
         }
         info!("\tCreated frame buffer {}", name);
         Some(name)
@@ -102,34 +327,765 @@ fn create_fbo_internal(share: &Starc<Share>) -> Option<gl::types::GLuint> {
 #[derive(Debug)]
 pub struct Device {
     share: Starc<Share>,
+    // Worker owning the context, if the device was opened on one.
+    worker: Option<worker::Handle>,
+    #[cfg(feature = "renderdoc")]
+    renderdoc: Option<RenderDoc>,
 }
 
 impl Drop for Device {
     fn drop(&mut self) {
         self.share.open.set(false);
+        *self.share.program_cache.borrow_mut() = None;
+    }
+}
+
+/// Graphics pipelines being built on the worker owning the context, see
+/// `Device::prewarm_graphics_pipelines_async`.
+#[derive(Debug)]
+pub struct PrewarmedPipelines {
+    receiver: mpsc::Receiver<Vec<Result<n::GraphicsPipeline, pso::CreationError>>>,
+    // Pipelines received by `is_ready`.
+    pipelines: Option<Vec<Result<n::GraphicsPipeline, pso::CreationError>>>,
+}
+
+impl PrewarmedPipelines {
+    /// Check if the pipelines are built, without blocking.
+    pub fn is_ready(&mut self) -> bool {
+        if self.pipelines.is_none() {
+            self.pipelines = self.receiver.try_recv().ok();
+        }
+        self.pipelines.is_some()
+    }
+
+    /// Wait for the pipelines to be built.
+    pub fn wait(self) -> Vec<Result<n::GraphicsPipeline, pso::CreationError>> {
+        match self.pipelines {
+            Some(pipelines) => pipelines,
+            None => self.receiver.recv().expect("GL worker thread panicked"),
+        }
+    }
+}
+
+fn import_image(image: n::ImageKind, kind: i::Kind, format: Format) -> n::Image {
+    let ext = kind.extent();
+    let desc = format.surface_desc();
+    let (block_width, block_height) = (desc.dim.0 as u32, desc.dim.1 as u32);
+    let blocks_x = (ext.width - 1) / block_width + 1;
+    let blocks_y = (ext.height - 1) / block_height + 1;
+    let size = blocks_x as u64
+        * blocks_y as u64
+        * ext.depth as u64
+        * kind.num_layers() as u64
+        * (desc.bits / 8) as u64;
+    n::Image {
+        kind: image,
+        dimensions: kind,
+        channel: format.base_format().1,
+        format,
+        requirements: memory::Requirements {
+            size,
+            alignment: 1,
+            type_mask: 0x7,
+        },
+        owned: false,
     }
 }
 
+/// Positions of consecutive descriptors, starting at an array element of a
+/// binding and spilling onto the following bindings of the layout once the
+/// array is exhausted.
+fn descriptor_positions(
+    layout: &[pso::DescriptorSetLayoutBinding],
+    binding: pso::DescriptorBinding,
+    array_offset: pso::DescriptorArrayIndex,
+) -> impl Iterator<Item = (pso::DescriptorBinding, pso::DescriptorArrayIndex)> {
+    let mut sizes = layout
+        .iter()
+        .filter(|b| b.binding >= binding)
+        .map(|b| (b.binding, b.count))
+        .collect::<Vec<_>>();
+    sizes.sort();
+    let mut sizes = sizes.into_iter();
+    let mut current = sizes.next();
+    let mut position = (binding, array_offset);
+    iter::from_fn(move || {
+        while let Some((b, count)) = current {
+            if position.0 == b && position.1 < count {
+                let result = position;
+                position.1 += 1;
+                return Some(result);
+            }
+            current = sizes.next();
+            if let Some((b, _)) = current {
+                position = (b, 0);
+            }
+        }
+        None
+    })
+}
+
 impl Device {
     /// Create a new `Device`.
     pub(crate) fn new(share: Starc<Share>) -> Self {
-        Device { share: share }
+        Device {
+            share: share,
+            worker: worker::Handle::current(),
+            #[cfg(feature = "renderdoc")]
+            renderdoc: RenderDoc::load(),
+        }
     }
 
-    pub fn create_shader_module_from_source(
+    // Register a vertex layout, reusing the ID of an identical layout.
+    fn register_vertex_layout(&self, layout: n::VertexLayout) -> n::VertexLayoutId {
+        let mut layouts = self.share.vertex_layouts.borrow_mut();
+        let existing = layouts.iter_mut().find(|entry| (entry.1).0 == layout);
+        if let Some((&id, &mut (_, ref mut refs))) = existing {
+            *refs += 1;
+            return id;
+        }
+        let id = self.share.next_vertex_layout.get();
+        self.share.next_vertex_layout.set(id + 1);
+        layouts.insert(id, (layout, 1));
+        id
+    }
+
+    // Release a vertex layout of a destroyed pipeline, freeing it along with
+    // the vertex arrays of the queues once no pipeline uses it.
+    fn release_vertex_layout(&self, id: n::VertexLayoutId) {
+        let mut layouts = self.share.vertex_layouts.borrow_mut();
+        let unused = match layouts.get_mut(&id) {
+            Some(&mut (_, ref mut refs)) => {
+                *refs -= 1;
+                *refs == 0
+            }
+            None => false,
+        };
+        if unused {
+            layouts.remove(&id);
+            let epoch = &self.share.vertex_array_epoch;
+            epoch.set(epoch.get() + 1);
+        }
+    }
+
+    /// GL function table of the context, for mixing custom GL code with the
+    /// objects of the device, see the `raw` accessors of the resources.
+    ///
+    /// The queues track the state of the context to skip redundant calls.
+    /// Custom code has to restore what it changes, or call
+    /// `invalidate_state` once done.
+    pub fn raw_context(&self) -> &gl::Gl {
+        &self.share.context
+    }
+
+    /// Make the queues reset the state of the context on their next
+    /// submission, after custom GL code changed it.
+    pub fn invalidate_state(&self) {
+        self.share.last_queue.set(None);
+    }
+
+    /// Wrap a buffer object created outside of the device.
+    ///
+    /// The buffer has to be bound to `target` for accessing its data, and
+    /// already have a data store of at least `size` bytes: it must not be
+    /// bound to a memory object. It is left alive when destroyed, and must
+    /// outlive its uses by the device.
+    pub unsafe fn import_buffer(&self, name: n::RawBuffer, target: GLenum, size: u64) -> n::Buffer {
+        n::Buffer {
+            raw: name,
+            target,
+            requirements: memory::Requirements {
+                size,
+                alignment: 1,
+                type_mask: 0x7,
+            },
+            owned: false,
+        }
+    }
+
+    /// Wrap a texture object created outside of the device.
+    ///
+    /// The texture has to be complete, with a storage matching `kind` and
+    /// `format`. It is left alive when destroyed, and must outlive its uses
+    /// by the device.
+    pub unsafe fn import_texture(
         &self,
-        data: &[u8],
-        stage: pso::Stage,
-    ) -> Result<n::ShaderModule, d::ShaderError> {
+        name: n::Texture,
+        kind: i::Kind,
+        format: Format,
+    ) -> n::Image {
+        import_image(n::ImageKind::Texture(name), kind, format)
+    }
+
+    /// Wrap a renderbuffer object created outside of the device, see
+    /// `import_texture`.
+    pub unsafe fn import_renderbuffer(
+        &self,
+        name: n::Surface,
+        kind: i::Kind,
+        format: Format,
+    ) -> n::Image {
+        import_image(n::ImageKind::Surface(name), kind, format)
+    }
+
+    /// Whether EGLImages (`GL_OES_EGL_image`) can be imported with
+    /// `import_egl_image`.
+    pub fn supports_egl_image_import(&self) -> bool {
+        self.share.ext.egl_image_target.is_some()
+    }
+
+    /// Whether images can be exported to EGLImages (`EGL_KHR_image_base`)
+    /// with `export_egl_image`.
+    ///
+    /// The display has to expose `EGL_KHR_gl_texture_2D_image` or
+    /// `EGL_KHR_gl_renderbuffer_image` as well, depending on the image.
+    pub fn supports_egl_image_export(&self) -> bool {
+        self.share.ext.egl_image_base.is_some()
+    }
+
+    /// Create a 2D texture sharing the storage of an EGLImage, for example
+    /// a frame of a camera or a video decoder.
+    ///
+    /// The texture keeps the storage alive, the EGLImage can be destroyed
+    /// once imported. `kind` and `format` have to describe its contents.
+    pub unsafe fn import_egl_image(
+        &self,
+        egl_image: n::EglImage,
+        kind: i::Kind,
+        format: Format,
+    ) -> Result<n::Image, i::CreationError> {
+        let egl_image_target = match self.share.ext.egl_image_target {
+            Some(egl_image_target) => egl_image_target,
+            None => {
+                error!("Importing EGLImages is not supported");
+                return Err(i::CreationError::Format(format));
+            }
+        };
+        match kind {
+            i::Kind::D2(_, _, 1, 1) => (),
+            _ => return Err(i::CreationError::Kind),
+        }
+
+        let gl = &self.share.context;
+        let mut name = 0;
+        gl.GenTextures(1, &mut name);
+        gl.BindTexture(gl::TEXTURE_2D, name);
+        (egl_image_target.egl_image_target_texture_2d)(gl::TEXTURE_2D, egl_image);
+        gl.BindTexture(gl::TEXTURE_2D, 0);
+
+        if let Err(err) = self.share.check() {
+            error!("Error importing EGLImage: {:?}", err);
+            gl.DeleteTextures(1, &name);
+            return Err(i::CreationError::OutOfMemory(
+                d::OutOfMemory::OutOfDeviceMemory,
+            ));
+        }
+
+        Ok(n::Image {
+            owned: true,
+            ..import_image(n::ImageKind::Texture(name), kind, format)
+        })
+    }
+
+    /// Export the first level of an image to an EGLImage, to share it with
+    /// other APIs or processes.
+    ///
+    /// `display` and `context` are the EGL display and context the device
+    /// was created on. Only single layer 2D images can be exported. Returns
+    /// `None` if the export is not supported or EGL fails to create the
+    /// EGLImage, which has to be destroyed with `destroy_egl_image`.
+    pub unsafe fn export_egl_image(
+        &self,
+        display: n::EglDisplay,
+        context: n::EglContext,
+        image: &n::Image,
+    ) -> Option<n::EglImage> {
+        let egl_image_base = match self.share.ext.egl_image_base {
+            Some(egl_image_base) => egl_image_base,
+            None => {
+                error!("Exporting EGLImages is not supported");
+                return None;
+            }
+        };
+        // Other textures can't be bound to `TEXTURE_2D`.
+        match image.dimensions {
+            i::Kind::D2(_, _, 1, 1) => (),
+            kind => {
+                error!("Can't export {:?} images to EGLImages", kind);
+                return None;
+            }
+        }
+        let (target, buffer) = match image.kind {
+            n::ImageKind::Texture(name) => (ext::EglImageBase::GL_TEXTURE_2D, name),
+            n::ImageKind::Surface(name) => (ext::EglImageBase::GL_RENDERBUFFER, name),
+        };
+        let attributes = [
+            ext::EglImageBase::GL_TEXTURE_LEVEL,
+            0,
+            ext::EglImageBase::NONE,
+        ];
+        let attributes = match image.kind {
+            n::ImageKind::Texture(_) => &attributes[..],
+            n::ImageKind::Surface(_) => &attributes[2..],
+        };
+
+        let egl_image = (egl_image_base.create_image)(
+            display,
+            context,
+            target,
+            buffer as usize as *mut _,
+            attributes.as_ptr(),
+        );
+        if egl_image.is_null() {
+            error!("Failed to export {:?} to an EGLImage", image.kind);
+            None
+        } else {
+            Some(egl_image)
+        }
+    }
+
+    /// Destroy an EGLImage returned by `export_egl_image`.
+    pub unsafe fn destroy_egl_image(&self, display: n::EglDisplay, egl_image: n::EglImage) {
+        // No EGLImage can be exported without the extension.
+        if let Some(egl_image_base) = self.share.ext.egl_image_base {
+            (egl_image_base.destroy_image)(display, egl_image);
+        }
+    }
+
+    /// Create a 2D texture from the planes of a Linux dma-buf, for example a
+    /// buffer of a Wayland client or a frame of a video decoder.
+    ///
+    /// `fourcc` is the DRM format of the buffer, and `modifier` its DRM
+    /// format modifier, if any. The display has to expose
+    /// `EGL_EXT_image_dma_buf_import`, and
+    /// `EGL_EXT_image_dma_buf_import_modifiers` for buffers with a modifier.
+    /// Both EGLImage import and export have to be supported by the device.
+    pub unsafe fn import_dma_buf(
+        &self,
+        display: n::EglDisplay,
+        kind: i::Kind,
+        format: Format,
+        fourcc: u32,
+        modifier: Option<u64>,
+        planes: &[n::DmaBufPlane],
+    ) -> Result<n::Image, i::CreationError> {
+        // The dma-buf is imported as an EGLImage first.
+        let egl_image_base = match self.share.ext.egl_image_base {
+            Some(egl_image_base) if self.supports_egl_image_import() => egl_image_base,
+            _ => {
+                error!("Importing dma-bufs is not supported");
+                return Err(i::CreationError::Format(format));
+            }
+        };
+        if planes.is_empty() || planes.len() > ext::EglImageBase::DMA_BUF_PLANES.len() {
+            return Err(i::CreationError::Data(planes.len()));
+        }
+        let (width, height) = match kind {
+            i::Kind::D2(width, height, 1, 1) => (width, height),
+            _ => return Err(i::CreationError::Kind),
+        };
+
+        let mut attributes = vec![
+            ext::EglImageBase::WIDTH,
+            width as _,
+            ext::EglImageBase::HEIGHT,
+            height as _,
+            ext::EglImageBase::LINUX_DRM_FOURCC,
+            fourcc as _,
+        ];
+        for (plane, names) in planes.iter().zip(&ext::EglImageBase::DMA_BUF_PLANES) {
+            attributes.extend_from_slice(&[
+                names[0],
+                plane.fd,
+                names[1],
+                plane.offset as _,
+                names[2],
+                plane.pitch as _,
+            ]);
+        }
+        if let Some(modifier) = modifier {
+            for names in &ext::EglImageBase::DMA_BUF_PLANE_MODIFIERS[..planes.len()] {
+                attributes.extend_from_slice(&[
+                    names[0],
+                    modifier as u32 as _,
+                    names[1],
+                    (modifier >> 32) as u32 as _,
+                ]);
+            }
+        }
+        attributes.push(ext::EglImageBase::NONE);
+
+        let egl_image = (egl_image_base.create_image)(
+            display,
+            ext::EglImageBase::NO_CONTEXT,
+            ext::EglImageBase::LINUX_DMA_BUF,
+            ptr::null_mut(),
+            attributes.as_ptr(),
+        );
+        if egl_image.is_null() {
+            error!(
+                "Failed to import a dma-buf of fourcc {:#x} and modifier {:?}",
+                fourcc, modifier
+            );
+            return Err(i::CreationError::Format(format));
+        }
+        let image = self.import_egl_image(egl_image, kind, format);
+        (egl_image_base.destroy_image)(display, egl_image);
+        image
+    }
+
+    /// Whether memory exported as an opaque file descriptor, for example by
+    /// a Vulkan device, can be imported with `import_memory_fd`.
+    pub fn supports_memory_fd_import(&self) -> bool {
+        self.share
+            .ext
+            .memory_object
+            .map_or(false, |ext| ext.import_memory_fd.is_some())
+    }
+
+    /// Whether memory exported as an opaque Win32 handle can be imported
+    /// with `import_memory_win32_handle`.
+    pub fn supports_memory_win32_handle_import(&self) -> bool {
+        self.share
+            .ext
+            .memory_object
+            .map_or(false, |ext| ext.import_memory_win32_handle.is_some())
+    }
+
+    /// Import `size` bytes of memory exported as an opaque file descriptor
+    /// (`GL_EXT_memory_object_fd`).
+    ///
+    /// The implementation takes ownership of the file descriptor. Memory of
+    /// a dedicated allocation has to be marked as `dedicated`.
+    pub unsafe fn import_memory_fd(
+        &self,
+        fd: c_int,
+        size: u64,
+        dedicated: bool,
+    ) -> Result<n::ExternalMemory, d::OutOfMemory> {
+        let import = match self
+            .share
+            .ext
+            .memory_object
+            .and_then(|ext| ext.import_memory_fd)
+        {
+            Some(import) => import,
+            None => {
+                error!("Importing memory from file descriptors is not supported");
+                return Err(d::OutOfMemory::OutOfDeviceMemory);
+            }
+        };
+        self.import_memory(size, dedicated, |raw| {
+            import(raw, size, ext::MemoryObject::HANDLE_TYPE_OPAQUE_FD, fd)
+        })
+    }
+
+    /// Import `size` bytes of memory exported as an opaque Win32 handle
+    /// (`GL_EXT_memory_object_win32`).
+    ///
+    /// The handle isn't closed by the implementation. Memory of a dedicated
+    /// allocation has to be marked as `dedicated`.
+    pub unsafe fn import_memory_win32_handle(
+        &self,
+        handle: *mut c_void,
+        size: u64,
+        dedicated: bool,
+    ) -> Result<n::ExternalMemory, d::OutOfMemory> {
+        let import = match self
+            .share
+            .ext
+            .memory_object
+            .and_then(|ext| ext.import_memory_win32_handle)
+        {
+            Some(import) => import,
+            None => {
+                error!("Importing memory from Win32 handles is not supported");
+                return Err(d::OutOfMemory::OutOfDeviceMemory);
+            }
+        };
+        self.import_memory(size, dedicated, |raw| {
+            import(
+                raw,
+                size,
+                ext::MemoryObject::HANDLE_TYPE_OPAQUE_WIN32,
+                handle,
+            )
+        })
+    }
+
+    unsafe fn import_memory<F>(
+        &self,
+        size: u64,
+        dedicated: bool,
+        import: F,
+    ) -> Result<n::ExternalMemory, d::OutOfMemory>
+    where
+        F: FnOnce(gl::types::GLuint),
+    {
+        let memory_object = self.share.ext.memory_object.unwrap();
+        let mut raw = 0;
+        (memory_object.create_memory_objects)(1, &mut raw);
+        if dedicated {
+            (memory_object.memory_object_parameteriv)(
+                raw,
+                ext::MemoryObject::DEDICATED_MEMORY_OBJECT,
+                &(gl::TRUE as GLint),
+            );
+        }
+        import(raw);
+
+        if let Err(err) = self.share.check() {
+            error!("Error importing memory: {:?}", err);
+            (memory_object.delete_memory_objects)(1, &raw);
+            return Err(d::OutOfMemory::OutOfDeviceMemory);
+        }
+        Ok(n::ExternalMemory { raw, size })
+    }
+
+    /// Create a 2D texture on imported memory, at the given offset.
+    ///
+    /// The parameters have to match the ones of the image the memory was
+    /// exported for.
+    pub unsafe fn create_image_on_external_memory(
+        &self,
+        memory: &n::ExternalMemory,
+        offset: u64,
+        kind: i::Kind,
+        num_levels: i::Level,
+        format: Format,
+    ) -> Result<n::Image, i::CreationError> {
+        let memory_object = self.share.ext.memory_object.unwrap();
+        let (width, height) = match kind {
+            i::Kind::D2(width, height, 1, 1) => (width, height),
+            _ => return Err(i::CreationError::Kind),
+        };
+        let internal_format = match format {
+            Format::Rgba8Srgb => gl::SRGB8_ALPHA8,
+            _ => match conv::format_to_gl_image_format(format) {
+                Some(internal_format) => internal_format,
+                None => return Err(i::CreationError::Format(format)),
+            },
+        };
+
+        let gl = &self.share.context;
+        let mut name = 0;
+        gl.GenTextures(1, &mut name);
+        gl.BindTexture(gl::TEXTURE_2D, name);
+        (memory_object.tex_storage_mem_2d)(
+            gl::TEXTURE_2D,
+            num_levels as _,
+            internal_format,
+            width as _,
+            height as _,
+            memory.raw,
+            offset,
+        );
+        gl.BindTexture(gl::TEXTURE_2D, 0);
+
+        if let Err(err) = self.share.check() {
+            error!("Error creating image on external memory: {:?}", err);
+            gl.DeleteTextures(1, &name);
+            return Err(i::CreationError::OutOfMemory(
+                d::OutOfMemory::OutOfDeviceMemory,
+            ));
+        }
+
+        Ok(n::Image {
+            owned: true,
+            ..import_image(n::ImageKind::Texture(name), kind, format)
+        })
+    }
+
+    /// Create a buffer of `size` bytes on imported memory, at the given
+    /// offset.
+    ///
+    /// The buffer can't be bound to a memory object of the device.
+    pub unsafe fn create_buffer_on_external_memory(
+        &self,
+        memory: &n::ExternalMemory,
+        offset: u64,
+        size: u64,
+        usage: buffer::Usage,
+    ) -> Result<n::Buffer, buffer::CreationError> {
+        let memory_object = self.share.ext.memory_object.unwrap();
+        match offset.checked_add(size) {
+            Some(end) if end <= memory.size => (),
+            _ => {
+                error!(
+                    "Buffer range {}+{} exceeds the {} bytes of external memory",
+                    offset, size, memory.size
+                );
+                return Err(buffer::CreationError::OutOfMemory(
+                    d::OutOfMemory::OutOfDeviceMemory,
+                ));
+            }
+        }
+        let target = if self.share.private_caps.buffer_role_change {
+            gl::ARRAY_BUFFER
+        } else {
+            match conv::buffer_usage_to_gl_target(usage) {
+                Some(target) => target,
+                None => return Err(buffer::CreationError::UnsupportedUsage { usage }),
+            }
+        };
+
+        let gl = &self.share.context;
+        let mut name = 0;
+        gl.GenBuffers(1, &mut name);
+        gl.BindBuffer(target, name);
+        (memory_object.buffer_storage_mem)(target, size as _, memory.raw, offset);
+        gl.BindBuffer(target, 0);
+
+        if let Err(err) = self.share.check() {
+            error!("Error creating buffer on external memory: {:?}", err);
+            gl.DeleteBuffers(1, &name);
+            return Err(buffer::CreationError::OutOfMemory(
+                d::OutOfMemory::OutOfDeviceMemory,
+            ));
+        }
+
+        Ok(n::Buffer {
+            owned: true,
+            ..self.import_buffer(name, target, size)
+        })
+    }
+
+    /// Release imported memory, once the images and buffers created on it
+    /// are destroyed.
+    pub unsafe fn free_external_memory(&self, memory: n::ExternalMemory) {
+        let memory_object = self.share.ext.memory_object.unwrap();
+        (memory_object.delete_memory_objects)(1, &memory.raw);
+    }
+
+    /// Whether semaphores exported as an opaque file descriptor, for example
+    /// by a Vulkan device, can be imported with `import_semaphore_fd`.
+    pub fn supports_semaphore_fd_import(&self) -> bool {
+        self.share
+            .ext
+            .semaphore
+            .map_or(false, |ext| ext.import_semaphore_fd.is_some())
+    }
+
+    /// Whether semaphores exported as an opaque Win32 handle can be imported
+    /// with `import_semaphore_win32_handle`.
+    pub fn supports_semaphore_win32_handle_import(&self) -> bool {
+        self.share
+            .ext
+            .semaphore
+            .map_or(false, |ext| ext.import_semaphore_win32_handle.is_some())
+    }
+
+    /// Import a semaphore exported as an opaque file descriptor
+    /// (`GL_EXT_semaphore_fd`).
+    ///
+    /// The implementation takes ownership of the file descriptor. The
+    /// semaphore can be waited on and signalled by submissions like any
+    /// other, synchronizing them with the work of the other API. Images
+    /// aren't transitioned, they have to be shared in the general layout.
+    pub unsafe fn import_semaphore_fd(&self, fd: c_int) -> Result<n::Semaphore, d::OutOfMemory> {
+        let import = match self
+            .share
+            .ext
+            .semaphore
+            .and_then(|ext| ext.import_semaphore_fd)
+        {
+            Some(import) => import,
+            None => {
+                error!("Importing semaphores from file descriptors is not supported");
+                return Err(d::OutOfMemory::OutOfDeviceMemory);
+            }
+        };
+        self.import_semaphore(|raw| import(raw, ext::Semaphore::HANDLE_TYPE_OPAQUE_FD, fd))
+    }
+
+    /// Import a semaphore exported as an opaque Win32 handle
+    /// (`GL_EXT_semaphore_win32`), see `import_semaphore_fd`.
+    ///
+    /// The handle isn't closed by the implementation.
+    pub unsafe fn import_semaphore_win32_handle(
+        &self,
+        handle: *mut c_void,
+    ) -> Result<n::Semaphore, d::OutOfMemory> {
+        let import = match self
+            .share
+            .ext
+            .semaphore
+            .and_then(|ext| ext.import_semaphore_win32_handle)
+        {
+            Some(import) => import,
+            None => {
+                error!("Importing semaphores from Win32 handles is not supported");
+                return Err(d::OutOfMemory::OutOfDeviceMemory);
+            }
+        };
+        self.import_semaphore(|raw| import(raw, ext::Semaphore::HANDLE_TYPE_OPAQUE_WIN32, handle))
+    }
+
+    unsafe fn import_semaphore<F>(&self, import: F) -> Result<n::Semaphore, d::OutOfMemory>
+    where
+        F: FnOnce(gl::types::GLuint),
+    {
+        let semaphore = self.share.ext.semaphore.unwrap();
+        let mut raw = 0;
+        (semaphore.gen_semaphores)(1, &mut raw);
+        import(raw);
+
+        if let Err(err) = self.share.check() {
+            error!("Error importing semaphore: {:?}", err);
+            (semaphore.delete_semaphores)(1, &raw);
+            return Err(d::OutOfMemory::OutOfDeviceMemory);
+        }
+        Ok(n::Semaphore {
+            sync: Mutex::new(None),
+            external: Some(raw),
+        })
+    }
+
+    /// Statistics of the work executed during the last presented frame.
+    pub fn frame_statistics(&self) -> FrameStatistics {
+        self.share.last_frame_statistics.get()
+    }
+
+    /// Start a RenderDoc frame capture of the commands submitted to the
+    /// context. Returns `false` if the application isn't running under
+    /// RenderDoc.
+    #[cfg(feature = "renderdoc")]
+    pub fn start_capture(&self) -> bool {
+        match self.renderdoc {
+            Some(ref renderdoc) => {
+                renderdoc.start_capture();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// End the RenderDoc frame capture started with `start_capture`.
+    /// Returns `true` if the capture was successful.
+    #[cfg(feature = "renderdoc")]
+    pub fn end_capture(&self) -> bool {
+        match self.renderdoc {
+            Some(ref renderdoc) if renderdoc.is_capturing() => renderdoc.end_capture(),
+            Some(_) => {
+                warn!("Ending a RenderDoc capture which wasn't started");
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Start compiling a shader, without waiting for the result.
+    fn compile_shader(&self, data: &[u8], stage: pso::Stage) -> Result<n::Shader, d::ShaderError> {
         let gl = &self.share.context;
 
         let can_compute = self.share.limits.max_compute_work_group_count[0] != 0;
         let can_tessellate = self.share.limits.max_patch_size != 0;
+        let can_geometry = self.share.features.contains(c::Features::GEOMETRY_SHADER);
         let target = match stage {
             pso::Stage::Vertex => gl::VERTEX_SHADER,
             pso::Stage::Hull if can_tessellate => gl::TESS_CONTROL_SHADER,
             pso::Stage::Domain if can_tessellate => gl::TESS_EVALUATION_SHADER,
-            pso::Stage::Geometry => gl::GEOMETRY_SHADER,
+            pso::Stage::Geometry if can_geometry => gl::GEOMETRY_SHADER,
             pso::Stage::Fragment => gl::FRAGMENT_SHADER,
             pso::Stage::Compute if can_compute => gl::COMPUTE_SHADER,
             _ => return Err(d::ShaderError::UnsupportedStage(stage)),
@@ -147,8 +1103,22 @@ impl Device {
         }
         info!("\tCompiled shader {}", name);
         if let Err(err) = self.share.check() {
-            panic!("Error compiling shader: {:?}", err);
+            unsafe { gl.DeleteShader(name) };
+            return Err(d::ShaderError::CompilationFailed(format!(
+                "Error compiling shader: {:?}",
+                err
+            )));
         }
+        Ok(name)
+    }
+
+    pub fn create_shader_module_from_source(
+        &self,
+        data: &[u8],
+        stage: pso::Stage,
+    ) -> Result<n::ShaderModule, d::ShaderError> {
+        let gl = &self.share.context;
+        let name = self.compile_shader(data, stage)?;
 
         let status = get_shader_iv(gl, name, gl::COMPILE_STATUS);
         let log = get_shader_log(gl, name);
@@ -162,7 +1132,299 @@ impl Device {
         }
     }
 
-    fn bind_target_compat(
+    /// Whether resident texture handles (`GL_ARB_bindless_texture`) can be
+    /// used through a `BindlessTable`.
+    pub fn supports_bindless_textures(&self) -> bool {
+        self.share.ext.bindless_texture.is_some()
+    }
+
+    /// Create a table of bindless texture handles with `capacity` entries.
+    ///
+    /// Fails with `BindlessTableError::Unsupported` if
+    /// `supports_bindless_textures` is false.
+    pub unsafe fn create_bindless_table(
+        &self,
+        capacity: usize,
+    ) -> Result<n::BindlessTable, n::BindlessTableError> {
+        if self.share.ext.bindless_texture.is_none() {
+            return Err(n::BindlessTableError::Unsupported);
+        }
+
+        let gl = &self.share.context;
+        let size = capacity as u64 * 16;
+        let mut name = 0;
+        if self.share.private_caps.direct_state_access {
+            gl.CreateBuffers(1, &mut name);
+            gl.NamedBufferData(name, size as _, ptr::null(), gl::DYNAMIC_DRAW);
+        } else {
+            gl.GenBuffers(1, &mut name);
+            gl.BindBuffer(gl::UNIFORM_BUFFER, name);
+            gl.BufferData(gl::UNIFORM_BUFFER, size as _, ptr::null(), gl::DYNAMIC_DRAW);
+            gl.BindBuffer(gl::UNIFORM_BUFFER, 0);
+        }
+        if let Err(err) = self.share.check() {
+            error!("Error creating bindless table: {:?}", err);
+            gl.DeleteBuffers(1, &name);
+            return Err(n::BindlessTableError::OutOfMemory(
+                d::OutOfMemory::OutOfDeviceMemory,
+            ));
+        }
+
+        Ok(n::BindlessTable {
+            buffer: n::Buffer {
+                raw: name,
+                target: gl::UNIFORM_BUFFER,
+                requirements: memory::Requirements {
+                    size,
+                    alignment: 1,
+                    type_mask: 0x7,
+                },
+                owned: true,
+            },
+            entries: vec![None; capacity],
+        })
+    }
+
+    /// Make the view resident and store its handle in the given table entry.
+    ///
+    /// The sampler state of the texture becomes immutable once a handle has
+    /// been created for it, which matters for `FatSampler::Info`. Handles are
+    /// made non-resident when their texture or sampler is destroyed.
+    pub unsafe fn write_bindless_table(
+        &self,
+        table: &mut n::BindlessTable,
+        index: usize,
+        view: &n::ImageView,
+        sampler: &n::FatSampler,
+    ) -> Result<(), n::BindlessTableError> {
+        let bindless = self
+            .share
+            .ext
+            .bindless_texture
+            .expect("Bindless tables are only created with bindless texture support");
+        let gl = &self.share.context;
+
+        if index >= table.entries.len() {
+            return Err(n::BindlessTableError::IndexOutOfRange);
+        }
+        let texture = match *view {
+            n::ImageView::Texture(texture, _, _) | n::ImageView::TextureLayer(texture, _, _, _) => {
+                texture
+            }
+            n::ImageView::Surface(_) => return Err(n::BindlessTableError::InvalidView),
+        };
+        let (handle, sampler) = match *sampler {
+            n::FatSampler::Sampler(sampler) => (
+                (bindless.get_texture_sampler_handle)(texture, sampler),
+                Some(sampler),
+            ),
+            n::FatSampler::Info(ref info) if self.share.private_caps.direct_state_access => {
+                set_sampler_info(
+                    &self.share,
+                    info,
+                    |a, b| gl.TextureParameterf(texture, a, b),
+                    |a, b| gl.TextureParameterfv(texture, a, &b[0]),
+                    |a, b| gl.TextureParameteri(texture, a, b),
+                );
+                ((bindless.get_texture_handle)(texture), None)
+            }
+            n::FatSampler::Info(ref info) => {
+                gl.BindTexture(gl::TEXTURE_2D, texture);
+                set_sampler_info(
+                    &self.share,
+                    info,
+                    |a, b| gl.TexParameterf(gl::TEXTURE_2D, a, b),
+                    |a, b| gl.TexParameterfv(gl::TEXTURE_2D, a, &b[0]),
+                    |a, b| gl.TexParameteri(gl::TEXTURE_2D, a, b),
+                );
+                ((bindless.get_texture_handle)(texture), None)
+            }
+        };
+
+        self.clear_bindless_entry(table, index)?;
+        self.share
+            .bindless_handles
+            .borrow_mut()
+            .entry(handle)
+            .or_insert_with(|| {
+                (bindless.make_texture_handle_resident)(handle);
+                n::BindlessHandle {
+                    texture,
+                    sampler,
+                    entries: 0,
+                }
+            })
+            .entries += 1;
+        table.entries[index] = Some(handle);
+
+        let data = [handle as u32, (handle >> 32) as u32, 0, 0];
+        let offset = (index * mem::size_of_val(&data)) as _;
+        let size = mem::size_of_val(&data) as _;
+        if self.share.private_caps.direct_state_access {
+            gl.NamedBufferSubData(table.buffer.raw, offset, size, data.as_ptr() as *const _);
+        } else {
+            gl.BindBuffer(gl::UNIFORM_BUFFER, table.buffer.raw);
+            gl.BufferSubData(gl::UNIFORM_BUFFER, offset, size, data.as_ptr() as *const _);
+            gl.BindBuffer(gl::UNIFORM_BUFFER, 0);
+        }
+        Ok(())
+    }
+
+    /// Release the handle stored in the given table entry, making it
+    /// non-resident if no other entry references it.
+    pub unsafe fn clear_bindless_entry(
+        &self,
+        table: &mut n::BindlessTable,
+        index: usize,
+    ) -> Result<(), n::BindlessTableError> {
+        match table.entries.get_mut(index) {
+            Some(entry) => {
+                if let Some(handle) = entry.take() {
+                    self.release_bindless_handle(handle);
+                }
+                Ok(())
+            }
+            None => Err(n::BindlessTableError::IndexOutOfRange),
+        }
+    }
+
+    /// Destroy the table, making the handles no other table references
+    /// non-resident.
+    pub unsafe fn destroy_bindless_table(&self, table: n::BindlessTable) {
+        for &handle in table.entries.iter().flatten() {
+            self.release_bindless_handle(handle);
+        }
+        self.share.context.DeleteBuffers(1, &table.buffer.raw);
+    }
+
+    // Drop the reference of a table entry to a handle, making the handle
+    // non-resident if it was the last one.
+    unsafe fn release_bindless_handle(&self, handle: u64) {
+        let mut handles = self.share.bindless_handles.borrow_mut();
+        let released = match handles.get_mut(&handle) {
+            Some(resident) => {
+                resident.entries -= 1;
+                resident.entries == 0
+            }
+            // Already released along with its texture or sampler.
+            None => false,
+        };
+        if released {
+            handles.remove(&handle);
+            if let Some(bindless) = self.share.ext.bindless_texture {
+                (bindless.make_texture_handle_non_resident)(handle);
+            }
+        }
+    }
+
+    // Make the handles of a texture or sampler about to be destroyed
+    // non-resident, the entries referencing them become undefined.
+    unsafe fn release_bindless_handles<F>(&self, destroyed: F)
+    where
+        F: Fn(&n::BindlessHandle) -> bool,
+    {
+        let bindless = match self.share.ext.bindless_texture {
+            Some(bindless) => bindless,
+            None => return,
+        };
+        self.share
+            .bindless_handles
+            .borrow_mut()
+            .retain(|&handle, resident| {
+                let destroyed = destroyed(resident);
+                if destroyed {
+                    (bindless.make_texture_handle_non_resident)(handle);
+                }
+                !destroyed
+            });
+    }
+
+    /// Create a timeline, starting at `initial_value`.
+    ///
+    /// Values are signalled with `CommandQueue::signal_timeline`.
+    pub fn create_timeline(&self, initial_value: u64) -> n::Timeline {
+        n::Timeline(Mutex::new(n::TimelineState {
+            signalled: initial_value,
+            submitted: initial_value,
+            pending: VecDeque::new(),
+        }))
+    }
+
+    /// Returns the last value reached by the timeline, without blocking.
+    pub unsafe fn timeline_value(&self, timeline: &n::Timeline) -> u64 {
+        let mut state = timeline.0.lock().unwrap();
+        self.poll_timeline(&mut state)
+    }
+
+    unsafe fn poll_timeline(&self, state: &mut n::TimelineState) -> u64 {
+        let gl = &self.share.context;
+        while let Some(&(value, sync)) = state.pending.front() {
+            match gl.ClientWaitSync(sync, 0, 0) {
+                gl::ALREADY_SIGNALED | gl::CONDITION_SATISFIED => {
+                    gl.DeleteSync(sync);
+                    state.signalled = value;
+                    state.pending.pop_front();
+                }
+                _ => break,
+            }
+        }
+        state.signalled
+    }
+
+    /// Wait for the timeline to reach `value`, at most `timeout_ns` nanoseconds.
+    ///
+    /// Returns `false` if the value wasn't reached before the timeout, or if
+    /// no signal of the value (or a greater one) was submitted yet.
+    pub unsafe fn wait_for_timeline_value(
+        &self,
+        timeline: &n::Timeline,
+        value: u64,
+        timeout_ns: u64,
+    ) -> Result<bool, d::OomOrDeviceLost> {
+        // The lock is held while waiting, so that the sync object can't be
+        // released by another thread in the meantime.
+        let mut state = timeline.0.lock().unwrap();
+        if self.poll_timeline(&mut state) >= value {
+            return Ok(true);
+        }
+        if self.share.is_lost() {
+            return Err(d::OomOrDeviceLost::DeviceLost(d::DeviceLost));
+        }
+
+        let gl = &self.share.context;
+        // Waiting on the first signal covering the value is enough.
+        let index = match state.pending.iter().position(|&(v, _)| v >= value) {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+        let (reached, sync) = state.pending[index];
+        match gl.ClientWaitSync(sync, gl::SYNC_FLUSH_COMMANDS_BIT, timeout_ns) {
+            gl::ALREADY_SIGNALED | gl::CONDITION_SATISFIED => {
+                for (_, sync) in state.pending.drain(..=index) {
+                    gl.DeleteSync(sync);
+                }
+                state.signalled = reached;
+                Ok(true)
+            }
+            gl::WAIT_FAILED => {
+                if let Err(err) = self.share.check() {
+                    error!("Error when waiting on timeline: {:?}", err);
+                }
+                Ok(false)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Destroy the timeline, releasing the sync objects of pending values.
+    pub unsafe fn destroy_timeline(&self, timeline: n::Timeline) {
+        let gl = &self.share.context;
+        for (_, sync) in timeline.0.into_inner().unwrap().pending {
+            gl.DeleteSync(sync);
+        }
+    }
+
+    pub(crate) fn bind_target_compat(
         gl: &GlContainer,
         point: GLenum,
         attachment: GLenum,
@@ -172,11 +1434,11 @@ impl Device {
             n::ImageView::Surface(surface) => unsafe {
                 gl.FramebufferRenderbuffer(point, attachment, gl::RENDERBUFFER, surface);
             },
-            n::ImageView::Texture(texture, level) => unsafe {
+            n::ImageView::Texture(texture, level, _) => unsafe {
                 gl.BindTexture(gl::TEXTURE_2D, texture);
                 gl.FramebufferTexture2D(point, attachment, gl::TEXTURE_2D, texture, level as _);
             },
-            n::ImageView::TextureLayer(texture, level, layer) => unsafe {
+            n::ImageView::TextureLayer(texture, level, layer, _) => unsafe {
                 gl.BindTexture(gl::TEXTURE_2D, texture);
                 gl.FramebufferTexture3D(
                     point,
@@ -190,16 +1452,46 @@ impl Device {
         }
     }
 
-    fn bind_target(gl: &GlContainer, point: GLenum, attachment: GLenum, view: &n::ImageView) {
+    pub(crate) fn bind_target(
+        gl: &GlContainer,
+        point: GLenum,
+        attachment: GLenum,
+        view: &n::ImageView,
+    ) {
+        match *view {
+            n::ImageView::Surface(surface) => unsafe {
+                gl.FramebufferRenderbuffer(point, attachment, gl::RENDERBUFFER, surface);
+            },
+            n::ImageView::Texture(texture, level, _) => unsafe {
+                gl.FramebufferTexture(point, attachment, texture, level as _);
+            },
+            n::ImageView::TextureLayer(texture, level, layer, _) => unsafe {
+                gl.FramebufferTextureLayer(point, attachment, texture, level as _, layer as _);
+            },
+        }
+    }
+
+    fn bind_target_named(
+        gl: &GlContainer,
+        framebuffer: n::FrameBuffer,
+        attachment: GLenum,
+        view: &n::ImageView,
+    ) {
         match *view {
             n::ImageView::Surface(surface) => unsafe {
-                gl.FramebufferRenderbuffer(point, attachment, gl::RENDERBUFFER, surface);
+                gl.NamedFramebufferRenderbuffer(framebuffer, attachment, gl::RENDERBUFFER, surface);
             },
-            n::ImageView::Texture(texture, level) => unsafe {
-                gl.FramebufferTexture(point, attachment, texture, level as _);
+            n::ImageView::Texture(texture, level, _) => unsafe {
+                gl.NamedFramebufferTexture(framebuffer, attachment, texture, level as _);
             },
-            n::ImageView::TextureLayer(texture, level, layer) => unsafe {
-                gl.FramebufferTextureLayer(point, attachment, texture, level as _, layer as _);
+            n::ImageView::TextureLayer(texture, level, layer, _) => unsafe {
+                gl.NamedFramebufferTextureLayer(
+                    framebuffer,
+                    attachment,
+                    texture,
+                    level as _,
+                    layer as _,
+                );
             },
         }
     }
@@ -260,7 +1552,12 @@ impl Device {
     ) -> Result<String, d::ShaderError> {
         let mut compile_options = glsl::CompilerOptions::default();
         // see version table at https://en.wikipedia.org/wiki/OpenGL_Shading_Language
-        compile_options.version = match self.share.info.shading_language.tuple() {
+        let shading_language = &self.share.info.shading_language;
+        compile_options.version = match shading_language.tuple() {
+            // ESSL 3.00 is the latest version SPIRV-Cross targets, the
+            // version of newer contexts is declared after translation.
+            (3, _) if shading_language.is_embedded => glsl::Version::V3_00Es,
+            (1, _) if shading_language.is_embedded => glsl::Version::V1_00Es,
             (4, 60) => glsl::Version::V4_60,
             (4, 50) => glsl::Version::V4_50,
             (4, 40) => glsl::Version::V4_40,
@@ -282,20 +1579,32 @@ impl Device {
 
         ast.set_compiler_options(&compile_options)
             .map_err(gen_unexpected_error)?;
-        ast.compile().map_err(|err| {
+        let source = ast.compile().map_err(|err| {
             let msg = match err {
                 SpirvErrorCode::CompilationError(msg) => msg,
                 SpirvErrorCode::Unhandled => "Unknown compile error".into(),
             };
             d::ShaderError::CompilationFailed(msg)
-        })
+        })?;
+
+        // The geometry and tessellation extensions of ES 3.1 require ESSL
+        // 3.10. ESSL 3.00 shaders are valid in later versions, so declaring
+        // the version of the context is enough.
+        match shading_language.tuple() {
+            (3, minor) if shading_language.is_embedded && minor >= 10 => {
+                let version = format!("#version 3{:02} es", minor.min(20));
+                Ok(source.replacen("#version 300 es", &version, 1))
+            }
+            _ => Ok(source),
+        }
     }
 
     fn remap_bindings(
         &self,
         ast: &mut spirv::Ast<glsl::Target>,
         desc_remap_data: &mut n::DescRemapData,
-        nb_map: &mut FastHashMap<String, pso::DescriptorBinding>,
+        nb_map: &mut NameBindingMap,
+        fetches: &[FramebufferFetch],
     ) {
         let res = ast.get_shader_resources().unwrap();
         self.remap_binding(
@@ -305,6 +1614,20 @@ impl Device {
             &res.sampled_images,
             n::BindingTypes::Images,
         );
+        // Subpass inputs not read with framebuffer fetch are sampled from
+        // the attachments.
+        let subpass_inputs = res
+            .subpass_inputs
+            .into_iter()
+            .filter(|input| fetches.iter().all(|fetch| fetch.id != input.id))
+            .collect::<Vec<_>>();
+        self.remap_binding(
+            ast,
+            desc_remap_data,
+            nb_map,
+            &subpass_inputs,
+            n::BindingTypes::Images,
+        );
         self.remap_binding(
             ast,
             desc_remap_data,
@@ -312,13 +1635,20 @@ impl Device {
             &res.uniform_buffers,
             n::BindingTypes::UniformBuffers,
         );
+        self.remap_binding(
+            ast,
+            desc_remap_data,
+            nb_map,
+            &res.storage_images,
+            n::BindingTypes::StorageImages,
+        );
     }
 
     fn remap_binding(
         &self,
         ast: &mut spirv::Ast<glsl::Target>,
         desc_remap_data: &mut n::DescRemapData,
-        nb_map: &mut FastHashMap<String, pso::DescriptorBinding>,
+        nb_map: &mut NameBindingMap,
         all_res: &[spirv::Resource],
         btype: n::BindingTypes,
     ) {
@@ -341,10 +1671,40 @@ impl Device {
                 {
                     ast.set_decoration(res.id, spirv::Decoration::Binding, *nb)
                         .unwrap()
+                } else if btype == n::BindingTypes::UniformBuffers
+                    && !self
+                        .share
+                        .legacy_features
+                        .contains(LegacyFeatures::CONSTANT_BUFFER)
+                {
+                    // The block is declared as a uniform of structure type,
+                    // whose members are loaded from the buffer before draws.
+                    let name = format!("{}{}", UNIFORM_BLOCK_PREFIX, nb);
+                    ast.set_name(res.id, &name).unwrap();
+                    let num_members = match ast.get_type(res.base_type_id).unwrap() {
+                        spirv::Type::Struct { member_types, .. } => member_types.len(),
+                        _ => 0,
+                    };
+                    for index in 0..num_members as u32 {
+                        let mut member = ast.get_member_name(res.base_type_id, index).unwrap();
+                        if member.is_empty() {
+                            // Name given by SPIRV-Cross to unnamed members.
+                            member = format!("_m{}", index);
+                        }
+                        let offset = ast
+                            .get_member_decoration(
+                                res.base_type_id,
+                                index,
+                                spirv::Decoration::Offset,
+                            )
+                            .unwrap();
+                        nb_map.insert(format!("{}.{}", name, member), (btype, *nb, offset));
+                    }
                 } else {
                     ast.unset_decoration(res.id, spirv::Decoration::Binding)
                         .unwrap();
-                    assert!(nb_map.insert(res.name.clone(), *nb).is_none());
+                    let old = nb_map.insert(res.name.clone(), (btype, *nb, 0));
+                    assert!(old.is_none() || old == Some((btype, *nb, 0)));
                 }
                 ast.unset_decoration(res.id, spirv::Decoration::DescriptorSet)
                     .unwrap();
@@ -356,7 +1716,7 @@ impl Device {
         &self,
         ast: &mut spirv::Ast<glsl::Target>,
         desc_remap_data: &mut n::DescRemapData,
-        nb_map: &mut FastHashMap<String, pso::DescriptorBinding>,
+        nb_map: &mut NameBindingMap,
     ) {
         let mut id_map =
             FastHashMap::<u32, (pso::DescriptorSetIndex, pso::DescriptorBinding)>::default();
@@ -365,19 +1725,16 @@ impl Device {
         self.populate_id_map(ast, &mut id_map, &res.separate_samplers);
 
         for cis in ast.get_combined_image_samplers().unwrap() {
-            let (set, binding) = id_map.get(&cis.image_id).unwrap();
-            let nb = desc_remap_data.reserve_binding(n::BindingTypes::Images);
-            desc_remap_data.insert_missing_binding(nb, n::BindingTypes::Images, *set, *binding);
-            let (set, binding) = id_map.get(&cis.sampler_id).unwrap();
-            desc_remap_data.insert_missing_binding(nb, n::BindingTypes::Images, *set, *binding);
-
-            let new_name = "GFX_HAL_COMBINED_SAMPLER".to_owned()
-                + "_"
-                + &cis.sampler_id.to_string()
-                + "_"
-                + &cis.image_id.to_string()
-                + "_"
-                + &cis.combined_id.to_string();
+            let image = *id_map.get(&cis.image_id).unwrap();
+            let sampler = *id_map.get(&cis.sampler_id).unwrap();
+            // The same pair may be used by several stages of a pipeline, so
+            // both the unit and the name have to be stable across them.
+            let nb = desc_remap_data.combine_image_sampler(image, sampler);
+
+            let new_name = format!(
+                "GFX_HAL_COMBINED_SAMPLER_{}_{}_{}_{}",
+                image.0, image.1, sampler.0, sampler.1,
+            );
             ast.set_name(cis.combined_id, &new_name).unwrap();
             if self
                 .share
@@ -389,7 +1746,8 @@ impl Device {
             } else {
                 ast.unset_decoration(cis.combined_id, spirv::Decoration::Binding)
                     .unwrap();
-                assert!(nb_map.insert(new_name, nb).is_none())
+                let old = nb_map.insert(new_name, (n::BindingTypes::Images, nb, 0));
+                assert!(old.is_none() || old == Some((n::BindingTypes::Images, nb, 0)));
             }
             ast.unset_decoration(cis.combined_id, spirv::Decoration::DescriptorSet)
                 .unwrap();
@@ -413,48 +1771,650 @@ impl Device {
         }
     }
 
-    fn compile_shader(
+    /// Built-ins through which color attachments can be fetched in the
+    /// shaders, if framebuffer fetch is supported.
+    fn framebuffer_fetch_kind(&self) -> Option<FramebufferFetchKind> {
+        let shading_language = &self.share.info.shading_language;
+        let legacy = if shading_language.is_embedded {
+            shading_language.major < 3
+        } else {
+            match shading_language.tuple() {
+                version if version < (1, 30) => true,
+                // Fragment outputs have no explicit location to fetch from.
+                version if version < (3, 30) => return None,
+                _ => false,
+            }
+        };
+        if self.share.private_caps.framebuffer_fetch {
+            Some(FramebufferFetchKind::Ext { legacy })
+        } else if self.share.private_caps.framebuffer_fetch_arm {
+            Some(FramebufferFetchKind::Arm)
+        } else {
+            None
+        }
+    }
+
+    /// Select the subpass inputs of a fragment shader which can be read with
+    /// framebuffer fetch, naming them after their input attachment index.
+    fn framebuffer_fetches(
+        &self,
+        ast: &mut spirv::Ast<glsl::Target>,
+        subpass: &n::SubpassDesc,
+        kind: FramebufferFetchKind,
+    ) -> Vec<FramebufferFetch> {
+        let res = ast.get_shader_resources().unwrap();
+        let mut fetches = Vec::new();
+        for input in res.subpass_inputs {
+            let index = ast
+                .get_decoration(input.id, spirv::Decoration::InputAttachmentIndex)
+                .unwrap();
+            let location = match subpass.fetch_location(index as _) {
+                Some(0) => 0,
+                Some(_) if kind == FramebufferFetchKind::Arm => continue,
+                Some(location) => location,
+                None => continue,
+            };
+            let name = format!("{}{}", INPUT_ATTACHMENT_PREFIX, index);
+            ast.set_name(input.id, &name).unwrap();
+            fetches.push(FramebufferFetch {
+                id: input.id,
+                name,
+                location,
+            });
+        }
+        fetches
+    }
+
+    /// Translate a shader module to GLSL, remapping its bindings.
+    ///
+    /// `subpass` is the subpass a fragment shader is used in, its input
+    /// attachments are read with framebuffer fetch if possible.
+    fn translate_shader(
         &self,
         point: &pso::EntryPoint<B>,
-        stage: pso::Stage,
+        subpass: Option<&pass::Subpass<B>>,
         desc_remap_data: &mut n::DescRemapData,
-        name_binding_map: &mut FastHashMap<String, pso::DescriptorBinding>,
-    ) -> n::Shader {
+        name_binding_map: &mut NameBindingMap,
+    ) -> Result<ShaderSource, d::ShaderError> {
         assert_eq!(point.entry, "main");
         match *point.module {
             n::ShaderModule::Raw(raw) => {
                 debug!("Can't remap bindings for raw shaders. Assuming they are already rebound.");
-                raw
+                Ok(ShaderSource::Raw(raw))
             }
             n::ShaderModule::Spirv(ref spirv) => {
-                let mut ast = self.parse_spirv(spirv).unwrap();
-
-                self.specialize_ast(&mut ast, point.specialization).unwrap();
-                self.remap_bindings(&mut ast, desc_remap_data, name_binding_map);
+                let mut ast = self.parse_spirv(spirv)?;
+
+                self.specialize_ast(&mut ast, point.specialization)?;
+                let fetch = match (subpass, self.framebuffer_fetch_kind()) {
+                    (Some(subpass), Some(kind)) => {
+                        let sp = &subpass.main_pass.subpasses[subpass.index];
+                        let fetched_outputs = sp
+                            .fetched_attachments
+                            .iter()
+                            .enumerate()
+                            .map(|(i, &id)| {
+                                let format = subpass.main_pass.attachments[id].format;
+                                (sp.color_attachments.len() + i, fetch_type(format))
+                            })
+                            .collect::<Vec<_>>();
+                        Some((
+                            kind,
+                            self.framebuffer_fetches(&mut ast, sp, kind),
+                            fetched_outputs,
+                        ))
+                    }
+                    _ => None,
+                };
+                let fetches = match fetch {
+                    Some((_, ref fetches, _)) => &fetches[..],
+                    None => &[],
+                };
+                self.remap_bindings(&mut ast, desc_remap_data, name_binding_map, fetches);
                 self.combine_separate_images_and_samplers(
                     &mut ast,
                     desc_remap_data,
                     name_binding_map,
                 );
 
-                let glsl = self.translate_spirv(&mut ast).unwrap();
+                let mut glsl = self.translate_spirv(&mut ast)?;
+                if let Some((kind, fetches, fetched_outputs)) = fetch {
+                    if !fetches.is_empty() || !fetched_outputs.is_empty() {
+                        glsl = apply_framebuffer_fetch(&glsl, kind, &fetches, &fetched_outputs);
+                    }
+                }
                 info!("Generated:\n{:?}", glsl);
-                let shader = match self
-                    .create_shader_module_from_source(glsl.as_bytes(), stage)
-                    .unwrap()
-                {
-                    n::ShaderModule::Raw(raw) => raw,
-                    _ => panic!("Unhandled"),
+                Ok(ShaderSource::Glsl(glsl))
+            }
+        }
+    }
+
+    /// Resolve the locations of the uniforms and the indices of the uniform
+    /// blocks of a linked program, which are bound by name.
+    fn resolve_uniform_bindings(
+        &self,
+        program: n::Program,
+        name_binding_map: &NameBindingMap,
+    ) -> Vec<n::UniformBinding> {
+        let gl = &self.share.context;
+        let mut bindings = Vec::with_capacity(name_binding_map.len());
+        if !self
+            .share
+            .legacy_features
+            .contains(LegacyFeatures::CONSTANT_BUFFER)
+        {
+            self.resolve_uniform_block_members(program, name_binding_map, &mut bindings);
+        }
+        for (name, &(btype, binding, _)) in name_binding_map {
+            let c_name = CString::new(name.as_bytes()).unwrap();
+            match btype {
+                // Members of emulated blocks are resolved above.
+                n::BindingTypes::UniformBuffers if name.starts_with(UNIFORM_BLOCK_PREFIX) => {}
+                n::BindingTypes::UniformBuffers => {
+                    let index = unsafe { gl.GetUniformBlockIndex(program, c_name.as_ptr()) };
+                    if index != gl::INVALID_INDEX {
+                        bindings.push(n::UniformBinding::Block { index, binding });
+                    }
+                }
+                n::BindingTypes::Images | n::BindingTypes::StorageImages => {
+                    let location = unsafe { gl.GetUniformLocation(program, c_name.as_ptr()) };
+                    // Unused uniforms are optimized out.
+                    if location != -1 {
+                        bindings.push(n::UniformBinding::Uniform { location, binding });
+                    }
+                }
+            }
+        }
+        bindings
+    }
+
+    /// Resolve the locations and types of the uniforms emulating the
+    /// members of the uniform blocks of a linked program.
+    fn resolve_uniform_block_members(
+        &self,
+        program: n::Program,
+        name_binding_map: &NameBindingMap,
+        bindings: &mut Vec<n::UniformBinding>,
+    ) {
+        let gl = &self.share.context;
+        let num_uniforms = get_program_iv(gl, program, gl::ACTIVE_UNIFORMS);
+        let mut name = [0u8; 256];
+        for index in 0..num_uniforms as gl::types::GLuint {
+            let (mut length, mut count, mut ty) = (0, 0, 0);
+            unsafe {
+                gl.GetActiveUniform(
+                    program,
+                    index,
+                    name.len() as _,
+                    &mut length,
+                    &mut count,
+                    &mut ty,
+                    name.as_mut_ptr() as *mut _,
+                );
+            }
+            let name = String::from_utf8_lossy(&name[..length as usize]);
+            // Arrays are reported as their first element.
+            let name = name.trim_end_matches("[0]");
+            match name_binding_map.get(name) {
+                Some(&(n::BindingTypes::UniformBuffers, binding, offset)) => {
+                    let c_name = CString::new(name.as_bytes()).unwrap();
+                    let location = unsafe { gl.GetUniformLocation(program, c_name.as_ptr()) };
+                    bindings.push(n::UniformBinding::BlockMember {
+                        location,
+                        binding,
+                        offset,
+                        ty,
+                        count,
+                    });
+                }
+                _ if name.starts_with(UNIFORM_BLOCK_PREFIX) => {
+                    warn!("Unsupported member {} of an emulated uniform block", name);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Assign the resolved uniform bindings to the program, they are part of
+    /// the program state so this is only done once after linking.
+    fn apply_uniform_bindings(&self, program: n::Program, bindings: &[n::UniformBinding]) {
+        if bindings.is_empty() {
+            return;
+        }
+        let gl = &self.share.context;
+        let mut block_members = Vec::new();
+        unsafe {
+            gl.UseProgram(program);
+            for binding in bindings {
+                match *binding {
+                    n::UniformBinding::Uniform { location, binding } => {
+                        gl.Uniform1i(location, binding as _)
+                    }
+                    n::UniformBinding::Block { index, binding } => {
+                        gl.UniformBlockBinding(program, index, binding)
+                    }
+                    n::UniformBinding::BlockMember { .. } => block_members.push(*binding),
+                }
+            }
+        }
+        if !block_members.is_empty() {
+            self.share
+                .uniform_block_members
+                .borrow_mut()
+                .insert(program, block_members);
+        }
+    }
+
+    /// Start linking the shaders into the program, loading the program
+    /// binary from the cache instead if possible.
+    ///
+    /// `color_targets` fragment outputs are bound to the draw buffers, if
+    /// locations aren't specified in the shaders.
+    unsafe fn link_program(
+        &self,
+        name: n::Program,
+        sources: Vec<(pso::Stage, ShaderSource)>,
+        color_targets: usize,
+        name_binding_map: NameBindingMap,
+    ) -> Result<PendingProgram, pso::CreationError> {
+        let gl = &self.share.context;
+        let share = &self.share;
+        let program_cache = share.program_cache.borrow();
+
+        // Raw shaders can't be hashed.
+        let cache_key = match *program_cache {
+            Some(ref cache) if share.private_caps.program_binary => {
+                let mut glsl_sources = Vec::new();
+                for &(stage, ref source) in &sources {
+                    match *source {
+                        ShaderSource::Glsl(ref glsl) => glsl_sources.push((stage, &glsl[..])),
+                        ShaderSource::Raw(_) => break,
+                    }
+                }
+                if glsl_sources.len() == sources.len() {
+                    Some((cache, cache.key(glsl_sources)))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        if let Some((cache, key)) = cache_key {
+            if cache.load(gl, name, key) {
+                return Ok(PendingProgram {
+                    name,
+                    shaders: Vec::new(),
+                    from_cache: true,
+                    cache_key: None,
+                    name_binding_map,
+                });
+            }
+            gl.ProgramParameteri(name, gl::PROGRAM_BINARY_RETRIEVABLE_HINT, gl::TRUE as _);
+        }
+
+        let mut shader_names = Vec::new();
+        for (stage, source) in sources {
+            let shader = match source {
+                ShaderSource::Raw(raw) => Ok(raw),
+                ShaderSource::Glsl(glsl) => self.compile_shader(glsl.as_bytes(), stage),
+            };
+            match shader {
+                Ok(shader_name) => {
+                    gl.AttachShader(name, shader_name);
+                    shader_names.push(shader_name);
+                }
+                Err(err) => {
+                    for shader_name in shader_names {
+                        gl.DeleteShader(shader_name);
+                    }
+                    return Err(pso::CreationError::Shader(err));
+                }
+            }
+        }
+
+        if !share.private_caps.program_interface && share.private_caps.frag_data_location {
+            for i in 0..color_targets {
+                let color_name = format!("Target{}\0", i);
+                gl.BindFragDataLocation(
+                    name,
+                    i as u32,
+                    (&color_name[..]).as_ptr() as *mut gl::types::GLchar,
+                );
+            }
+        }
+
+        gl.LinkProgram(name);
+        info!("\tLinked program {}", name);
+
+        if let Err(err) = share.check() {
+            error!("Error linking program: {:?}", err);
+            for shader_name in shader_names {
+                gl.DetachShader(name, shader_name);
+                gl.DeleteShader(shader_name);
+            }
+            return Err(pso::CreationError::Other);
+        }
+
+        Ok(PendingProgram {
+            name,
+            shaders: shader_names,
+            from_cache: false,
+            cache_key: cache_key.map(|(_, key)| key),
+            name_binding_map,
+        })
+    }
+
+    /// Wait for a program to be linked, returning its uniform bindings.
+    ///
+    /// The program is deleted if linking failed.
+    unsafe fn finish_program(
+        &self,
+        pending: PendingProgram,
+    ) -> Result<Vec<n::UniformBinding>, pso::CreationError> {
+        let gl = &self.share.context;
+        let name = pending.name;
+
+        if !pending.from_cache {
+            let status = get_program_iv(gl, name, gl::LINK_STATUS);
+            let result = if status == 0 {
+                // Compilation errors are only checked now, so that shaders
+                // compile in parallel on drivers supporting it.
+                let log = pending
+                    .shaders
+                    .iter()
+                    .find(|&&shader| get_shader_iv(gl, shader, gl::COMPILE_STATUS) == 0)
+                    .map_or_else(
+                        || get_program_log(gl, name),
+                        |&shader| get_shader_log(gl, shader),
+                    );
+                Err(pso::CreationError::Shader(
+                    d::ShaderError::CompilationFailed(log),
+                ))
+            } else {
+                let log = get_program_log(gl, name);
+                if !log.is_empty() {
+                    warn!("\tLog: {}", log);
+                }
+                Ok(())
+            };
+
+            for shader_name in pending.shaders {
+                gl.DetachShader(name, shader_name);
+                gl.DeleteShader(shader_name);
+            }
+            if let Err(err) = result {
+                gl.DeleteProgram(name);
+                return Err(err);
+            }
+
+            if let (Some(key), Some(ref cache)) =
+                (pending.cache_key, &*self.share.program_cache.borrow())
+            {
+                cache.store(gl, name, key);
+            }
+        }
+
+        Ok(self.resolve_uniform_bindings(name, &pending.name_binding_map))
+    }
+
+    /// Translate the shaders of a graphics pipeline and start linking its
+    /// program, see `link_program`.
+    ///
+    /// The uniform bindings of the returned pipeline are set once the
+    /// program is finished.
+    unsafe fn start_graphics_pipeline(
+        &self,
+        desc: &pso::GraphicsPipelineDesc<B>,
+    ) -> Result<(n::GraphicsPipeline, PendingProgram), pso::CreationError> {
+        let gl = &self.share.context;
+        let subpass = {
+            let subpass = desc.subpass;
+            match subpass.main_pass.subpasses.get(subpass.index) {
+                Some(sp) => sp,
+                None => return Err(pso::CreationError::InvalidSubpass(subpass.index)),
+            }
+        };
+
+        let primitive = match conv::primitive_to_gl_primitive(
+            desc.input_assembler.primitive,
+            self.share.features.contains(c::Features::GEOMETRY_SHADER),
+        ) {
+            Some(primitive) => primitive,
+            None => {
+                error!(
+                    "Primitive {:?} requires geometry shader support",
+                    desc.input_assembler.primitive
+                );
+                return Err(pso::CreationError::Other);
+            }
+        };
+
+        let program = {
+            let name = gl.CreateProgram();
+
+            let shaders = [
+                (pso::Stage::Vertex, Some(&desc.shaders.vertex)),
+                (pso::Stage::Hull, desc.shaders.hull.as_ref()),
+                (pso::Stage::Domain, desc.shaders.domain.as_ref()),
+                (pso::Stage::Geometry, desc.shaders.geometry.as_ref()),
+                (pso::Stage::Fragment, desc.shaders.fragment.as_ref()),
+            ];
+
+            let mut name_binding_map = NameBindingMap::default();
+            let mut sources = Vec::new();
+            for &(stage, point_maybe) in &shaders {
+                let point = match point_maybe {
+                    Some(point) => point,
+                    None => continue,
+                };
+                let subpass = match stage {
+                    pso::Stage::Fragment => Some(&desc.subpass),
+                    _ => None,
                 };
+                let source = self.translate_shader(
+                    point,
+                    subpass,
+                    &mut desc.layout.desc_remap_data.write().unwrap(),
+                    &mut name_binding_map,
+                );
+                match source {
+                    Ok(source) => sources.push((stage, source)),
+                    Err(err) => {
+                        gl.DeleteProgram(name);
+                        return Err(pso::CreationError::Shader(err));
+                    }
+                }
+            }
+
+            match self.link_program(
+                name,
+                sources,
+                subpass.color_attachments.len(),
+                name_binding_map,
+            ) {
+                Ok(program) => program,
+                Err(err) => {
+                    gl.DeleteProgram(name);
+                    return Err(err);
+                }
+            }
+        };
+
+        let patch_size = match desc.input_assembler.primitive {
+            c::Primitive::PatchList(size) => Some(size as _),
+            _ => None,
+        };
+
+        let mut vertex_layout = n::VertexLayout::new();
+        let mut vertex_bindings = Vec::new();
+        for a in &desc.attributes {
+            let vb = desc
+                .vertex_buffers
+                .iter()
+                .find(|vb| vb.binding == a.binding);
+            let vb = match vb {
+                Some(vb) => vb,
+                None => {
+                    error!("No vertex buffer description bound at {}", a.binding);
+                    continue;
+                }
+            };
+            let (size, format, vertex_attrib_fn) =
+                conv::format_to_gl_format(a.element.format).unwrap();
+            vertex_layout.push(n::VertexAttribute {
+                desc: n::AttributeDesc {
+                    location: a.location,
+                    offset: a.element.offset,
+                    size,
+                    format,
+                    vertex_attrib_fn,
+                },
+                stride: vb.stride as _,
+                rate: vb.rate.as_uint() as _,
+            });
+            vertex_bindings.push(a.binding);
+        }
+
+        let pipeline = n::GraphicsPipeline {
+            program: program.name,
+            primitive,
+            patch_size,
+            rasterizer: state::RasterizerState::new(
+                &desc.rasterizer,
+                self.share.info.version.is_embedded,
+            ),
+            depth: state::DepthState::new(
+                &desc.depth_stencil.depth,
+                self.share
+                    .ext
+                    .depth_bounds_test
+                    .map(|_| desc.depth_stencil.depth_bounds),
+            ),
+            stencil: desc.depth_stencil.stencil,
+            cull_face: desc.rasterizer.cull_face,
+            blend_targets: desc
+                .blender
+                .targets
+                .iter()
+                .map(state::BlendSlotState::new)
+                .collect(),
+            blend_color: desc.baked_states.blend_color,
+            depth_bounds: desc.baked_states.depth_bounds.clone(),
+            vertex_layout: self.register_vertex_layout(vertex_layout),
+            vertex_bindings,
+            color_formats: subpass
+                .color_attachments
+                .iter()
+                .map(|&id| desc.subpass.main_pass.attachments[id].format)
+                .collect(),
+            num_sets: desc.layout.num_sets,
+        };
+        Ok((pipeline, program))
+    }
 
-                shader
+    unsafe fn finish_graphics_pipeline(
+        &self,
+        pipeline: n::GraphicsPipeline,
+        program: PendingProgram,
+    ) -> Result<n::GraphicsPipeline, pso::CreationError> {
+        let uniform_bindings = match self.finish_program(program) {
+            Ok(uniform_bindings) => uniform_bindings,
+            Err(err) => {
+                self.release_vertex_layout(pipeline.vertex_layout);
+                return Err(err);
+            }
+        };
+        self.apply_uniform_bindings(pipeline.program, &uniform_bindings);
+        Ok(pipeline)
+    }
+
+    /// Create graphics pipelines ahead of their first use, avoiding hitches
+    /// when they are first drawn with.
+    ///
+    /// The programs of all the pipelines are linked before waiting for any
+    /// of them, so drivers supporting `KHR_parallel_shader_compile` build
+    /// them concurrently on their compiler threads. Each program is then
+    /// bound once, as some drivers defer part of the work until then.
+    ///
+    /// The pipelines are built on the context of the device, blocking the
+    /// calling thread. They can be built on the worker owning the context
+    /// instead with `prewarm_graphics_pipelines_async`.
+    pub unsafe fn prewarm_graphics_pipelines<'a, I>(
+        &self,
+        descs: I,
+    ) -> Vec<Result<n::GraphicsPipeline, pso::CreationError>>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<pso::GraphicsPipelineDesc<'a, B>>,
+    {
+        let pipelines = self.create_graphics_pipelines_batched(descs);
+        let gl = &self.share.context;
+        for pipeline in &pipelines {
+            if let Ok(ref pipeline) = *pipeline {
+                gl.UseProgram(pipeline.program);
+            }
+        }
+        gl.UseProgram(0);
+        pipelines
+    }
+
+    /// Prewarm graphics pipelines on the worker owning the context, without
+    /// blocking the calling thread, see `prewarm_graphics_pipelines`.
+    ///
+    /// Work forwarded to the worker meanwhile, like submissions, is executed
+    /// once the pipelines are built. If the device wasn't opened on a worker,
+    /// or if this is called from the worker, the pipelines are built right
+    /// away.
+    ///
+    /// # Safety
+    ///
+    /// The device, and the shader modules, pipeline layouts and render
+    /// passes referenced by `descs`, have to outlive the build, which is
+    /// over once `PrewarmedPipelines::wait` returns.
+    pub unsafe fn prewarm_graphics_pipelines_async<'a>(
+        &'a self,
+        descs: Vec<pso::GraphicsPipelineDesc<'a, B>>,
+    ) -> PrewarmedPipelines {
+        let (sender, receiver) = mpsc::channel();
+        match self.worker {
+            Some(ref worker) if !worker.is_current() => worker.post_unchecked(move || {
+                let _ = sender.send(self.prewarm_graphics_pipelines(descs));
+            }),
+            _ => {
+                let _ = sender.send(self.prewarm_graphics_pipelines(descs));
             }
         }
+        PrewarmedPipelines {
+            receiver,
+            pipelines: None,
+        }
+    }
+
+    // Start linking the programs of all the pipelines before finishing any.
+    unsafe fn create_graphics_pipelines_batched<'a, I>(
+        &self,
+        descs: I,
+    ) -> Vec<Result<n::GraphicsPipeline, pso::CreationError>>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<pso::GraphicsPipelineDesc<'a, B>>,
+    {
+        let pending = descs
+            .into_iter()
+            .map(|desc| self.start_graphics_pipeline(desc.borrow()))
+            .collect::<Vec<_>>();
+        pending
+            .into_iter()
+            .map(|result| {
+                let (pipeline, program) = result?;
+                self.finish_graphics_pipeline(pipeline, program)
+            })
+            .collect()
     }
 }
 
 pub(crate) unsafe fn set_sampler_info<SetParamFloat, SetParamFloatVec, SetParamInt>(
-    share: &Starc<Share>,
+    share: &Share,
     info: &i::SamplerInfo,
     mut set_param_float: SetParamFloat,
     mut set_param_float_vec: SetParamFloatVec,
@@ -488,7 +2448,11 @@ pub(crate) unsafe fn set_sampler_info<SetParamFloat, SetParamFloatVec, SetParamI
         .features
         .contains(hal::Features::SAMPLER_MIP_LOD_BIAS)
     {
-        set_param_float(gl::TEXTURE_LOD_BIAS, info.lod_bias.into());
+        // Out of range biases are clamped by the driver anyway, but some
+        // implementations raise `INVALID_VALUE` instead.
+        let max_bias = share.limits.max_sampler_lod_bias;
+        let bias: f32 = info.lod_bias.into();
+        set_param_float(gl::TEXTURE_LOD_BIAS, bias.max(-max_bias).min(max_bias));
     }
     if share
         .legacy_features
@@ -498,8 +2462,12 @@ pub(crate) unsafe fn set_sampler_info<SetParamFloat, SetParamFloatVec, SetParamI
         set_param_float_vec(gl::TEXTURE_BORDER_COLOR, &border);
     }
 
-    set_param_float(gl::TEXTURE_MIN_LOD, info.lod_range.start.into());
-    set_param_float(gl::TEXTURE_MAX_LOD, info.lod_range.end.into());
+    if share.private_caps.sampler_lod_range {
+        let min_lod: f32 = info.lod_range.start.into();
+        let max_lod: f32 = info.lod_range.end.into();
+        set_param_float(gl::TEXTURE_MIN_LOD, min_lod);
+        set_param_float(gl::TEXTURE_MAX_LOD, max_lod.max(min_lod));
+    }
 
     match info.comparison {
         None => set_param_int(gl::TEXTURE_COMPARE_MODE, gl::NONE as GLint),
@@ -551,6 +2519,7 @@ impl d::Device<B> for Device {
         Ok(RawCommandPool {
             fbo,
             limits,
+            validation: self.share.validation.get(),
             memory: Arc::new(Mutex::new(memory)),
         })
     }
@@ -576,25 +2545,63 @@ impl d::Device<B> for Device {
         ID: IntoIterator,
         ID::Item: Borrow<pass::SubpassDependency>,
     {
+        let attachments = attachments
+            .into_iter()
+            .map(|attachment| attachment.borrow().clone())
+            .collect::<Vec<_>>();
+        // Input attachments which aren't written by the subpass are bound to
+        // extra draw buffers to be fetched, which requires fragment outputs
+        // with explicit locations.
+        let fetch_inputs = self.share.private_caps.draw_buffers
+            && self.framebuffer_fetch_kind() == Some(FramebufferFetchKind::Ext { legacy: false });
+
         let subpasses = subpasses
             .into_iter()
             .map(|subpass| {
+                let subpass = subpass.borrow();
                 let color_attachments = subpass
-                    .borrow()
                     .colors
                     .iter()
                     .map(|&(index, _)| index)
+                    .collect::<Vec<_>>();
+                let input_attachments = subpass
+                    .inputs
+                    .iter()
+                    .map(|&(index, _)| index)
+                    .collect::<Vec<_>>();
+                let mut fetched_attachments = Vec::new();
+                if fetch_inputs {
+                    for &index in &input_attachments {
+                        let is_color = attachments[index]
+                            .format
+                            .map(|format| format.is_color())
+                            .unwrap_or(false);
+                        if is_color
+                            && !color_attachments.contains(&index)
+                            && !fetched_attachments.contains(&index)
+                        {
+                            fetched_attachments.push(index);
+                        }
+                    }
+                }
+
+                let resolve_attachments = color_attachments
+                    .iter()
+                    .cloned()
+                    .zip(subpass.resolves.iter().map(|&(index, _)| index))
                     .collect();
 
-                n::SubpassDesc { color_attachments }
+                n::SubpassDesc {
+                    color_attachments,
+                    input_attachments,
+                    fetched_attachments,
+                    resolve_attachments,
+                }
             })
             .collect();
 
         Ok(n::RenderPass {
-            attachments: attachments
-                .into_iter()
-                .map(|attachment| attachment.borrow().clone())
-                .collect::<Vec<_>>(),
+            attachments,
             subpasses,
         })
     }
@@ -611,9 +2618,11 @@ impl d::Device<B> for Device {
         IR::Item: Borrow<(pso::ShaderStageFlags, Range<u32>)>,
     {
         let mut drd = n::DescRemapData::new();
+        let mut num_sets = 0;
 
-        layouts.into_iter().enumerate().for_each(|(set, layout)| {
-            layout.borrow().iter().for_each(|binding| {
+        for (set, layout) in layouts.into_iter().enumerate() {
+            num_sets += 1;
+            for binding in layout.borrow().iter() {
                 // DescriptorType -> Descriptor
                 //
                 // Sampler -> Sampler
@@ -637,22 +2646,44 @@ impl d::Device<B> for Device {
                         // We need to figure out combos once we get the shaders, until then we
                         // do nothing
                     }
+                    // Input attachments not read with framebuffer fetch are
+                    // sampled as textures.
+                    InputAttachment => {
+                        drd.insert_missing_binding_into_spare(
+                            n::BindingTypes::Images,
+                            set as _,
+                            binding.binding,
+                        );
+                    }
                     UniformBuffer => {
                         drd.insert_missing_binding_into_spare(
-                            n::BindingTypes::UniformBuffers,
+                            n::BindingTypes::UniformBuffers,
+                            set as _,
+                            binding.binding,
+                        );
+                    }
+                    StorageImage => {
+                        // Reported as unsupported by the limits and
+                        // `DownlevelProperties` without image load/store.
+                        assert!(
+                            self.share.private_caps.image_load_store,
+                            "Storage images are not supported"
+                        );
+                        drd.insert_missing_binding_into_spare(
+                            n::BindingTypes::StorageImages,
                             set as _,
                             binding.binding,
                         );
                     }
-                    StorageImage | UniformTexelBuffer | UniformBufferDynamic
-                    | StorageTexelBuffer | StorageBufferDynamic | StorageBuffer
-                    | InputAttachment => unimplemented!(), // 6
+                    UniformTexelBuffer | UniformBufferDynamic | StorageTexelBuffer
+                    | StorageBufferDynamic | StorageBuffer => unimplemented!(), // 6
                 }
-            })
-        });
+            }
+        }
 
         Ok(n::PipelineLayout {
             desc_remap_data: Arc::new(RwLock::new(drd)),
+            num_sets,
         })
     }
 
@@ -683,134 +2714,20 @@ impl d::Device<B> for Device {
         desc: &pso::GraphicsPipelineDesc<'a, B>,
         _cache: Option<&()>,
     ) -> Result<n::GraphicsPipeline, pso::CreationError> {
-        let gl = &self.share.context;
-        let share = &self.share;
-        let desc = desc.borrow();
-        let subpass = {
-            let subpass = desc.subpass;
-            match subpass.main_pass.subpasses.get(subpass.index) {
-                Some(sp) => sp,
-                None => return Err(pso::CreationError::InvalidSubpass(subpass.index)),
-            }
-        };
-
-        let program = {
-            let name = gl.CreateProgram();
-
-            // Attach shaders to program
-            let shaders = [
-                (pso::Stage::Vertex, Some(&desc.shaders.vertex)),
-                (pso::Stage::Hull, desc.shaders.hull.as_ref()),
-                (pso::Stage::Domain, desc.shaders.domain.as_ref()),
-                (pso::Stage::Geometry, desc.shaders.geometry.as_ref()),
-                (pso::Stage::Fragment, desc.shaders.fragment.as_ref()),
-            ];
-
-            let mut name_binding_map = FastHashMap::<String, pso::DescriptorBinding>::default();
-            let shader_names = &shaders
-                .iter()
-                .filter_map(|&(stage, point_maybe)| {
-                    point_maybe.map(|point| {
-                        let shader_name = self.compile_shader(
-                            point,
-                            stage,
-                            &mut desc.layout.desc_remap_data.write().unwrap(),
-                            &mut name_binding_map,
-                        );
-
-                        gl.AttachShader(name, shader_name);
-
-                        shader_name
-                    })
-                })
-                .collect::<Vec<_>>();
-
-            if !share.private_caps.program_interface && share.private_caps.frag_data_location {
-                for i in 0..subpass.color_attachments.len() {
-                    let color_name = format!("Target{}\0", i);
-                    gl.BindFragDataLocation(
-                        name,
-                        i as u32,
-                        (&color_name[..]).as_ptr() as *mut gl::types::GLchar,
-                    );
-                }
-            }
-
-            gl.LinkProgram(name);
-            info!("\tLinked program {}", name);
-            if let Err(err) = share.check() {
-                panic!("Error linking program: {:?}", err);
-            }
-
-            for shader_name in shader_names {
-                gl.DetachShader(name, *shader_name);
-                gl.DeleteShader(*shader_name);
-            }
-
-            if !self
-                .share
-                .legacy_features
-                .contains(LegacyFeatures::EXPLICIT_LAYOUTS_IN_SHADER)
-            {
-                let gl = &self.share.context;
-                gl.UseProgram(name);
-                for (bname, binding) in name_binding_map.iter() {
-                    let loc = gl.GetUniformLocation(name, bname.as_ptr() as _);
-                    gl.Uniform1i(loc, *binding as _);
-                }
-            }
-
-            let status = get_program_iv(gl, name, gl::LINK_STATUS);
-            let log = get_program_log(gl, name);
-            if status != 0 {
-                if !log.is_empty() {
-                    warn!("\tLog: {}", log);
-                }
-            } else {
-                return Err(pso::CreationError::Shader(
-                    d::ShaderError::CompilationFailed(log),
-                ));
-            }
-
-            name
-        };
-
-        let patch_size = match desc.input_assembler.primitive {
-            c::Primitive::PatchList(size) => Some(size as _),
-            _ => None,
-        };
-
-        let mut vertex_buffers = Vec::new();
-        for vb in &desc.vertex_buffers {
-            while vertex_buffers.len() <= vb.binding as usize {
-                vertex_buffers.push(None);
-            }
-            vertex_buffers[vb.binding as usize] = Some(*vb);
-        }
+        let (pipeline, program) = self.start_graphics_pipeline(desc)?;
+        self.finish_graphics_pipeline(pipeline, program)
+    }
 
-        Ok(n::GraphicsPipeline {
-            program,
-            primitive: conv::primitive_to_gl_primitive(desc.input_assembler.primitive),
-            patch_size,
-            blend_targets: desc.blender.targets.clone(),
-            vertex_buffers,
-            attributes: desc
-                .attributes
-                .iter()
-                .map(|&a| {
-                    let (size, format, vertex_attrib_fn) =
-                        conv::format_to_gl_format(a.element.format).unwrap();
-                    n::AttributeDesc {
-                        location: a.location,
-                        offset: a.element.offset,
-                        binding: a.binding,
-                        size,
-                        format,
-                        vertex_attrib_fn,
-                    }
-                })
-                .collect(),
-        })
+    unsafe fn create_graphics_pipelines<'a, I>(
+        &self,
+        descs: I,
+        _cache: Option<&()>,
+    ) -> Vec<Result<n::GraphicsPipeline, pso::CreationError>>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<pso::GraphicsPipelineDesc<'a, B>>,
+    {
+        self.create_graphics_pipelines_batched(descs)
     }
 
     unsafe fn create_compute_pipeline<'a>(
@@ -819,63 +2736,45 @@ impl d::Device<B> for Device {
         _cache: Option<&()>,
     ) -> Result<n::ComputePipeline, pso::CreationError> {
         let gl = &self.share.context;
-        let share = &self.share;
 
         let program = {
             let name = gl.CreateProgram();
 
-            let mut name_binding_map = FastHashMap::<String, pso::DescriptorBinding>::default();
-            let shader = self.compile_shader(
+            let mut name_binding_map = NameBindingMap::default();
+            let source = match self.translate_shader(
                 &desc.shader,
-                pso::Stage::Compute,
+                None,
                 &mut desc.layout.desc_remap_data.write().unwrap(),
                 &mut name_binding_map,
-            );
-            gl.AttachShader(name, shader);
-
-            gl.LinkProgram(name);
-            info!("\tLinked program {}", name);
-            if let Err(err) = share.check() {
-                panic!("Error linking program: {:?}", err);
-            }
-
-            gl.DetachShader(name, shader);
-            gl.DeleteShader(shader);
-
-            if !self
-                .share
-                .legacy_features
-                .contains(LegacyFeatures::EXPLICIT_LAYOUTS_IN_SHADER)
-            {
-                let gl = &self.share.context;
-                gl.UseProgram(name);
-                for (bname, binding) in name_binding_map.iter() {
-                    let loc = gl.GetUniformLocation(name, bname.as_ptr() as _);
-                    gl.Uniform1i(loc, *binding as _);
+            ) {
+                Ok(source) => source,
+                Err(err) => {
+                    gl.DeleteProgram(name);
+                    return Err(pso::CreationError::Shader(err));
                 }
-            }
+            };
 
-            let status = get_program_iv(gl, name, gl::LINK_STATUS);
-            let log = get_program_log(gl, name);
-            if status != 0 {
-                if !log.is_empty() {
-                    warn!("\tLog: {}", log);
+            let sources = vec![(pso::Stage::Compute, source)];
+            match self.link_program(name, sources, 0, name_binding_map) {
+                Ok(program) => program,
+                Err(err) => {
+                    gl.DeleteProgram(name);
+                    return Err(err);
                 }
-            } else {
-                return Err(pso::CreationError::Other);
             }
-
-            name
         };
 
-        Ok(n::ComputePipeline { program })
+        let name = program.name;
+        let uniform_bindings = self.finish_program(program)?;
+        self.apply_uniform_bindings(name, &uniform_bindings);
+        Ok(n::ComputePipeline { program: name })
     }
 
     unsafe fn create_framebuffer<I>(
         &self,
         pass: &n::RenderPass,
         attachments: I,
-        _extent: i::Extent,
+        extent: i::Extent,
     ) -> Result<n::FrameBuffer, d::OutOfMemory>
     where
         I: IntoIterator,
@@ -886,10 +2785,19 @@ impl d::Device<B> for Device {
         }
 
         let gl = &self.share.context;
-        let target = gl::DRAW_FRAMEBUFFER;
+        let target = if self.share.private_caps.read_draw_framebuffer {
+            gl::DRAW_FRAMEBUFFER
+        } else {
+            gl::FRAMEBUFFER
+        };
+        let dsa = self.share.private_caps.direct_state_access;
         let mut name = 0;
-        gl.GenFramebuffers(1, &mut name);
-        gl.BindFramebuffer(target, name);
+        if dsa {
+            gl.CreateFramebuffers(1, &mut name);
+        } else {
+            gl.GenFramebuffers(1, &mut name);
+            gl.BindFramebuffer(target, name);
+        }
 
         let att_points = [
             gl::COLOR_ATTACHMENT0,
@@ -899,10 +2807,23 @@ impl d::Device<B> for Device {
         ];
 
         let mut attachments_len = 0;
+        let mut resolve_views = Vec::new();
         //TODO: exclude depth/stencil attachments from here
         for (&att_point, view) in att_points.iter().zip(attachments.into_iter()) {
             attachments_len += 1;
-            if self.share.private_caps.framebuffer_texture {
+            let id = attachments_len - 1;
+            if pass.is_resolve_target(id) {
+                // Single-sampled, attached to a framebuffer of its own.
+                resolve_views.push((id, *view.borrow()));
+                // Only used as a resolve target, it would make the
+                // framebuffer incomplete by mixing sample counts.
+                if !pass.is_subpass_attachment(id) {
+                    continue;
+                }
+            }
+            if dsa {
+                Self::bind_target_named(gl, name, att_point, view.borrow());
+            } else if self.share.private_caps.framebuffer_texture {
                 Self::bind_target(gl, target, att_point, view.borrow());
             } else {
                 Self::bind_target_compat(gl, target, att_point, view.borrow());
@@ -912,15 +2833,52 @@ impl d::Device<B> for Device {
         // attachments_len actually equals min(attachments.len(), att_points.len()) until the next assert
 
         assert!(pass.attachments.len() <= att_points.len());
-        gl.DrawBuffers(attachments_len as _, att_points.as_ptr());
-        let _status = gl.CheckFramebufferStatus(target); //TODO: check status
-        gl.BindFramebuffer(target, 0);
+        if dsa {
+            gl.NamedFramebufferDrawBuffers(name, attachments_len as _, att_points.as_ptr());
+            let _status = gl.CheckNamedFramebufferStatus(name, target); //TODO: check status
+        } else {
+            if self.share.private_caps.draw_buffers {
+                gl.DrawBuffers(attachments_len as _, att_points.as_ptr());
+            }
+            let _status = gl.CheckFramebufferStatus(target); //TODO: check status
+            gl.BindFramebuffer(target, 0);
+        }
+
+        let mut resolve_framebuffers = Vec::new();
+        for (id, view) in resolve_views {
+            let mut resolve_name = 0;
+            if dsa {
+                gl.CreateFramebuffers(1, &mut resolve_name);
+                Self::bind_target_named(gl, resolve_name, gl::COLOR_ATTACHMENT0, &view);
+            } else {
+                gl.GenFramebuffers(1, &mut resolve_name);
+                gl.BindFramebuffer(target, resolve_name);
+                if self.share.private_caps.framebuffer_texture {
+                    Self::bind_target(gl, target, gl::COLOR_ATTACHMENT0, &view);
+                } else {
+                    Self::bind_target_compat(gl, target, gl::COLOR_ATTACHMENT0, &view);
+                }
+                gl.BindFramebuffer(target, 0);
+            }
+            resolve_framebuffers.push((id, resolve_name));
+        }
 
         if let Err(err) = self.share.check() {
-            //TODO: attachments have been consumed
-            panic!(
-                "Error creating FBO: {:?} for {:?}", /* with attachments {:?}"*/
-                err, pass /*, attachments*/
+            error!("Error creating FBO: {:?} for {:?}", err, pass);
+            gl.DeleteFramebuffers(1, &name);
+            for &(_, resolve_name) in &resolve_framebuffers {
+                gl.DeleteFramebuffers(1, &resolve_name);
+            }
+            return Err(d::OutOfMemory::OutOfDeviceMemory);
+        }
+
+        if !resolve_framebuffers.is_empty() {
+            self.share.resolve_targets.borrow_mut().insert(
+                name,
+                n::ResolveTargets {
+                    extent,
+                    framebuffers: resolve_framebuffers,
+                },
             );
         }
 
@@ -946,6 +2904,10 @@ impl d::Device<B> for Device {
             return Ok(n::FatSampler::Info(info));
         }
 
+        if let Some(name) = self.share.samplers.borrow_mut().acquire(&info) {
+            return Ok(n::FatSampler::Sampler(name));
+        }
+
         let gl = &self.share.context;
         let mut name = 0 as n::Sampler;
 
@@ -963,6 +2925,7 @@ impl d::Device<B> for Device {
                 d::OutOfMemory::OutOfHostMemory,
             ))
         } else {
+            self.share.samplers.borrow_mut().insert(info, name);
             Ok(n::FatSampler::Sampler(name))
         }
     }
@@ -972,27 +2935,53 @@ impl d::Device<B> for Device {
         size: u64,
         usage: buffer::Usage,
     ) -> Result<n::Buffer, buffer::CreationError> {
-        if !self
-            .share
-            .legacy_features
-            .contains(LegacyFeatures::CONSTANT_BUFFER)
-            && usage.contains(buffer::Usage::UNIFORM)
-        {
-            return Err(buffer::CreationError::UnsupportedUsage { usage });
-        }
+        // Emulated uniform buffers are read from a host copy, the GL buffer
+        // only serves the other usages.
+        let emulate_uniform = usage.contains(buffer::Usage::UNIFORM)
+            && !self
+                .share
+                .legacy_features
+                .contains(LegacyFeatures::CONSTANT_BUFFER);
+        let gl_usage = if emulate_uniform {
+            usage - buffer::Usage::UNIFORM
+        } else {
+            usage
+        };
 
         let target = if self.share.private_caps.buffer_role_change {
             gl::ARRAY_BUFFER
         } else {
-            match conv::buffer_usage_to_gl_target(usage) {
+            match conv::buffer_usage_to_gl_target(gl_usage) {
                 Some(target) => target,
+                None if emulate_uniform => gl::ARRAY_BUFFER,
                 None => return Err(buffer::CreationError::UnsupportedUsage { usage }),
             }
         };
 
         let gl = &self.share.context;
         let mut name = 0;
-        gl.GenBuffers(1, &mut name);
+        if self.share.private_caps.direct_state_access {
+            gl.CreateBuffers(1, &mut name);
+        } else {
+            gl.GenBuffers(1, &mut name);
+        }
+        // Without pixel buffers or buffer copies, the context can't read
+        // from transfer sources, which are kept on the host instead.
+        let transfer_on_host = usage.contains(buffer::Usage::TRANSFER_SRC)
+            && !(self.share.private_caps.pixel_buffer
+                && self
+                    .share
+                    .legacy_features
+                    .contains(LegacyFeatures::COPY_BUFFER));
+        if emulate_uniform || transfer_on_host {
+            self.share
+                .buffer_shadows
+                .borrow_mut()
+                .insert(name, vec![0; size as usize]);
+        }
+        // Writes through a mapping wouldn't reach the host copy of emulated
+        // uniform buffers, so they can't be bound to CPU visible memory.
+        let type_mask = if emulate_uniform { 0x1 } else { 0x7 };
 
         Ok(n::Buffer {
             raw: name,
@@ -1000,8 +2989,9 @@ impl d::Device<B> for Device {
             requirements: memory::Requirements {
                 size,
                 alignment: 1, // TODO: do we need specific alignment for any use-case?
-                type_mask: 0x7,
+                type_mask,
             },
+            owned: true,
         })
     }
 
@@ -1030,10 +3020,18 @@ impl d::Device<B> for Device {
         if self.share.private_caps.buffer_storage {
             //TODO: gl::DYNAMIC_STORAGE_BIT | gl::MAP_PERSISTENT_BIT
             let flags = memory.map_flags();
-            //TODO: use *Named calls to avoid binding
-            gl.BindBuffer(target, buffer.raw);
-            gl.BufferStorage(target, buffer.requirements.size as _, ptr::null(), flags);
-            gl.BindBuffer(target, 0);
+            if self.share.private_caps.direct_state_access {
+                gl.NamedBufferStorage(
+                    buffer.raw,
+                    buffer.requirements.size as _,
+                    ptr::null(),
+                    flags,
+                );
+            } else {
+                gl.BindBuffer(target, buffer.raw);
+                gl.BufferStorage(target, buffer.requirements.size as _, ptr::null(), flags);
+                gl.BindBuffer(target, 0);
+            }
         } else {
             let flags = if cpu_can_read && cpu_can_write {
                 gl::DYNAMIC_DRAW
@@ -1044,16 +3042,26 @@ impl d::Device<B> for Device {
             } else {
                 gl::STATIC_DRAW
             };
-            gl.BindBuffer(target, buffer.raw);
-            gl.BufferData(target, buffer.requirements.size as _, ptr::null(), flags);
-            gl.BindBuffer(target, 0);
+            if self.share.private_caps.direct_state_access {
+                gl.NamedBufferData(
+                    buffer.raw,
+                    buffer.requirements.size as _,
+                    ptr::null(),
+                    flags,
+                );
+            } else {
+                gl.BindBuffer(target, buffer.raw);
+                gl.BufferData(target, buffer.requirements.size as _, ptr::null(), flags);
+                gl.BindBuffer(target, 0);
+            }
         }
 
         if let Err(err) = self.share.check() {
-            panic!(
+            error!(
                 "Error {:?} initializing buffer {:?}, memory {:?}",
                 err, buffer, memory.properties
             );
+            return Err(d::BindError::OutOfMemory(d::OutOfMemory::OutOfDeviceMemory));
         }
 
         Ok(())
@@ -1077,7 +3085,9 @@ impl d::Device<B> for Device {
         let offset = *range.start().unwrap_or(&0);
         let size = *range.end().unwrap_or(&memory.size) - offset;
 
-        let ptr = {
+        let ptr = if self.share.private_caps.direct_state_access {
+            gl.MapNamedBufferRange(buffer, offset as _, size as _, access) as *mut _
+        } else {
             gl.BindBuffer(target, buffer);
             let ptr = gl.MapBufferRange(target, offset as _, size as _, access);
             gl.BindBuffer(target, 0);
@@ -1085,7 +3095,8 @@ impl d::Device<B> for Device {
         };
 
         if let Err(err) = self.share.check() {
-            panic!("Error mapping memory: {:?} for memory {:?}", err, memory);
+            error!("Error mapping memory: {:?} for memory {:?}", err, memory);
+            return Err(mapping::Error::MappingFailed);
         }
 
         Ok(ptr)
@@ -1099,12 +3110,16 @@ impl d::Device<B> for Device {
         };
         let target = gl::PIXEL_PACK_BUFFER;
 
-        gl.BindBuffer(target, buffer);
-        gl.UnmapBuffer(target);
-        gl.BindBuffer(target, 0);
+        if self.share.private_caps.direct_state_access {
+            gl.UnmapNamedBuffer(buffer);
+        } else {
+            gl.BindBuffer(target, buffer);
+            gl.UnmapBuffer(target);
+            gl.BindBuffer(target, 0);
+        }
 
         if let Err(err) = self.share.check() {
-            panic!("Error unmapping memory: {:?} for memory {:?}", err, memory);
+            error!("Error unmapping memory: {:?} for memory {:?}", err, memory);
         }
     }
 
@@ -1150,22 +3165,57 @@ impl d::Device<B> for Device {
     ) -> Result<n::Image, i::CreationError> {
         let gl = &self.share.context;
 
-        let (int_format, iformat, itype) = match format {
-            Format::Rgba8Unorm => (gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE),
-            Format::Rgba8Srgb => (gl::SRGB8_ALPHA8, gl::RGBA, gl::UNSIGNED_BYTE),
-            _ => unimplemented!(),
+        let (int_format, iformat, itype) = match conv::format_to_gl_texture_format(format) {
+            Some(formats) => formats,
+            None => return Err(i::CreationError::Format(format)),
+        };
+        let is_texture = num_levels > 1
+            || usage.contains(i::Usage::STORAGE)
+            || usage.contains(i::Usage::SAMPLED);
+        // Multisampled textures would need a binding target of their own,
+        // only renderbuffers can be multisampled.
+        let samples = kind.num_samples();
+        if samples > 1
+            && (is_texture || self.share.limits.framebuffer_color_samples_count & samples == 0)
+        {
+            return Err(i::CreationError::Samples(samples));
+        }
+        // ES 2.0 only knows unsized texture formats, the format of the data
+        // doubling as the internal one, and relies on extensions for the
+        // 8-bit and sRGB renderbuffer formats.
+        let (tex_format, iformat) = if self.share.private_caps.sized_internal_formats {
+            (int_format, iformat)
+        } else {
+            let info = &self.share.info;
+            match format {
+                Format::Rgba8Srgb if !info.is_extension_supported("GL_EXT_sRGB") => {
+                    return Err(i::CreationError::Format(format));
+                }
+                Format::Rgba8Srgb => (gl::SRGB_ALPHA, gl::SRGB_ALPHA),
+                _ if iformat != gl::RGBA || itype != gl::UNSIGNED_BYTE => {
+                    return Err(i::CreationError::Format(format));
+                }
+                _ if !is_texture
+                    && !info.is_extension_supported("GL_OES_rgb8_rgba8")
+                    && !info.is_extension_supported("GL_ARM_rgba8") =>
+                {
+                    return Err(i::CreationError::Format(format));
+                }
+                _ => (iformat, iformat),
+            }
         };
 
         let channel = format.base_format().1;
 
-        let image = if num_levels > 1
-            || usage.contains(i::Usage::STORAGE)
-            || usage.contains(i::Usage::SAMPLED)
-        {
+        let image = if is_texture {
             let mut name = 0;
-            gl.GenTextures(1, &mut name);
             match kind {
+                i::Kind::D2(w, h, 1, 1) if self.share.private_caps.direct_state_access => {
+                    gl.CreateTextures(gl::TEXTURE_2D, 1, &mut name);
+                    gl.TextureStorage2D(name, num_levels as _, int_format, w as _, h as _);
+                }
                 i::Kind::D2(w, h, 1, 1) => {
+                    gl.GenTextures(1, &mut name);
                     gl.BindTexture(gl::TEXTURE_2D, name);
                     if self.share.private_caps.image_storage {
                         gl.TexStorage2D(
@@ -1187,7 +3237,7 @@ impl d::Device<B> for Device {
                             gl.TexImage2D(
                                 gl::TEXTURE_2D,
                                 i as _,
-                                int_format as _,
+                                tex_format as _,
                                 w as _,
                                 h as _,
                                 0,
@@ -1205,12 +3255,37 @@ impl d::Device<B> for Device {
             n::ImageKind::Texture(name)
         } else {
             let mut name = 0;
-            gl.GenRenderbuffers(1, &mut name);
             match kind {
+                i::Kind::D2(w, h, 1, 1) if self.share.private_caps.direct_state_access => {
+                    gl.CreateRenderbuffers(1, &mut name);
+                    gl.NamedRenderbufferStorage(name, int_format, w as _, h as _);
+                }
                 i::Kind::D2(w, h, 1, 1) => {
+                    gl.GenRenderbuffers(1, &mut name);
                     gl.BindRenderbuffer(gl::RENDERBUFFER, name);
                     gl.RenderbufferStorage(gl::RENDERBUFFER, int_format, w as _, h as _);
                 }
+                i::Kind::D2(w, h, 1, samples) if self.share.private_caps.direct_state_access => {
+                    gl.CreateRenderbuffers(1, &mut name);
+                    gl.NamedRenderbufferStorageMultisample(
+                        name,
+                        samples as _,
+                        int_format,
+                        w as _,
+                        h as _,
+                    );
+                }
+                i::Kind::D2(w, h, 1, samples) => {
+                    gl.GenRenderbuffers(1, &mut name);
+                    gl.BindRenderbuffer(gl::RENDERBUFFER, name);
+                    gl.RenderbufferStorageMultisample(
+                        gl::RENDERBUFFER,
+                        samples as _,
+                        int_format,
+                        w as _,
+                        h as _,
+                    );
+                }
                 _ => unimplemented!(),
             };
             n::ImageKind::Surface(name)
@@ -1219,23 +3294,34 @@ impl d::Device<B> for Device {
         let surface_desc = format.base_format().0.desc();
         let bytes_per_texel = surface_desc.bits / 8;
         let ext = kind.extent();
-        let size = (ext.width * ext.height * ext.depth) as u64 * bytes_per_texel as u64;
+        let size =
+            (ext.width * ext.height * ext.depth) as u64 * bytes_per_texel as u64 * samples as u64;
 
         if let Err(err) = self.share.check() {
-            panic!(
+            error!(
                 "Error creating image: {:?} for kind {:?} of {:?}",
                 err, kind, format
             );
+            match image {
+                n::ImageKind::Texture(name) => gl.DeleteTextures(1, &name),
+                n::ImageKind::Surface(name) => gl.DeleteRenderbuffers(1, &name),
+            }
+            return Err(i::CreationError::OutOfMemory(
+                d::OutOfMemory::OutOfDeviceMemory,
+            ));
         }
 
         Ok(n::Image {
             kind: image,
+            dimensions: kind,
             channel,
+            format,
             requirements: memory::Requirements {
                 size,
                 alignment: 1,
                 type_mask: 0x7,
             },
+            owned: true,
         })
     }
 
@@ -1243,12 +3329,43 @@ impl d::Device<B> for Device {
         unbound.requirements
     }
 
+    /// Footprint of a subresource in a buffer holding the whole image, as
+    /// copied to and from buffers without `buffer_width` and
+    /// `buffer_height`.
+    ///
+    /// Rows are tightly packed, layers follow each other and levels are
+    /// stored in order, each with all of its layers.
     unsafe fn get_image_subresource_footprint(
         &self,
-        _image: &n::Image,
-        _sub: i::Subresource,
+        image: &n::Image,
+        sub: i::Subresource,
     ) -> i::SubresourceFootprint {
-        unimplemented!()
+        let desc = image.format.surface_desc();
+        let (block_width, block_height) = (desc.dim.0 as u32, desc.dim.1 as u32);
+        let bytes_per_block = (desc.bits / 8) as buffer::Offset;
+        let layers = image.dimensions.num_layers() as buffer::Offset;
+        let pitches = |level| {
+            let extent = image.dimensions.level_extent(level);
+            // Levels are never smaller than a texel.
+            let blocks_x = (extent.width - 1) / block_width + 1;
+            let blocks_y = (extent.height - 1) / block_height + 1;
+            let row_pitch = blocks_x as buffer::Offset * bytes_per_block;
+            let depth_pitch = blocks_y as buffer::Offset * row_pitch;
+            let array_pitch = depth_pitch * extent.depth as buffer::Offset;
+            (row_pitch, depth_pitch, array_pitch)
+        };
+
+        let level_offset = (0..sub.level)
+            .map(|level| pitches(level).2 * layers)
+            .sum::<buffer::Offset>();
+        let (row_pitch, depth_pitch, array_pitch) = pitches(sub.level);
+        let offset = level_offset + sub.layer as buffer::Offset * array_pitch;
+        i::SubresourceFootprint {
+            slice: offset..offset + array_pitch,
+            row_pitch,
+            array_pitch,
+            depth_pitch,
+        }
     }
 
     unsafe fn bind_image_memory(
@@ -1264,7 +3381,7 @@ impl d::Device<B> for Device {
         &self,
         image: &n::Image,
         _kind: i::ViewKind,
-        _format: Format,
+        format: Format,
         swizzle: Swizzle,
         range: i::SubresourceRange,
     ) -> Result<n::ImageView, i::ViewError> {
@@ -1289,12 +3406,13 @@ impl d::Device<B> for Device {
             n::ImageKind::Texture(texture) => {
                 //TODO: check that `level` exists
                 if range.layers.start == 0 {
-                    Ok(n::ImageView::Texture(texture, level))
+                    Ok(n::ImageView::Texture(texture, level, format))
                 } else if range.layers.start + 1 == range.layers.end {
                     Ok(n::ImageView::TextureLayer(
                         texture,
                         level,
                         range.layers.start,
+                        format,
                     ))
                 } else {
                     Err(i::ViewError::Layer(i::LayerError::OutOfBounds(
@@ -1307,15 +3425,20 @@ impl d::Device<B> for Device {
 
     unsafe fn create_descriptor_pool<I>(
         &self,
-        _: usize,
-        _: I,
-        _: pso::DescriptorPoolCreateFlags,
+        max_sets: usize,
+        descriptor_ranges: I,
+        flags: pso::DescriptorPoolCreateFlags,
     ) -> Result<n::DescriptorPool, d::OutOfMemory>
     where
         I: IntoIterator,
         I::Item: Borrow<pso::DescriptorRangeDesc>,
     {
-        Ok(n::DescriptorPool {})
+        let mut capacity = n::DescriptorCounts::default();
+        for range in descriptor_ranges {
+            let range = range.borrow();
+            capacity.add(range.ty, range.count);
+        }
+        Ok(n::DescriptorPool::new(max_sets, capacity, flags))
     }
 
     unsafe fn create_descriptor_set_layout<I, J>(
@@ -1339,61 +3462,84 @@ impl d::Device<B> for Device {
         J: IntoIterator,
         J::Item: Borrow<pso::Descriptor<'a, B>>,
     {
-        for mut write in writes {
-            let set = &mut write.set;
+        for write in writes {
+            let set = write.set;
             let mut bindings = set.bindings.lock().unwrap();
-            let binding = write.binding;
-            let mut offset = write.array_offset as _;
+            let positions = descriptor_positions(&set.layout, write.binding, write.array_offset);
 
-            for descriptor in write.descriptors {
+            for ((binding, element), descriptor) in positions.zip(write.descriptors) {
+                // Each binding is bound to a single unit, which only
+                // the first array element can occupy.
+                if element != 0 {
+                    continue;
+                }
+                let ty = set
+                    .layout
+                    .iter()
+                    .find(|b| b.binding == binding)
+                    .map(|b| b.ty);
                 match descriptor.borrow() {
                     pso::Descriptor::Buffer(buffer, ref range) => {
                         let start = range.start.unwrap_or(0);
                         let end = range.end.unwrap_or(buffer.requirements.size);
-                        let size = (end - start) as _;
-
-                        bindings.push(n::DescSetBindings::Buffer {
-                            ty: n::BindingTypes::UniformBuffers,
+                        bindings.write_uniform_buffer(
                             binding,
-                            buffer: buffer.raw,
-                            offset,
-                            size,
-                        });
-
-                        offset += size;
+                            n::BufferBinding {
+                                buffer: buffer.raw,
+                                offset: start as _,
+                                size: (end - start) as _,
+                            },
+                        );
                     }
                     pso::Descriptor::CombinedImageSampler(view, _layout, sampler) => {
                         match view {
-                            n::ImageView::Texture(tex, _)
-                            | n::ImageView::TextureLayer(tex, _, _) => {
-                                bindings.push(n::DescSetBindings::Texture(binding, *tex))
+                            n::ImageView::Texture(tex, _, _)
+                            | n::ImageView::TextureLayer(tex, _, _, _) => {
+                                bindings.write_texture(binding, *tex)
                             }
                             n::ImageView::Surface(_) => unimplemented!(),
                         }
-                        match sampler {
-                            n::FatSampler::Sampler(sampler) => {
-                                bindings.push(n::DescSetBindings::Sampler(binding, *sampler))
+                        bindings.write_sampler(binding, sampler);
+                    }
+                    pso::Descriptor::Image(view, _layout)
+                        if ty == Some(pso::DescriptorType::StorageImage) =>
+                    {
+                        let (texture, level, layer, format) = match **view {
+                            n::ImageView::Texture(tex, level, format) => (tex, level, None, format),
+                            n::ImageView::TextureLayer(tex, level, layer, format) => {
+                                (tex, level, Some(layer), format)
                             }
-                            n::FatSampler::Info(info) => bindings
-                                .push(n::DescSetBindings::SamplerInfo(binding, info.clone())),
-                        }
+                            n::ImageView::Surface(_) => panic!(
+                                "Texture was created with only render target usage which is invalid."
+                            ),
+                        };
+                        let format = match conv::format_to_gl_image_format(format) {
+                            Some(format) => format,
+                            None => {
+                                error!("Format {:?} can't be used for storage images", format);
+                                continue;
+                            }
+                        };
+                        bindings.write_storage_image(
+                            binding,
+                            n::StorageImageBinding {
+                                texture,
+                                level,
+                                layer,
+                                format,
+                            },
+                        )
                     }
                     pso::Descriptor::Image(view, _layout) => match view {
-                        n::ImageView::Texture(tex, _) | n::ImageView::TextureLayer(tex, _, _) => {
-                            bindings.push(n::DescSetBindings::Texture(binding, *tex))
+                        n::ImageView::Texture(tex, _, _)
+                        | n::ImageView::TextureLayer(tex, _, _, _) => {
+                            bindings.write_texture(binding, *tex)
                         }
                         n::ImageView::Surface(_) => panic!(
                             "Texture was created with only render target usage which is invalid."
                         ),
                     },
-                    pso::Descriptor::Sampler(sampler) => match sampler {
-                        n::FatSampler::Sampler(sampler) => {
-                            bindings.push(n::DescSetBindings::Sampler(binding, *sampler))
-                        }
-                        n::FatSampler::Info(info) => {
-                            bindings.push(n::DescSetBindings::SamplerInfo(binding, info.clone()))
-                        }
-                    },
+                    pso::Descriptor::Sampler(sampler) => bindings.write_sampler(binding, sampler),
                     pso::Descriptor::UniformTexelBuffer(_view) => unimplemented!(),
                     pso::Descriptor::StorageTexelBuffer(_view) => unimplemented!(),
                 }
@@ -1406,23 +3552,54 @@ impl d::Device<B> for Device {
         I: IntoIterator,
         I::Item: Borrow<pso::DescriptorSetCopy<'a, B>>,
     {
-        for _copy in copies {
-            unimplemented!()
+        for copy in copies {
+            let copy = copy.borrow();
+            let positions = descriptor_positions(
+                &copy.src_set.layout,
+                copy.src_binding,
+                copy.src_array_offset,
+            )
+            .zip(descriptor_positions(
+                &copy.dst_set.layout,
+                copy.dst_binding,
+                copy.dst_array_offset,
+            ))
+            .take(copy.count);
+
+            let same_set = Arc::ptr_eq(&copy.src_set.bindings, &copy.dst_set.bindings);
+            let (src, mut dst);
+            if same_set {
+                dst = copy.dst_set.bindings.lock().unwrap();
+                src = dst.clone();
+            } else {
+                src = copy.src_set.bindings.lock().unwrap().clone();
+                dst = copy.dst_set.bindings.lock().unwrap();
+            }
+            for ((src_binding, src_element), (dst_binding, dst_element)) in positions {
+                // Only the first array element of a binding is tracked, any
+                // other source element leaves the destination unknown.
+                match (src_element, dst_element) {
+                    (0, 0) => dst.copy_binding(&src, src_binding, dst_binding),
+                    (_, 0) => dst.clear_binding(dst_binding),
+                    _ => {}
+                }
+            }
         }
     }
 
     fn create_semaphore(&self) -> Result<n::Semaphore, d::OutOfMemory> {
-        Ok(n::Semaphore)
+        Ok(n::Semaphore {
+            sync: Mutex::new(None),
+            external: None,
+        })
     }
 
     fn create_fence(&self, signalled: bool) -> Result<n::Fence, d::OutOfMemory> {
-        let sync = if signalled && self.share.private_caps.sync {
-            let gl = &self.share.context;
-            unsafe { gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0) }
+        Ok(n::Fence::new(if signalled {
+            n::FenceState::Signalled
         } else {
-            ptr::null()
-        };
-        Ok(n::Fence::new(sync))
+            n::FenceState::Idle
+        }))
     }
 
     unsafe fn reset_fences<I>(&self, fences: I) -> Result<(), d::OutOfMemory>
@@ -1433,13 +3610,10 @@ impl d::Device<B> for Device {
         let gl = &self.share.context;
         for fence in fences {
             let fence = fence.borrow();
-            let sync = fence.0.get();
-            if !sync.is_null() {
-                if self.share.private_caps.sync && gl.IsSync(sync) == gl::TRUE {
-                    gl.DeleteSync(sync);
-                }
+            if let n::FenceState::Pending(sync) = fence.0.get() {
+                gl.DeleteSync(sync);
             }
-            fence.0.set(ptr::null())
+            fence.0.set(n::FenceState::Idle);
         }
         Ok(())
     }
@@ -1449,8 +3623,8 @@ impl d::Device<B> for Device {
         fence: &n::Fence,
         timeout_ns: u64,
     ) -> Result<bool, d::OomOrDeviceLost> {
-        if !self.share.private_caps.sync {
-            return Ok(true);
+        if self.share.is_lost() {
+            return Err(d::OomOrDeviceLost::DeviceLost(d::DeviceLost));
         }
         match wait_fence(fence, &self.share, timeout_ns) {
             gl::TIMEOUT_EXPIRED => Ok(false),
@@ -1464,8 +3638,86 @@ impl d::Device<B> for Device {
         }
     }
 
-    unsafe fn get_fence_status(&self, _: &n::Fence) -> Result<bool, d::DeviceLost> {
-        unimplemented!()
+    unsafe fn wait_for_fences<I>(
+        &self,
+        fences: I,
+        wait: d::WaitFor,
+        timeout_ns: u64,
+    ) -> Result<bool, d::OomOrDeviceLost>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<n::Fence>,
+    {
+        if self.share.is_lost() {
+            return Err(d::OomOrDeviceLost::DeviceLost(d::DeviceLost));
+        }
+        // Make sure all the sync objects were sent to the GPU before blocking,
+        // otherwise none of the fences might ever get signalled.
+        self.share.context.Flush();
+
+        let start = Instant::now();
+        let remaining_ns = || {
+            let elapsed = start.elapsed();
+            let elapsed_ns = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+            timeout_ns.saturating_sub(elapsed_ns)
+        };
+
+        match wait {
+            d::WaitFor::All => {
+                for fence in fences {
+                    if !self.wait_for_fence(fence.borrow(), remaining_ns())? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            d::WaitFor::Any => {
+                let fences: Vec<_> = fences.into_iter().collect();
+                loop {
+                    let mut pending = None;
+                    for fence in &fences {
+                        let fence = fence.borrow();
+                        if self.wait_for_fence(fence, 0)? {
+                            return Ok(true);
+                        }
+                        if let n::FenceState::Pending(_) = fence.0.get() {
+                            pending = pending.or(Some(fence));
+                        }
+                    }
+                    let remaining = remaining_ns();
+                    if remaining == 0 {
+                        return Ok(false);
+                    }
+                    // Block on one of the pending fences for a short while instead of
+                    // spinning, so we still notice if any other fence gets signalled.
+                    match pending {
+                        Some(fence) => {
+                            if self.wait_for_fence(fence, remaining.min(ANY_FENCE_SLICE_NS))? {
+                                return Ok(true);
+                            }
+                        }
+                        // None of the fences were submitted, so they can't be signalled.
+                        None => return Ok(false),
+                    }
+                }
+            }
+        }
+    }
+
+    unsafe fn get_fence_status(&self, fence: &n::Fence) -> Result<bool, d::DeviceLost> {
+        if self.share.is_lost() {
+            return Err(d::DeviceLost);
+        }
+        match wait_fence(fence, &self.share, 0) {
+            gl::ALREADY_SIGNALED | gl::CONDITION_SATISFIED => Ok(true),
+            gl::WAIT_FAILED => {
+                if let Err(err) = self.share.check() {
+                    error!("Error when querying fence status: {:?}", err);
+                }
+                Ok(false)
+            }
+            _ => Ok(false),
+        }
     }
 
     unsafe fn free_memory(&self, _memory: n::Memory) {
@@ -1508,30 +3760,58 @@ impl d::Device<B> for Device {
     }
 
     unsafe fn destroy_graphics_pipeline(&self, pipeline: n::GraphicsPipeline) {
+        self.share
+            .uniform_block_members
+            .borrow_mut()
+            .remove(&pipeline.program);
         self.share.context.DeleteProgram(pipeline.program);
+        self.release_vertex_layout(pipeline.vertex_layout);
     }
 
     unsafe fn destroy_compute_pipeline(&self, pipeline: n::ComputePipeline) {
+        self.share
+            .uniform_block_members
+            .borrow_mut()
+            .remove(&pipeline.program);
         self.share.context.DeleteProgram(pipeline.program);
     }
 
     unsafe fn destroy_framebuffer(&self, frame_buffer: n::FrameBuffer) {
         let gl = &self.share.context;
         gl.DeleteFramebuffers(1, &frame_buffer);
+        let mut resolve_targets = self.share.resolve_targets.borrow_mut();
+        if let Some(targets) = resolve_targets.remove(&frame_buffer) {
+            for (_, name) in targets.framebuffers {
+                gl.DeleteFramebuffers(1, &name);
+            }
+        }
     }
 
     unsafe fn destroy_buffer(&self, buffer: n::Buffer) {
-        self.share.context.DeleteBuffers(1, &buffer.raw);
+        self.share.buffer_shadows.borrow_mut().remove(&buffer.raw);
+        if buffer.owned {
+            self.share.context.DeleteBuffers(1, &buffer.raw);
+        }
+        // The vertex arrays keep pointing to the buffer, whose name may be
+        // reused by another one.
+        let epoch = &self.share.vertex_array_epoch;
+        epoch.set(epoch.get() + 1);
     }
     unsafe fn destroy_buffer_view(&self, _: n::BufferView) {
         // Nothing to do
     }
 
     unsafe fn destroy_image(&self, image: n::Image) {
+        if !image.owned {
+            return;
+        }
         let gl = &self.share.context;
         match image.kind {
             n::ImageKind::Surface(rb) => gl.DeleteRenderbuffers(1, &rb),
-            n::ImageKind::Texture(t) => gl.DeleteTextures(1, &t),
+            n::ImageKind::Texture(t) => {
+                self.release_bindless_handles(|handle| handle.texture == t);
+                gl.DeleteTextures(1, &t)
+            }
         }
     }
 
@@ -1542,7 +3822,12 @@ impl d::Device<B> for Device {
     unsafe fn destroy_sampler(&self, sampler: n::FatSampler) {
         let gl = &self.share.context;
         match sampler {
-            n::FatSampler::Sampler(s) => gl.DeleteSamplers(1, &s),
+            n::FatSampler::Sampler(s) => {
+                if self.share.samplers.borrow_mut().release(s) {
+                    self.release_bindless_handles(|handle| handle.sampler == Some(s));
+                    gl.DeleteSamplers(1, &s);
+                }
+            }
             _ => (),
         }
     }
@@ -1556,15 +3841,18 @@ impl d::Device<B> for Device {
     }
 
     unsafe fn destroy_fence(&self, fence: n::Fence) {
-        let gl = &self.share.context;
-        let sync = fence.0.get();
-        if self.share.private_caps.sync && gl.IsSync(sync) == gl::TRUE {
-            gl.DeleteSync(sync);
+        if let n::FenceState::Pending(sync) = fence.0.get() {
+            self.share.context.DeleteSync(sync);
         }
     }
 
-    unsafe fn destroy_semaphore(&self, _: n::Semaphore) {
-        // Nothing to do
+    unsafe fn destroy_semaphore(&self, semaphore: n::Semaphore) {
+        if let Some(sync) = semaphore.sync.into_inner().unwrap() {
+            self.share.context.DeleteSync(sync);
+        }
+        if let Some(external) = semaphore.external {
+            (self.share.ext.semaphore.unwrap().delete_semaphores)(1, &external);
+        }
     }
 
     unsafe fn create_swapchain(
@@ -1581,26 +3869,34 @@ impl d::Device<B> for Device {
     }
 
     fn wait_idle(&self) -> Result<(), error::HostExecutionError> {
-        unsafe {
-            self.share.context.Finish();
-        }
-        Ok(())
+        self.share.wait_idle()
     }
 }
 
+/// Wait for the fence to be signalled, at most `timeout_ns` nanoseconds.
+///
+/// A timeout of zero only polls the status without blocking. The sync object
+/// is released as soon as it is known to be signalled, so subsequent queries
+/// don't need to call into the driver.
 pub(crate) fn wait_fence(fence: &n::Fence, share: &Starc<Share>, timeout_ns: u64) -> GLenum {
-    // TODO:
-    // This can be called by multiple objects wanting to ensure they have exclusive
-    // access to a resource. How much does this call costs ? The status of the fence
-    // could be cached to avoid calling this more than once (in core or in the backend ?).
+    let sync = match fence.0.get() {
+        n::FenceState::Signalled => return gl::ALREADY_SIGNALED,
+        // Nothing has been submitted, and nothing can be submitted while
+        // we are blocking the only thread owning the context.
+        n::FenceState::Idle => return gl::TIMEOUT_EXPIRED,
+        n::FenceState::Pending(sync) => sync,
+    };
+
     let gl = &share.context;
-    unsafe {
-        if share.private_caps.sync {
-            gl.ClientWaitSync(fence.0.get(), gl::SYNC_FLUSH_COMMANDS_BIT, timeout_ns)
-        } else {
-            // We fallback to waiting for *everything* to finish
-            gl.Flush();
-            gl::CONDITION_SATISFIED
+    // Flushing makes sure the sync object actually reaches the GPU,
+    // otherwise the fence might never signal.
+    let status = unsafe { gl.ClientWaitSync(sync, gl::SYNC_FLUSH_COMMANDS_BIT, timeout_ns) };
+    match status {
+        gl::ALREADY_SIGNALED | gl::CONDITION_SATISFIED => {
+            unsafe { gl.DeleteSync(sync) };
+            fence.0.set(n::FenceState::Signalled);
         }
+        _ => (),
     }
+    status
 }
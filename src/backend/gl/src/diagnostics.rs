@@ -0,0 +1,302 @@
+//! Self-test of the implementation, producing a report to attach to bug
+//! reports.
+//!
+//! The checks exercise the paths most likely to be broken on a driver:
+//! framebuffer completeness of common formats, buffer mapping and shader
+//! compilation. They run on the context of the adapter and leave no object
+//! behind.
+
+use std::fmt;
+use std::ptr;
+
+use crate::hal::format::Format;
+
+use crate::device::{get_program_iv, get_program_log, get_shader_iv, get_shader_log};
+use crate::gl::{self, types::GLenum};
+use crate::info::{DownlevelProperties, Version};
+use crate::{Error, GlContainer, Share};
+
+/// Outcome of a diagnostic check.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    /// The check passed.
+    Works,
+    /// The implementation doesn't expose the functionality, the check was
+    /// skipped.
+    Unsupported,
+    /// The check failed, with a description of the failure.
+    Fails(String),
+}
+
+/// Report of the checks run by `PhysicalDevice::diagnostics`.
+#[derive(Clone, Debug)]
+pub struct Diagnostics {
+    pub vendor: String,
+    pub renderer: String,
+    pub version: String,
+    pub shading_language: String,
+    /// Operations emulated or unsupported by the backend.
+    pub downlevel: DownlevelProperties,
+    /// Completeness of a framebuffer with a single attachment of each
+    /// format.
+    pub framebuffer_formats: Vec<(Format, Outcome)>,
+    /// Writing to a mapped buffer and reading the data back.
+    pub memory_mapping: Outcome,
+    /// Compiling and linking a trivial vertex and fragment shader.
+    pub shader_compilation: Outcome,
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Vendor: {}", self.vendor)?;
+        writeln!(f, "Renderer: {}", self.renderer)?;
+        writeln!(f, "Version: {}", self.version)?;
+        writeln!(f, "Shading Language: {}", self.shading_language)?;
+        writeln!(f, "Downlevel: {:#?}", self.downlevel)?;
+        writeln!(f, "Framebuffer formats:")?;
+        for &(format, ref outcome) in &self.framebuffer_formats {
+            writeln!(f, "- {:?}: {:?}", format, outcome)?;
+        }
+        writeln!(f, "Memory mapping: {:?}", self.memory_mapping)?;
+        writeln!(f, "Shader compilation: {:?}", self.shader_compilation)
+    }
+}
+
+/// Formats checked for framebuffer completeness, with their internal
+/// format and attachment point.
+const FRAMEBUFFER_FORMATS: &[(Format, GLenum, GLenum)] = &[
+    (Format::Rgba8Unorm, gl::RGBA8, gl::COLOR_ATTACHMENT0),
+    (Format::Rgba8Srgb, gl::SRGB8_ALPHA8, gl::COLOR_ATTACHMENT0),
+    (Format::R8Unorm, gl::R8, gl::COLOR_ATTACHMENT0),
+    (Format::Rg8Unorm, gl::RG8, gl::COLOR_ATTACHMENT0),
+    (
+        Format::A2b10g10r10Unorm,
+        gl::RGB10_A2,
+        gl::COLOR_ATTACHMENT0,
+    ),
+    (
+        Format::B10g11r11Ufloat,
+        gl::R11F_G11F_B10F,
+        gl::COLOR_ATTACHMENT0,
+    ),
+    (Format::R16Sfloat, gl::R16F, gl::COLOR_ATTACHMENT0),
+    (Format::Rgba16Sfloat, gl::RGBA16F, gl::COLOR_ATTACHMENT0),
+    (Format::R32Sfloat, gl::R32F, gl::COLOR_ATTACHMENT0),
+    (Format::Rgba32Sfloat, gl::RGBA32F, gl::COLOR_ATTACHMENT0),
+    (Format::R32Uint, gl::R32UI, gl::COLOR_ATTACHMENT0),
+    (Format::Rgba32Uint, gl::RGBA32UI, gl::COLOR_ATTACHMENT0),
+    (
+        Format::D16Unorm,
+        gl::DEPTH_COMPONENT16,
+        gl::DEPTH_ATTACHMENT,
+    ),
+    (
+        Format::D32Sfloat,
+        gl::DEPTH_COMPONENT32F,
+        gl::DEPTH_ATTACHMENT,
+    ),
+    (
+        Format::D24UnormS8Uint,
+        gl::DEPTH24_STENCIL8,
+        gl::DEPTH_STENCIL_ATTACHMENT,
+    ),
+    (
+        Format::D32SfloatS8Uint,
+        gl::DEPTH32F_STENCIL8,
+        gl::DEPTH_STENCIL_ATTACHMENT,
+    ),
+];
+
+/// Clear the error flag, returning the error which was set.
+fn take_error(gl: &GlContainer) -> Result<(), Error> {
+    match Error::from_error_code(unsafe { gl.GetError() }) {
+        Error::NoError => Ok(()),
+        err => Err(err),
+    }
+}
+
+fn check_framebuffer(share: &Share, internal_format: GLenum, attachment: GLenum) -> Outcome {
+    if !share.private_caps.framebuffer {
+        return Outcome::Unsupported;
+    }
+    let gl = &share.context;
+    let (mut renderbuffer, mut framebuffer) = (0, 0);
+    let status = unsafe {
+        gl.GenRenderbuffers(1, &mut renderbuffer);
+        gl.BindRenderbuffer(gl::RENDERBUFFER, renderbuffer);
+        gl.RenderbufferStorage(gl::RENDERBUFFER, internal_format, 4, 4);
+        if let Err(err) = take_error(gl) {
+            gl.BindRenderbuffer(gl::RENDERBUFFER, 0);
+            gl.DeleteRenderbuffers(1, &renderbuffer);
+            // Formats unknown to the implementation can't be created at all.
+            return match err {
+                Error::InvalidEnum => Outcome::Unsupported,
+                err => Outcome::Fails(format!("{:?}", err)),
+            };
+        }
+        gl.GenFramebuffers(1, &mut framebuffer);
+        gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+        gl.FramebufferRenderbuffer(gl::FRAMEBUFFER, attachment, gl::RENDERBUFFER, renderbuffer);
+        let status = gl.CheckFramebufferStatus(gl::FRAMEBUFFER);
+        gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl.DeleteFramebuffers(1, &framebuffer);
+        gl.BindRenderbuffer(gl::RENDERBUFFER, 0);
+        gl.DeleteRenderbuffers(1, &renderbuffer);
+        status
+    };
+    match (take_error(gl), status) {
+        (Err(err), _) => Outcome::Fails(format!("{:?}", err)),
+        (Ok(()), gl::FRAMEBUFFER_COMPLETE) => Outcome::Works,
+        (Ok(()), gl::FRAMEBUFFER_UNSUPPORTED) => Outcome::Unsupported,
+        (Ok(()), status) => Outcome::Fails(format!("Incomplete framebuffer: 0x{:x}", status)),
+    }
+}
+
+fn check_memory_mapping(share: &Share) -> Outcome {
+    if !share.private_caps.map {
+        return Outcome::Unsupported;
+    }
+    let gl = &share.context;
+    let data = (0..64u8).collect::<Vec<_>>();
+    let mut buffer = 0;
+    let result = unsafe {
+        gl.GenBuffers(1, &mut buffer);
+        gl.BindBuffer(gl::ARRAY_BUFFER, buffer);
+        gl.BufferData(
+            gl::ARRAY_BUFFER,
+            data.len() as _,
+            ptr::null(),
+            gl::DYNAMIC_DRAW,
+        );
+        let map = |access| gl.MapBufferRange(gl::ARRAY_BUFFER, 0, data.len() as _, access);
+        let result = (|| {
+            let ptr = map(gl::MAP_WRITE_BIT | gl::MAP_INVALIDATE_BUFFER_BIT) as *mut u8;
+            if ptr.is_null() {
+                return Err("Failed to map the buffer for writing".to_string());
+            }
+            ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len());
+            if gl.UnmapBuffer(gl::ARRAY_BUFFER) == gl::FALSE {
+                return Err("The buffer contents were lost when unmapping it".to_string());
+            }
+            let ptr = map(gl::MAP_READ_BIT) as *const u8;
+            if ptr.is_null() {
+                return Err("Failed to map the buffer for reading".to_string());
+            }
+            let matches = std::slice::from_raw_parts(ptr, data.len()) == &data[..];
+            gl.UnmapBuffer(gl::ARRAY_BUFFER);
+            if !matches {
+                return Err("The data read back differs from the data written".to_string());
+            }
+            Ok(())
+        })();
+        gl.BindBuffer(gl::ARRAY_BUFFER, 0);
+        gl.DeleteBuffers(1, &buffer);
+        result
+    };
+    match (take_error(gl), result) {
+        (Err(err), _) => Outcome::Fails(format!("{:?}", err)),
+        (Ok(()), Err(message)) => Outcome::Fails(message),
+        (Ok(()), Ok(())) => Outcome::Works,
+    }
+}
+
+/// Sources of the trivial shaders, in the GLSL dialect of the context.
+fn shader_sources(version: &Version) -> (String, String) {
+    let vertex_main = "void main() { gl_Position = vec4(0.0, 0.0, 0.0, 1.0); }\n";
+    let modern = if version.is_embedded {
+        version.major >= 3
+    } else {
+        (version.major, version.minor) >= (1, 40)
+    };
+    let header = match (version.is_embedded, modern) {
+        (true, true) => "#version 300 es\nprecision mediump float;\n".to_string(),
+        (true, false) => "#version 100\nprecision mediump float;\n".to_string(),
+        (false, _) => format!("#version {}{:02}\n", version.major, version.minor),
+    };
+    let fragment_main = if modern {
+        "out vec4 color;\nvoid main() { color = vec4(1.0); }\n"
+    } else {
+        "void main() { gl_FragColor = vec4(1.0); }\n"
+    };
+    (
+        format!("{}{}", header, vertex_main),
+        format!("{}{}", header, fragment_main),
+    )
+}
+
+fn check_shader_compilation(share: &Share) -> Outcome {
+    let gl = &share.context;
+    let (vertex, fragment) = shader_sources(&share.info.shading_language);
+    let mut failure = None;
+    unsafe {
+        let program = gl.CreateProgram();
+        let mut shaders = Vec::new();
+        for &(stage, ty, ref source) in &[
+            ("vertex", gl::VERTEX_SHADER, vertex),
+            ("fragment", gl::FRAGMENT_SHADER, fragment),
+        ] {
+            let shader = gl.CreateShader(ty);
+            gl.ShaderSource(
+                shader,
+                1,
+                &(source.as_ptr() as *const gl::types::GLchar),
+                &(source.len() as gl::types::GLint),
+            );
+            gl.CompileShader(shader);
+            if failure.is_none() && get_shader_iv(gl, shader, gl::COMPILE_STATUS) == 0 {
+                failure = Some(format!(
+                    "Failed to compile the {} shader: {}",
+                    stage,
+                    get_shader_log(gl, shader)
+                ));
+            }
+            gl.AttachShader(program, shader);
+            shaders.push(shader);
+        }
+        if failure.is_none() {
+            gl.LinkProgram(program);
+            if get_program_iv(gl, program, gl::LINK_STATUS) == 0 {
+                failure = Some(format!(
+                    "Failed to link the program: {}",
+                    get_program_log(gl, program)
+                ));
+            }
+        }
+        for shader in shaders {
+            gl.DeleteShader(shader);
+        }
+        gl.DeleteProgram(program);
+    }
+    match (take_error(gl), failure) {
+        (Err(err), _) => Outcome::Fails(format!("{:?}", err)),
+        (Ok(()), Some(message)) => Outcome::Fails(message),
+        (Ok(()), None) => Outcome::Works,
+    }
+}
+
+pub(crate) fn run(share: &Share, downlevel: DownlevelProperties) -> Diagnostics {
+    // Errors raised before the checks would be attributed to them.
+    if let Err(err) = take_error(&share.context) {
+        warn!("Error {:?} set before running the diagnostics", err);
+    }
+    let framebuffer_formats = FRAMEBUFFER_FORMATS
+        .iter()
+        .map(|&(format, internal_format, attachment)| {
+            (
+                format,
+                check_framebuffer(share, internal_format, attachment),
+            )
+        })
+        .collect();
+
+    Diagnostics {
+        vendor: share.info.platform_name.vendor.to_string(),
+        renderer: share.info.platform_name.renderer.to_string(),
+        version: format!("{:?}", share.info.version),
+        shading_language: format!("{:?}", share.info.shading_language),
+        downlevel,
+        framebuffer_formats,
+        memory_mapping: check_memory_mapping(share),
+        shader_compilation: check_shader_compilation(share),
+    }
+}
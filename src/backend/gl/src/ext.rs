@@ -0,0 +1,483 @@
+//! Entry points of extensions which are not part of the generated bindings.
+//!
+//! These are loaded by hand from the same loader function as the core
+//! bindings, and are only present if the driver exposes the extension.
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::{mem, ptr};
+
+use crate::gl::types::{GLclampd, GLenum, GLint, GLsizei, GLsizeiptr, GLuint, GLuint64};
+use crate::info::Info;
+
+/// `GL_ARB_bindless_texture`
+#[derive(Clone, Copy)]
+pub(crate) struct BindlessTexture {
+    pub get_texture_handle: unsafe extern "system" fn(GLuint) -> GLuint64,
+    pub get_texture_sampler_handle: unsafe extern "system" fn(GLuint, GLuint) -> GLuint64,
+    pub make_texture_handle_resident: unsafe extern "system" fn(GLuint64),
+    pub make_texture_handle_non_resident: unsafe extern "system" fn(GLuint64),
+}
+
+impl BindlessTexture {
+    unsafe fn load<F>(fn_proc: &mut F) -> Option<Self>
+    where
+        F: FnMut(&str) -> *const c_void,
+    {
+        Some(BindlessTexture {
+            get_texture_handle: load_fn(fn_proc, "glGetTextureHandleARB")?,
+            get_texture_sampler_handle: load_fn(fn_proc, "glGetTextureSamplerHandleARB")?,
+            make_texture_handle_resident: load_fn(fn_proc, "glMakeTextureHandleResidentARB")?,
+            make_texture_handle_non_resident: load_fn(
+                fn_proc,
+                "glMakeTextureHandleNonResidentARB",
+            )?,
+        })
+    }
+}
+
+/// `GLX_MESA_query_renderer`
+#[derive(Clone, Copy)]
+pub(crate) struct MesaQueryRenderer {
+    pub query_current_renderer_integer: unsafe extern "system" fn(c_int, *mut c_uint) -> c_int,
+}
+
+impl MesaQueryRenderer {
+    pub const VENDOR_ID: c_int = 0x8183;
+    pub const DEVICE_ID: c_int = 0x8184;
+    pub const UNIFIED_MEMORY_ARCHITECTURE: c_int = 0x8188;
+
+    unsafe fn load<F>(fn_proc: &mut F) -> Option<Self>
+    where
+        F: FnMut(&str) -> *const c_void,
+    {
+        Some(MesaQueryRenderer {
+            query_current_renderer_integer: load_fn(fn_proc, "glXQueryCurrentRendererIntegerMESA")?,
+        })
+    }
+
+    fn query(&self, attribute: c_int) -> Option<usize> {
+        let mut value = 0;
+        if unsafe { (self.query_current_renderer_integer)(attribute, &mut value) } != 0 {
+            Some(value as usize)
+        } else {
+            None
+        }
+    }
+
+    /// PCI vendor ID of the device backing the current context.
+    pub fn vendor_id(&self) -> Option<usize> {
+        self.query(Self::VENDOR_ID)
+    }
+
+    /// PCI device ID of the device backing the current context.
+    pub fn device_id(&self) -> Option<usize> {
+        self.query(Self::DEVICE_ID)
+    }
+
+    /// Whether the device shares its memory with the host.
+    pub fn unified_memory(&self) -> Option<bool> {
+        self.query(Self::UNIFIED_MEMORY_ARCHITECTURE)
+            .map(|value| value != 0)
+    }
+}
+
+/// Window system interface the context was created with, whose extensions
+/// aren't listed with the GL ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Platform {
+    Glx,
+    Egl,
+    /// Any other interface, or an unknown one.
+    #[allow(dead_code)] // Not used by the window of every target.
+    Other,
+}
+
+/// EGL entry points to look up the extensions of the display of the current
+/// context.
+#[derive(Clone, Copy)]
+struct Egl {
+    get_current_display: unsafe extern "system" fn() -> *mut c_void,
+    query_string: unsafe extern "system" fn(*mut c_void, c_int) -> *const c_char,
+}
+
+impl Egl {
+    const EXTENSIONS: c_int = 0x3055;
+
+    unsafe fn load<F>(fn_proc: &mut F) -> Option<Self>
+    where
+        F: FnMut(&str) -> *const c_void,
+    {
+        Some(Egl {
+            get_current_display: load_fn(fn_proc, "eglGetCurrentDisplay")?,
+            query_string: load_fn(fn_proc, "eglQueryString")?,
+        })
+    }
+
+    /// Check if the display of the current context supports an extension.
+    unsafe fn is_extension_supported(&self, name: &str) -> bool {
+        let display = (self.get_current_display)();
+        if display.is_null() {
+            return false;
+        }
+        let extensions = (self.query_string)(display, Self::EXTENSIONS);
+        !extensions.is_null()
+            && CStr::from_ptr(extensions)
+                .to_string_lossy()
+                .split_whitespace()
+                .any(|extension| extension == name)
+    }
+}
+
+/// GLX entry points to look up the extensions of the display of the current
+/// context.
+#[derive(Clone, Copy)]
+struct Glx {
+    get_current_display: unsafe extern "system" fn() -> *mut c_void,
+    get_current_context: unsafe extern "system" fn() -> *mut c_void,
+    query_context: unsafe extern "system" fn(*mut c_void, *mut c_void, c_int, *mut c_int) -> c_int,
+    query_extensions_string: unsafe extern "system" fn(*mut c_void, c_int) -> *const c_char,
+}
+
+impl Glx {
+    const SCREEN: c_int = 0x800C;
+
+    unsafe fn load<F>(fn_proc: &mut F) -> Option<Self>
+    where
+        F: FnMut(&str) -> *const c_void,
+    {
+        Some(Glx {
+            get_current_display: load_fn(fn_proc, "glXGetCurrentDisplay")?,
+            get_current_context: load_fn(fn_proc, "glXGetCurrentContext")?,
+            query_context: load_fn(fn_proc, "glXQueryContext")?,
+            query_extensions_string: load_fn(fn_proc, "glXQueryExtensionsString")?,
+        })
+    }
+
+    /// Check if the screen of the current context supports an extension.
+    unsafe fn is_extension_supported(&self, name: &str) -> bool {
+        let display = (self.get_current_display)();
+        let context = (self.get_current_context)();
+        if display.is_null() || context.is_null() {
+            return false;
+        }
+        let mut screen = 0;
+        // `Success` is 0.
+        if (self.query_context)(display, context, Self::SCREEN, &mut screen) != 0 {
+            return false;
+        }
+        let extensions = (self.query_extensions_string)(display, screen);
+        !extensions.is_null()
+            && CStr::from_ptr(extensions)
+                .to_string_lossy()
+                .split_whitespace()
+                .any(|extension| extension == name)
+    }
+}
+
+/// `GL_KHR_parallel_shader_compile`
+#[derive(Clone, Copy)]
+pub(crate) struct ParallelShaderCompile {
+    pub max_shader_compiler_threads: unsafe extern "system" fn(GLuint),
+}
+
+impl ParallelShaderCompile {
+    unsafe fn load<F>(fn_proc: &mut F) -> Option<Self>
+    where
+        F: FnMut(&str) -> *const c_void,
+    {
+        Some(ParallelShaderCompile {
+            max_shader_compiler_threads: load_fn(fn_proc, "glMaxShaderCompilerThreadsKHR")
+                .or_else(|| load_fn(fn_proc, "glMaxShaderCompilerThreadsARB"))?,
+        })
+    }
+}
+
+/// `GL_EXT_depth_bounds_test`
+#[derive(Clone, Copy)]
+pub(crate) struct DepthBoundsTest {
+    pub depth_bounds: unsafe extern "system" fn(GLclampd, GLclampd),
+}
+
+impl DepthBoundsTest {
+    pub const DEPTH_BOUNDS_TEST: GLenum = 0x8890;
+
+    unsafe fn load<F>(fn_proc: &mut F) -> Option<Self>
+    where
+        F: FnMut(&str) -> *const c_void,
+    {
+        Some(DepthBoundsTest {
+            depth_bounds: load_fn(fn_proc, "glDepthBoundsEXT")?,
+        })
+    }
+}
+
+/// `GL_OES_EGL_image`
+#[derive(Clone, Copy)]
+pub(crate) struct EglImageTarget {
+    pub egl_image_target_texture_2d: unsafe extern "system" fn(GLenum, *const c_void),
+}
+
+impl EglImageTarget {
+    unsafe fn load<F>(fn_proc: &mut F) -> Option<Self>
+    where
+        F: FnMut(&str) -> *const c_void,
+    {
+        Some(EglImageTarget {
+            egl_image_target_texture_2d: load_fn(fn_proc, "glEGLImageTargetTexture2DOES")?,
+        })
+    }
+}
+
+/// `EGL_KHR_image_base`
+#[derive(Clone, Copy)]
+pub(crate) struct EglImageBase {
+    pub create_image: unsafe extern "system" fn(
+        *mut c_void,
+        *mut c_void,
+        c_uint,
+        *mut c_void,
+        *const c_int,
+    ) -> *mut c_void,
+    pub destroy_image: unsafe extern "system" fn(*mut c_void, *mut c_void) -> c_uint,
+}
+
+impl EglImageBase {
+    pub const GL_TEXTURE_2D: c_uint = 0x30B1;
+    pub const GL_RENDERBUFFER: c_uint = 0x30B9;
+    pub const GL_TEXTURE_LEVEL: c_int = 0x30BC;
+    pub const NONE: c_int = 0x3038;
+    pub const NO_CONTEXT: *mut c_void = ptr::null_mut();
+    pub const WIDTH: c_int = 0x3057;
+    pub const HEIGHT: c_int = 0x3056;
+    /// `EGL_EXT_image_dma_buf_import`
+    pub const LINUX_DMA_BUF: c_uint = 0x3270;
+    pub const LINUX_DRM_FOURCC: c_int = 0x3271;
+    /// File descriptor, offset and pitch of each plane.
+    pub const DMA_BUF_PLANES: [[c_int; 3]; 4] = [
+        [0x3272, 0x3273, 0x3274],
+        [0x3275, 0x3276, 0x3277],
+        [0x3278, 0x3279, 0x327A],
+        [0x3440, 0x3441, 0x3442],
+    ];
+    /// `EGL_EXT_image_dma_buf_import_modifiers`, low and high bits of the
+    /// modifier of each plane.
+    pub const DMA_BUF_PLANE_MODIFIERS: [[c_int; 2]; 4] = [
+        [0x3443, 0x3444],
+        [0x3445, 0x3446],
+        [0x3447, 0x3448],
+        [0x3449, 0x344A],
+    ];
+
+    unsafe fn load<F>(fn_proc: &mut F) -> Option<Self>
+    where
+        F: FnMut(&str) -> *const c_void,
+    {
+        Some(EglImageBase {
+            create_image: load_fn(fn_proc, "eglCreateImageKHR")?,
+            destroy_image: load_fn(fn_proc, "eglDestroyImageKHR")?,
+        })
+    }
+}
+
+/// `GL_EXT_memory_object`
+#[derive(Clone, Copy)]
+pub(crate) struct MemoryObject {
+    pub create_memory_objects: unsafe extern "system" fn(GLsizei, *mut GLuint),
+    pub delete_memory_objects: unsafe extern "system" fn(GLsizei, *const GLuint),
+    pub memory_object_parameteriv: unsafe extern "system" fn(GLuint, GLenum, *const GLint),
+    pub tex_storage_mem_2d:
+        unsafe extern "system" fn(GLenum, GLsizei, GLenum, GLsizei, GLsizei, GLuint, GLuint64),
+    pub buffer_storage_mem: unsafe extern "system" fn(GLenum, GLsizeiptr, GLuint, GLuint64),
+    /// `GL_EXT_memory_object_fd`
+    pub import_memory_fd: Option<unsafe extern "system" fn(GLuint, GLuint64, GLenum, GLint)>,
+    /// `GL_EXT_memory_object_win32`
+    pub import_memory_win32_handle:
+        Option<unsafe extern "system" fn(GLuint, GLuint64, GLenum, *mut c_void)>,
+}
+
+impl MemoryObject {
+    pub const DEDICATED_MEMORY_OBJECT: GLenum = 0x9581;
+    pub const HANDLE_TYPE_OPAQUE_FD: GLenum = 0x9586;
+    pub const HANDLE_TYPE_OPAQUE_WIN32: GLenum = 0x9587;
+
+    unsafe fn load<F>(info: &Info, fn_proc: &mut F) -> Option<Self>
+    where
+        F: FnMut(&str) -> *const c_void,
+    {
+        Some(MemoryObject {
+            create_memory_objects: load_fn(fn_proc, "glCreateMemoryObjectsEXT")?,
+            delete_memory_objects: load_fn(fn_proc, "glDeleteMemoryObjectsEXT")?,
+            memory_object_parameteriv: load_fn(fn_proc, "glMemoryObjectParameterivEXT")?,
+            tex_storage_mem_2d: load_fn(fn_proc, "glTexStorageMem2DEXT")?,
+            buffer_storage_mem: load_fn(fn_proc, "glBufferStorageMemEXT")?,
+            import_memory_fd: if info.is_extension_supported("GL_EXT_memory_object_fd") {
+                load_fn(fn_proc, "glImportMemoryFdEXT")
+            } else {
+                None
+            },
+            import_memory_win32_handle: if info.is_extension_supported("GL_EXT_memory_object_win32")
+            {
+                load_fn(fn_proc, "glImportMemoryWin32HandleEXT")
+            } else {
+                None
+            },
+        })
+    }
+}
+
+/// `GL_EXT_semaphore`
+#[derive(Clone, Copy)]
+pub(crate) struct Semaphore {
+    pub gen_semaphores: unsafe extern "system" fn(GLsizei, *mut GLuint),
+    pub delete_semaphores: unsafe extern "system" fn(GLsizei, *const GLuint),
+    pub wait_semaphore: unsafe extern "system" fn(
+        GLuint,
+        GLuint,
+        *const GLuint,
+        GLuint,
+        *const GLuint,
+        *const GLenum,
+    ),
+    pub signal_semaphore: unsafe extern "system" fn(
+        GLuint,
+        GLuint,
+        *const GLuint,
+        GLuint,
+        *const GLuint,
+        *const GLenum,
+    ),
+    /// `GL_EXT_semaphore_fd`
+    pub import_semaphore_fd: Option<unsafe extern "system" fn(GLuint, GLenum, GLint)>,
+    /// `GL_EXT_semaphore_win32`
+    pub import_semaphore_win32_handle:
+        Option<unsafe extern "system" fn(GLuint, GLenum, *mut c_void)>,
+}
+
+impl Semaphore {
+    pub const HANDLE_TYPE_OPAQUE_FD: GLenum = 0x9586;
+    pub const HANDLE_TYPE_OPAQUE_WIN32: GLenum = 0x9587;
+
+    unsafe fn load<F>(info: &Info, fn_proc: &mut F) -> Option<Self>
+    where
+        F: FnMut(&str) -> *const c_void,
+    {
+        Some(Semaphore {
+            gen_semaphores: load_fn(fn_proc, "glGenSemaphoresEXT")?,
+            delete_semaphores: load_fn(fn_proc, "glDeleteSemaphoresEXT")?,
+            wait_semaphore: load_fn(fn_proc, "glWaitSemaphoreEXT")?,
+            signal_semaphore: load_fn(fn_proc, "glSignalSemaphoreEXT")?,
+            import_semaphore_fd: if info.is_extension_supported("GL_EXT_semaphore_fd") {
+                load_fn(fn_proc, "glImportSemaphoreFdEXT")
+            } else {
+                None
+            },
+            import_semaphore_win32_handle: if info.is_extension_supported("GL_EXT_semaphore_win32")
+            {
+                load_fn(fn_proc, "glImportSemaphoreWin32HandleEXT")
+            } else {
+                None
+            },
+        })
+    }
+}
+
+/// Extension entry points that were found on the current context.
+#[derive(Clone, Copy)]
+pub(crate) struct Extensions {
+    pub bindless_texture: Option<BindlessTexture>,
+    pub mesa_query_renderer: Option<MesaQueryRenderer>,
+    pub parallel_shader_compile: Option<ParallelShaderCompile>,
+    pub depth_bounds_test: Option<DepthBoundsTest>,
+    pub egl_image_target: Option<EglImageTarget>,
+    pub egl_image_base: Option<EglImageBase>,
+    pub memory_object: Option<MemoryObject>,
+    pub semaphore: Option<Semaphore>,
+}
+
+unsafe fn load_fn<F, T>(fn_proc: &mut F, name: &str) -> Option<T>
+where
+    F: FnMut(&str) -> *const c_void,
+{
+    assert_eq!(mem::size_of::<T>(), mem::size_of::<*const c_void>());
+    let ptr = fn_proc(name);
+    if ptr.is_null() {
+        None
+    } else {
+        Some(mem::transmute_copy(&ptr))
+    }
+}
+
+impl Extensions {
+    /// Load the entry points of the extensions of the current context,
+    /// created through `platform`, whose functions can't be called on other
+    /// contexts.
+    pub(crate) fn load<F>(info: &Info, platform: Platform, mut fn_proc: F) -> Self
+    where
+        F: FnMut(&str) -> *const c_void,
+    {
+        unsafe {
+            let glx = match platform {
+                Platform::Glx => Glx::load(&mut fn_proc),
+                _ => None,
+            };
+            let egl = match platform {
+                Platform::Egl => Egl::load(&mut fn_proc),
+                _ => None,
+            };
+            Extensions {
+                bindless_texture: if info.is_extension_supported("GL_ARB_bindless_texture") {
+                    BindlessTexture::load(&mut fn_proc)
+                } else {
+                    None
+                },
+                // `glXGetProcAddress` returns non-null pointers for unknown
+                // functions, so check the extension first.
+                mesa_query_renderer: if glx
+                    .map(|glx| glx.is_extension_supported("GLX_MESA_query_renderer"))
+                    .unwrap_or(false)
+                {
+                    MesaQueryRenderer::load(&mut fn_proc)
+                } else {
+                    None
+                },
+                parallel_shader_compile: if info
+                    .is_extension_supported("GL_KHR_parallel_shader_compile")
+                    || info.is_extension_supported("GL_ARB_parallel_shader_compile")
+                {
+                    ParallelShaderCompile::load(&mut fn_proc)
+                } else {
+                    None
+                },
+                depth_bounds_test: if info.is_extension_supported("GL_EXT_depth_bounds_test") {
+                    DepthBoundsTest::load(&mut fn_proc)
+                } else {
+                    None
+                },
+                egl_image_target: if info.is_extension_supported("GL_OES_EGL_image") {
+                    EglImageTarget::load(&mut fn_proc)
+                } else {
+                    None
+                },
+                egl_image_base: if egl
+                    .map(|egl| egl.is_extension_supported("EGL_KHR_image_base"))
+                    .unwrap_or(false)
+                {
+                    EglImageBase::load(&mut fn_proc)
+                } else {
+                    None
+                },
+                memory_object: if info.is_extension_supported("GL_EXT_memory_object") {
+                    MemoryObject::load(info, &mut fn_proc)
+                } else {
+                    None
+                },
+                semaphore: if info.is_extension_supported("GL_EXT_semaphore") {
+                    Semaphore::load(info, &mut fn_proc)
+                } else {
+                    None
+                },
+            }
+        }
+    }
+}
@@ -1,7 +1,8 @@
+use crate::hal::adapter::DeviceType;
 use crate::hal::{Features, Limits};
 use std::collections::HashSet;
-use std::{ffi, fmt, mem, str};
-use crate::{gl, Error, GlContainer};
+use std::{env, ffi, fmt, mem, str};
+use crate::{gl, quirks, Error, GlContainer};
 
 /// A version number for a specific component of an OpenGL implementation
 #[derive(Copy, Clone, Eq, Ord, PartialEq, PartialOrd)]
@@ -55,8 +56,20 @@ impl Version {
     /// Note that this function is intentionally lenient in regards to parsing,
     /// and will try to recover at least the first two version numbers without
     /// resulting in an `Err`.
+    ///
+    /// WebGL versions are reported as the ES versions they are based on,
+    /// WebGL 1.0 and 2.0 mapping to ES 2.0 and 3.0.
     pub fn parse(mut src: &'static str) -> Result<Version, &'static str> {
         let es_sig = " ES ";
+        let webgl_sig = "WebGL ";
+        if src.starts_with(webgl_sig) && !src.contains(es_sig) {
+            let version = Version::parse(&src[webgl_sig.len()..])?;
+            return Ok(Version {
+                is_embedded: true,
+                major: version.major + 1,
+                ..version
+            });
+        }
         let is_es = match src.rfind(es_sig) {
             Some(pos) => {
                 src = &src[pos + es_sig.len()..];
@@ -89,6 +102,46 @@ impl Version {
     }
 }
 
+/// Version of the driver, found in the vendor specific part of the
+/// version string (e.g. `NVIDIA 418.56` or `Mesa 19.0.2`).
+#[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct DriverVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: Option<u32>,
+}
+
+impl DriverVersion {
+    /// Create a new driver version number
+    pub fn new(major: u32, minor: u32, patch: Option<u32>) -> Self {
+        DriverVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parse the first dotted version number of the vendor info.
+    pub fn parse(vendor_info: &str) -> Option<Self> {
+        vendor_info
+            .split_whitespace()
+            .filter(|word| word.starts_with(|c: char| c.is_ascii_digit()))
+            .filter_map(|word| {
+                // Ignore suffixes like `-devel` or `-rc1`.
+                let mut it = word
+                    .split(|c| c == '.' || c == '-')
+                    .map(|n| n.parse::<u32>());
+                match (it.next(), it.next(), it.next()) {
+                    (Some(Ok(major)), Some(Ok(minor)), patch) => {
+                        Some(DriverVersion::new(major, minor, patch.and_then(Result::ok)))
+                    }
+                    _ => None,
+                }
+            })
+            .next()
+    }
+}
+
 impl fmt::Debug for Version {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match (self.major, self.minor, self.revision, self.vendor_info) {
@@ -119,7 +172,7 @@ fn get_string(gl: &GlContainer, name: gl::types::GLenum) -> &'static str {
     }
 }
 
-fn get_usize(gl: &GlContainer, name: gl::types::GLenum) -> Result<usize, Error> {
+pub(crate) fn get_usize(gl: &GlContainer, name: gl::types::GLenum) -> Result<usize, Error> {
     let mut value = 0 as gl::types::GLint;
     unsafe { gl.GetIntegerv(name, &mut value) };
 
@@ -131,6 +184,18 @@ fn get_usize(gl: &GlContainer, name: gl::types::GLenum) -> Result<usize, Error>
     }
 }
 
+fn get_f32(gl: &GlContainer, name: gl::types::GLenum) -> Result<f32, Error> {
+    let mut value = 0 as gl::types::GLfloat;
+    unsafe { gl.GetFloatv(name, &mut value) };
+
+    let err = Error::from_error_code(unsafe { gl.GetError() });
+    if err != Error::NoError {
+        Err(err)
+    } else {
+        Ok(value)
+    }
+}
+
 unsafe fn c_str_as_static_str(c_str: *const i8) -> &'static str {
     //TODO: avoid transmuting
     mem::transmute(str::from_utf8(ffi::CStr::from_ptr(c_str as *const _).to_bytes()).unwrap())
@@ -152,6 +217,119 @@ impl PlatformName {
             renderer: get_string(gl, gl::RENDERER),
         }
     }
+
+    /// Infer the PCI vendor ID of the device from the vendor and renderer
+    /// strings, or 0 if it isn't recognized.
+    pub fn vendor_id(&self) -> usize {
+        // source: Sascha Willems at Vulkan
+        const VENDORS: &[(&str, usize)] = &[
+            ("amd", 0x1002),
+            ("ati technologies", 0x1002),
+            ("imgtec", 0x1010),
+            ("imagination", 0x1010),
+            ("nvidia", 0x10DE),
+            ("nouveau", 0x10DE),
+            ("arm", 0x13B5),
+            ("broadcom", 0x14E4),
+            ("vmware", 0x15AD),
+            ("qualcomm", 0x5143),
+            ("intel", 0x8086),
+        ];
+        // Mesa drivers usually report a generic vendor (e.g. "X.Org"),
+        // so the hardware has to be found in the renderer string.
+        const RENDERERS: &[(&str, usize)] = &[
+            ("radeon", 0x1002),
+            ("amd", 0x1002),
+            ("powervr", 0x1010),
+            ("geforce", 0x10DE),
+            ("nvidia", 0x10DE),
+            ("mali", 0x13B5),
+            ("vc4", 0x14E4),
+            ("adreno", 0x5143),
+            ("intel", 0x8086),
+        ];
+
+        let vendor = self.vendor.to_lowercase();
+        let renderer = self.renderer.to_lowercase();
+        VENDORS
+            .iter()
+            .find(|&&(name, _)| vendor.contains(name))
+            .or_else(|| RENDERERS.iter().find(|&&(name, _)| renderer.contains(name)))
+            .map_or(0, |&(_, id)| id)
+    }
+
+    /// Infer the type of the device from the vendor and renderer strings.
+    ///
+    /// OpenGL has no way to query it, so anything not recognized is
+    /// assumed to be a discrete GPU.
+    pub fn device_type(&self) -> DeviceType {
+        const CPU: &[&str] = &[
+            "llvmpipe",
+            "softpipe",
+            "swiftshader",
+            "swrast",
+            "software rasterizer",
+            "microsoft basic render",
+            "gdi generic",
+        ];
+        const VIRTUAL: &[&str] = &["virgl", "svga3d", "virtualbox", "parallels", "vmware"];
+        const INTEGRATED_VENDORS: &[&str] = &["intel", "apple", "arm", "qualcomm", "imgtec"];
+        const INTEGRATED_RENDERERS: &[&str] = &[
+            " xpress", // space here is on purpose so we don't match express
+            "radeon hd 4200",
+            "radeon hd 4250",
+            "radeon hd 4290",
+            "radeon hd 4270",
+            "radeon hd 4225",
+            "radeon hd 3100",
+            "radeon hd 3200",
+            "radeon hd 3000",
+            "radeon hd 3300",
+            "radeon(tm) r4 graphics",
+            "radeon(tm) r5 graphics",
+            "radeon(tm) r6 graphics",
+            "radeon(tm) r7 graphics",
+            "radeon r7 graphics",
+            // APUs, the discrete ones are named "radeon rx vega"
+            "radeon vega",
+            "nforce", // all nvidia nforce are integrated
+            "tegra",  // all nvidia tegra are integrated
+            "shield", // all nvidia shield are integrated
+            "igp",
+            "mali",
+            "adreno",
+            "powervr",
+            "vc4",
+            "apple",
+            "intel",
+        ];
+
+        let vendor = self.vendor.to_lowercase();
+        let renderer = self.renderer.to_lowercase();
+        if CPU.iter().any(|&s| renderer.contains(s)) {
+            DeviceType::Cpu
+        } else if VIRTUAL.iter().any(|&s| renderer.contains(s)) {
+            DeviceType::VirtualGpu
+        } else if INTEGRATED_VENDORS.iter().any(|&s| vendor.contains(s))
+            || INTEGRATED_RENDERERS.iter().any(|&s| renderer.contains(s))
+        {
+            // todo: Intel will release a discrete gpu soon, and we will need
+            // to update this logic when they do
+            DeviceType::IntegratedGpu
+        } else {
+            DeviceType::DiscreteGpu
+        }
+    }
+
+    /// Infer the type of the device, knowing if it shares its memory with
+    /// the host when the driver tells it.
+    pub fn device_type_with_memory(&self, unified_memory: Option<bool>) -> DeviceType {
+        match self.device_type() {
+            DeviceType::DiscreteGpu if unified_memory == Some(true) => DeviceType::IntegratedGpu,
+            DeviceType::IntegratedGpu if unified_memory == Some(false) => DeviceType::DiscreteGpu,
+            device_type => device_type,
+        }
+    }
 }
 
 /// Private capabilities that don't need to be exposed.
@@ -178,6 +356,256 @@ pub struct PrivateCaps {
     pub map: bool,
     /// Indicates if we only have support via the EXT.
     pub sampler_anisotropy_ext: bool,
+    /// Can clamp the sampled mip range with `TEXTURE_MIN_LOD`/`TEXTURE_MAX_LOD`
+    pub sampler_lod_range: bool,
+    /// Can bind texture levels to image units with `glBindImageTexture`
+    pub image_load_store: bool,
+    /// Can detect GPU resets with `glGetGraphicsResetStatus`, which requires
+    /// a robust context losing itself on reset
+    pub reset_notification: bool,
+    /// Can retrieve and load linked program binaries
+    pub program_binary: bool,
+    /// Can create and modify objects without binding them
+    pub direct_state_access: bool,
+    /// Can tell the range of the indices with `glDrawRangeElements`
+    pub draw_range_elements: bool,
+    /// Can bind buffers to the `PIXEL_PACK_BUFFER` and `PIXEL_UNPACK_BUFFER`
+    /// targets for transfers
+    pub pixel_buffer: bool,
+    /// Can bind separate `READ_FRAMEBUFFER` and `DRAW_FRAMEBUFFER` objects
+    pub read_draw_framebuffer: bool,
+    /// Can select the color attachments written with `glDrawBuffers`
+    pub draw_buffers: bool,
+    /// Can specify textures with sized internal formats, ES 2.0 requires
+    /// the internal format to match the format of the data
+    pub sized_internal_formats: bool,
+    /// Can set the row length and skipped texels of pixel transfers with
+    /// `glPixelStorei`, ES 2.0 only has the alignment of the rows
+    pub pixel_store_layout: bool,
+    /// Can discard the content of framebuffer attachments with
+    /// `glInvalidateFramebuffer`
+    pub invalidate_framebuffer: bool,
+    /// Can read the color attachments from fragment shaders with
+    /// `GL_EXT_shader_framebuffer_fetch`
+    pub framebuffer_fetch: bool,
+    /// Can read the first color attachment from fragment shaders with
+    /// `GL_ARM_shader_framebuffer_fetch`
+    pub framebuffer_fetch_arm: bool,
+    /// Can make rendered texels visible to texture fetches with
+    /// `glTextureBarrier`
+    pub texture_barrier: bool,
+}
+
+impl PrivateCaps {
+    /// Disable the capability with the given name, returns `false` if
+    /// there is no such capability.
+    fn disable(&mut self, name: &str) -> bool {
+        let cap = match name {
+            "vertex_array" => &mut self.vertex_array,
+            "framebuffer" => &mut self.framebuffer,
+            "framebuffer_texture" => &mut self.framebuffer_texture,
+            "buffer_role_change" => &mut self.buffer_role_change,
+            "buffer_storage" => &mut self.buffer_storage,
+            "image_storage" => &mut self.image_storage,
+            "clear_buffer" => &mut self.clear_buffer,
+            "program_interface" => &mut self.program_interface,
+            "frag_data_location" => &mut self.frag_data_location,
+            "sync" => &mut self.sync,
+            "map" => &mut self.map,
+            "sampler_anisotropy_ext" => &mut self.sampler_anisotropy_ext,
+            "sampler_lod_range" => &mut self.sampler_lod_range,
+            "image_load_store" => &mut self.image_load_store,
+            "reset_notification" => &mut self.reset_notification,
+            "program_binary" => &mut self.program_binary,
+            "direct_state_access" => &mut self.direct_state_access,
+            "draw_range_elements" => &mut self.draw_range_elements,
+            "pixel_buffer" => &mut self.pixel_buffer,
+            "read_draw_framebuffer" => &mut self.read_draw_framebuffer,
+            "draw_buffers" => &mut self.draw_buffers,
+            "sized_internal_formats" => &mut self.sized_internal_formats,
+            "pixel_store_layout" => &mut self.pixel_store_layout,
+            "invalidate_framebuffer" => &mut self.invalidate_framebuffer,
+            "framebuffer_fetch" => &mut self.framebuffer_fetch,
+            "framebuffer_fetch_arm" => &mut self.framebuffer_fetch_arm,
+            "texture_barrier" => &mut self.texture_barrier,
+            _ => return false,
+        };
+        *cap = false;
+        true
+    }
+}
+
+/// Level of support of a HAL operation.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Support {
+    /// Directly maps to the implementation.
+    Native,
+    /// Emulated by the backend, expect a noticeable overhead or
+    /// weaker guarantees than Vulkan.
+    Emulated,
+    /// Not available at all.
+    Unsupported,
+}
+
+impl Support {
+    fn native_or(native: bool, fallback: Support) -> Self {
+        if native {
+            Support::Native
+        } else {
+            fallback
+        }
+    }
+}
+
+/// Summary of the HAL operations which aren't natively supported by the
+/// implementation, so applications can pick their render paths up front.
+#[derive(Clone, Debug)]
+pub struct DownlevelProperties {
+    /// Compute pipelines and dispatches.
+    pub compute_shaders: Support,
+    /// Tessellation stages.
+    pub tessellation_shaders: Support,
+    /// Storage image descriptors.
+    pub storage_images: Support,
+    /// Indirect draws and dispatches.
+    pub indirect_execution: Support,
+    /// Draws with a base vertex and base instance.
+    pub base_vertex_instance: Support,
+    /// Separate samplers, emulated by setting the sampler state on textures.
+    pub sampler_objects: Support,
+    /// Buffer to buffer copies, emulated by writing a host copy of the
+    /// source buffer to the destination. Like emulated uniform buffers, the
+    /// source is only updated through `update_buffer` and copies.
+    pub buffer_copies: Support,
+    /// Mapping memory to the host.
+    pub memory_mapping: Support,
+    /// Fences and semaphores, emulated by flushing the context if sync
+    /// objects are missing.
+    pub synchronization: Support,
+    /// Multiple queues, always serialized onto the single context.
+    pub multiple_queues: Support,
+    /// sRGB framebuffers.
+    pub srgb_framebuffers: Support,
+    /// Uniform buffers, emulated by loading the members of the uniform
+    /// blocks into plain uniforms before each draw. The emulated buffers
+    /// are only updated through `update_buffer` and copies, their memory
+    /// requirements exclude the CPU visible memory types.
+    pub uniform_buffers: Support,
+    /// Input attachments, read with framebuffer fetch if supported. They are
+    /// emulated by sampling the attachments as textures otherwise, reading
+    /// an attachment written in the same subpass is then undefined.
+    pub input_attachments: Support,
+    /// Depth bounds test. Without it, the test can be done in the fragment
+    /// shader instead, by sampling a copy of the depth attachment at
+    /// `gl_FragCoord` and discarding the fragments out of the bounds, which
+    /// prevents early fragment tests.
+    pub depth_bounds: Support,
+    /// Bounded out of bounds accesses of buffers in shaders, which requires
+    /// a context created with robust access, see
+    /// `config_context` with glutin.
+    pub robust_buffer_access: Support,
+}
+
+impl DownlevelProperties {
+    pub(crate) fn new(
+        features: Features,
+        limits: &Limits,
+        legacy: LegacyFeatures,
+        private: &PrivateCaps,
+    ) -> Self {
+        DownlevelProperties {
+            compute_shaders: Support::native_or(
+                limits.max_compute_work_group_count[0] != 0,
+                Support::Unsupported,
+            ),
+            tessellation_shaders: Support::native_or(
+                limits.max_patch_size != 0,
+                Support::Unsupported,
+            ),
+            storage_images: Support::native_or(private.image_load_store, Support::Unsupported),
+            indirect_execution: Support::native_or(
+                legacy.contains(LegacyFeatures::INDIRECT_EXECUTION),
+                Support::Unsupported,
+            ),
+            base_vertex_instance: Support::native_or(
+                legacy.contains(LegacyFeatures::DRAW_INDEXED_INSTANCED_BASE),
+                Support::Unsupported,
+            ),
+            sampler_objects: Support::native_or(
+                legacy.contains(LegacyFeatures::SAMPLER_OBJECTS),
+                Support::Emulated,
+            ),
+            buffer_copies: Support::native_or(
+                legacy.contains(LegacyFeatures::COPY_BUFFER),
+                Support::Emulated,
+            ),
+            memory_mapping: Support::native_or(private.map, Support::Unsupported),
+            synchronization: Support::native_or(private.sync, Support::Emulated),
+            multiple_queues: Support::Emulated,
+            srgb_framebuffers: Support::native_or(
+                legacy.contains(LegacyFeatures::SRGB_COLOR),
+                Support::Unsupported,
+            ),
+            uniform_buffers: Support::native_or(
+                legacy.contains(LegacyFeatures::CONSTANT_BUFFER),
+                Support::Emulated,
+            ),
+            input_attachments: Support::native_or(
+                private.framebuffer_fetch || private.framebuffer_fetch_arm,
+                Support::Emulated,
+            ),
+            depth_bounds: Support::native_or(
+                features.contains(Features::DEPTH_BOUNDS),
+                Support::Unsupported,
+            ),
+            robust_buffer_access: Support::native_or(
+                features.contains(Features::ROBUST_BUFFER_ACCESS),
+                Support::Unsupported,
+            ),
+        }
+    }
+}
+
+/// Options for the creation of an adapter.
+///
+/// Meant for debugging driver issues, by disabling extensions or
+/// capabilities the backend would otherwise use.
+#[derive(Clone, Debug, Default)]
+pub struct AdapterOptions {
+    /// Names of the extensions (e.g. `GL_ARB_buffer_storage`, the `GL_`
+    /// prefix being optional) and private capabilities (e.g. `buffer_storage`)
+    /// to disable.
+    ///
+    /// Extensions which are part of the core version of the context are
+    /// still used, in which case the capability has to be disabled instead.
+    pub disabled: Vec<String>,
+}
+
+impl AdapterOptions {
+    /// Environment variable holding a comma separated list of extensions
+    /// and capabilities to disable.
+    pub const DISABLE_VAR: &'static str = "GFX_GL_DISABLE";
+
+    /// Read the options from the environment.
+    pub fn from_env() -> Self {
+        let disabled = env::var(Self::DISABLE_VAR)
+            .map(|var| {
+                var.split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        AdapterOptions { disabled }
+    }
+
+    fn is_extension_disabled(&self, extension: &str) -> bool {
+        let short = extension.trim_start_matches("GL_");
+        self.disabled
+            .iter()
+            .any(|name| name == extension || name == short)
+    }
 }
 
 /// OpenGL implementation information
@@ -191,6 +619,8 @@ pub struct Info {
     pub shading_language: Version,
     /// The extensions supported by the implementation
     pub extensions: HashSet<&'static str>,
+    /// The driver version, if it could be parsed from the version string
+    pub driver_version: Option<DriverVersion>,
 }
 
 bitflags! {
@@ -261,6 +691,7 @@ impl Info {
             version: version,
             shading_language: shading_language,
             extensions: extensions,
+            driver_version: DriverVersion::parse(version.vendor_info),
         }
     }
 
@@ -302,9 +733,19 @@ impl Info {
 
 /// Load the information pertaining to the driver and the corresponding device
 /// capabilities.
-pub(crate) fn query_all(gl: &GlContainer) -> (Info, Features, LegacyFeatures, Limits, PrivateCaps) {
+pub(crate) fn query_all(
+    gl: &GlContainer,
+    options: &AdapterOptions,
+) -> (Info, Features, LegacyFeatures, Limits, PrivateCaps) {
     use self::Requirement::*;
-    let info = Info::get(gl);
+    let mut info = Info::get(gl);
+    info.extensions.retain(|extension| {
+        let disabled = options.is_extension_disabled(extension);
+        if disabled {
+            info!("Disabling extension {}", extension);
+        }
+        !disabled
+    });
     let max_texture_size = get_usize(gl, gl::MAX_TEXTURE_SIZE).unwrap_or(64) as u32;
 
     let mut limits = Limits {
@@ -320,21 +761,48 @@ pub(crate) fn query_all(gl: &GlContainer) -> (Info, Features, LegacyFeatures, Li
         min_texel_buffer_offset_alignment: 1,   // TODO
         min_uniform_buffer_offset_alignment: 1, // TODO
         min_storage_buffer_offset_alignment: 1, // TODO
+        framebuffer_color_samples_count: 1,
+        framebuffer_depth_samples_count: 1,
+        framebuffer_stencil_samples_count: 1,
         ..Limits::default()
     };
 
-    if info.is_supported(&[Core(4, 0), Ext("GL_ARB_tessellation_shader")]) {
+    // The ES extensions require ESSL 3.10, which shaders are translated to on
+    // ES 3.1 contexts.
+    let tessellation = info.is_supported(&[
+        Core(4, 0),
+        Es(3, 2),
+        Ext("GL_ARB_tessellation_shader"),
+        Ext("GL_EXT_tessellation_shader"),
+    ]);
+    if tessellation {
         limits.max_patch_size = get_usize(gl, gl::MAX_PATCH_VERTICES).unwrap_or(0) as _;
     }
+    // Multisampled images are renderbuffers, resolved with framebuffer blits.
+    if info.is_supported(&[Core(3, 0), Es(3, 0), Ext("GL_ARB_framebuffer_object")]) {
+        let max_samples = get_usize(gl, gl::MAX_SAMPLES).unwrap_or(1);
+        // Masks of the supported counts, which are powers of two.
+        let counts = (0..7)
+            .map(|i| 1 << i)
+            .filter(|&count| count <= max_samples)
+            .fold(0, |counts, count| counts | count) as _;
+        limits.framebuffer_color_samples_count = counts;
+        limits.framebuffer_depth_samples_count = counts;
+        limits.framebuffer_stencil_samples_count = counts;
+    }
     if info.is_supported(&[Core(4, 1)]) {
         // TODO: extension
         limits.max_viewports = get_usize(gl, gl::MAX_VIEWPORTS).unwrap_or(0);
     }
 
+    // Compute stays disabled on every context, ES 3.1 ones included: storage
+    // buffer descriptors and pipeline barriers aren't implemented, so most
+    // compute shaders couldn't be used, or their results read back safely.
     if false
         && info.is_supported(&[
             //TODO: enable when compute is implemented
             Core(4, 3),
+            Es(3, 1),
             Ext("GL_ARB_compute_shader"),
         ])
     {
@@ -364,15 +832,55 @@ pub(crate) fn query_all(gl: &GlContainer) -> (Info, Features, LegacyFeatures, Li
     ]) {
         features |= Features::SAMPLER_ANISOTROPY;
     }
+    if info.is_supported(&[Core(3, 2), Es(3, 2), Ext("GL_EXT_geometry_shader")]) {
+        features |= Features::GEOMETRY_SHADER;
+    }
+    if tessellation && limits.max_patch_size != 0 {
+        features |= Features::TESSELLATION_SHADER;
+    }
+    if info.is_supported(&[Ext("GL_EXT_depth_bounds_test")]) {
+        features |= Features::DEPTH_BOUNDS;
+    }
     if info.is_supported(&[Core(4, 2)]) {
         legacy |= LegacyFeatures::EXPLICIT_LAYOUTS_IN_SHADER;
     }
     if info.is_supported(&[Core(3, 3), Es(3, 0), Ext("GL_ARB_instanced_arrays")]) {
         features |= Features::INSTANCE_RATE;
     }
+    // Wide lines are deprecated, forward compatible contexts reject them.
+    let forward_compatible = !info.version.is_embedded
+        && info.is_supported(&[Core(3, 0)])
+        && get_usize(gl, gl::CONTEXT_FLAGS).unwrap_or(0)
+            & gl::CONTEXT_FLAG_FORWARD_COMPATIBLE_BIT as usize
+            != 0;
+    let mut line_width_range = [1.0; 2];
+    if !forward_compatible {
+        unsafe { gl.GetFloatv(gl::ALIASED_LINE_WIDTH_RANGE, line_width_range.as_mut_ptr()) };
+    }
+    limits.line_width_range = line_width_range;
+    if line_width_range[1] > 1.0 {
+        features |= Features::LINE_WIDTH;
+    }
+    // Out of bounds accesses are only bounded on contexts created with
+    // robust access.
+    let robust_access = info.is_supported(&[Core(3, 0), Es(3, 2)])
+        && get_usize(gl, gl::CONTEXT_FLAGS).unwrap_or(0)
+            & gl::CONTEXT_FLAG_ROBUST_ACCESS_BIT as usize
+            != 0;
+    if robust_access
+        && info.is_supported(&[
+            Core(4, 3),
+            Es(3, 2),
+            Ext("GL_ARB_robust_buffer_access_behavior"),
+            Ext("GL_KHR_robust_buffer_access_behavior"),
+        ])
+    {
+        features |= Features::ROBUST_BUFFER_ACCESS;
+    }
     if info.is_supported(&[Core(3, 3)]) {
         // TODO: extension
         features |= Features::SAMPLER_MIP_LOD_BIAS;
+        limits.max_sampler_lod_bias = get_f32(gl, gl::MAX_TEXTURE_LOD_BIAS).unwrap_or(0.0);
     }
 
     if info.is_supported(&[Core(4, 3), Es(3, 1)]) {
@@ -385,7 +893,7 @@ pub(crate) fn query_all(gl: &GlContainer) -> (Info, Features, LegacyFeatures, Li
     if info.is_supported(&[Core(4, 2), Ext("GL_ARB_base_instance")]) {
         legacy |= LegacyFeatures::DRAW_INSTANCED_BASE;
     }
-    if info.is_supported(&[Core(3, 2)]) {
+    if info.is_supported(&[Core(3, 2), Es(3, 2)]) {
         // TODO: extension
         legacy |= LegacyFeatures::DRAW_INDEXED_BASE;
     }
@@ -393,7 +901,7 @@ pub(crate) fn query_all(gl: &GlContainer) -> (Info, Features, LegacyFeatures, Li
         // TODO: extension
         legacy |= LegacyFeatures::DRAW_INDEXED_INSTANCED;
     }
-    if info.is_supported(&[Core(3, 2)]) {
+    if info.is_supported(&[Core(3, 2), Es(3, 2)]) {
         // TODO: extension
         legacy |= LegacyFeatures::DRAW_INDEXED_INSTANCED_BASE_VERTEX;
     }
@@ -429,7 +937,7 @@ pub(crate) fn query_all(gl: &GlContainer) -> (Info, Features, LegacyFeatures, Li
     if info.is_supported(&[Core(3, 3), Es(3, 0), Ext("GL_ARB_sampler_objects")]) {
         legacy |= LegacyFeatures::SAMPLER_OBJECTS;
     }
-    if info.is_supported(&[Core(3, 3)]) {
+    if info.is_supported(&[Core(3, 3), Es(3, 2)]) {
         // TODO: extension
         legacy |= LegacyFeatures::SAMPLER_BORDER_COLOR;
     }
@@ -437,7 +945,7 @@ pub(crate) fn query_all(gl: &GlContainer) -> (Info, Features, LegacyFeatures, Li
         legacy |= LegacyFeatures::INSTANCED_ATTRIBUTE_BINDING;
     }
 
-    let private = PrivateCaps {
+    let mut private = PrivateCaps {
         vertex_array: info.is_supported(&[Core(3, 0), Es(3, 0), Ext("GL_ARB_vertex_array_object")])
             && gl.GenVertexArrays.is_loaded(),
         framebuffer: info.is_supported(&[Core(3, 0), Es(2, 0), Ext("GL_ARB_framebuffer_object")])
@@ -447,21 +955,170 @@ pub(crate) fn query_all(gl: &GlContainer) -> (Info, Features, LegacyFeatures, Li
         image_storage: info.is_supported(&[Core(4, 2), Ext("GL_ARB_texture_storage")]),
         buffer_storage: info.is_supported(&[Core(4, 4), Ext("GL_ARB_buffer_storage")]),
         clear_buffer: info.is_supported(&[Core(3, 0), Es(3, 0)]),
-        program_interface: info.is_supported(&[Core(4, 3), Ext("GL_ARB_program_interface_query")]),
+        program_interface: info.is_supported(&[
+            Core(4, 3),
+            Es(3, 1),
+            Ext("GL_ARB_program_interface_query"),
+        ]),
         frag_data_location: !info.version.is_embedded,
         sync: info.is_supported(&[Core(3, 2), Es(3, 0), Ext("GL_ARB_sync")]),
         map: !info.version.is_embedded, //TODO: OES extension
         sampler_anisotropy_ext: !info
             .is_supported(&[Core(4, 6), Ext("GL_ARB_texture_filter_anisotropic")])
             && info.is_supported(&[Ext("GL_EXT_texture_filter_anisotropic")]),
+        sampler_lod_range: info.is_supported(&[Core(1, 2), Es(3, 0)]),
+        image_load_store: info.is_supported(&[
+            Core(4, 2),
+            Es(3, 1),
+            Ext("GL_ARB_shader_image_load_store"),
+        ]),
+        reset_notification: gl.GetGraphicsResetStatus.is_loaded()
+            && get_usize(gl, gl::RESET_NOTIFICATION_STRATEGY).ok()
+                == Some(gl::LOSE_CONTEXT_ON_RESET as usize),
+        program_binary: info.is_supported(&[
+            Core(4, 1),
+            Es(3, 0),
+            Ext("GL_ARB_get_program_binary"),
+        ]) && get_usize(gl, gl::NUM_PROGRAM_BINARY_FORMATS).unwrap_or(0) > 0,
+        direct_state_access: info.is_supported(&[Core(4, 5), Ext("GL_ARB_direct_state_access")])
+            && gl.CreateBuffers.is_loaded(),
+        draw_range_elements: info.is_supported(&[Core(1, 2), Es(3, 0)]),
+        pixel_buffer: info.is_supported(&[
+            Core(2, 1),
+            Es(3, 0),
+            Ext("GL_ARB_pixel_buffer_object"),
+            Ext("GL_NV_pixel_buffer_object"),
+        ]),
+        read_draw_framebuffer: info.is_supported(&[
+            Core(3, 0),
+            Es(3, 0),
+            Ext("GL_ARB_framebuffer_object"),
+        ]),
+        draw_buffers: info.is_supported(&[Core(2, 0), Es(3, 0)]),
+        sized_internal_formats: !info.version.is_embedded || info.is_supported(&[Es(3, 0)]),
+        pixel_store_layout: !info.version.is_embedded || info.is_supported(&[Es(3, 0)]),
+        invalidate_framebuffer: info.is_supported(&[
+            Core(4, 3),
+            Es(3, 0),
+            Ext("GL_ARB_invalidate_subdata"),
+        ]),
+        framebuffer_fetch: info.is_supported(&[Ext("GL_EXT_shader_framebuffer_fetch")]),
+        framebuffer_fetch_arm: info.is_supported(&[Ext("GL_ARM_shader_framebuffer_fetch")]),
+        texture_barrier: info.is_supported(&[Core(4, 5), Ext("GL_ARB_texture_barrier")])
+            && gl.TextureBarrier.is_loaded(),
     };
+    quirks::apply(&info, &mut private);
+    for name in &options.disabled {
+        if private.disable(name) {
+            info!("Disabling private capability {}", name);
+        }
+    }
+    // Storage images are bound to image units.
+    if private.image_load_store {
+        let image_units = get_usize(gl, gl::MAX_IMAGE_UNITS).unwrap_or(0);
+        limits.max_per_stage_descriptor_storage_images = image_units;
+        limits.max_descriptor_set_storage_images = image_units;
+    }
 
     (info, features, legacy, limits, private)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Version;
+    use super::{DeviceType, DriverVersion, PlatformName, Version};
+
+    #[test]
+    fn test_driver_version_parse() {
+        assert_eq!(
+            DriverVersion::parse("NVIDIA 418.56"),
+            Some(DriverVersion::new(418, 56, None))
+        );
+        assert_eq!(
+            DriverVersion::parse("(Core Profile) Mesa 19.1.0-devel (git-1e4d3c2)"),
+            Some(DriverVersion::new(19, 1, Some(0)))
+        );
+        assert_eq!(
+            DriverVersion::parse("- Build 26.20.100.6861"),
+            Some(DriverVersion::new(26, 20, Some(100)))
+        );
+        assert_eq!(DriverVersion::parse("Compatibility Profile Context"), None);
+        assert_eq!(DriverVersion::parse(""), None);
+    }
+
+    #[test]
+    fn test_device_type() {
+        let device_type = |vendor, renderer| PlatformName { vendor, renderer }.device_type();
+        assert_eq!(
+            device_type("NVIDIA Corporation", "GeForce GTX 1080"),
+            DeviceType::DiscreteGpu
+        );
+        assert_eq!(
+            device_type("Intel", "Intel(R) UHD Graphics 630"),
+            DeviceType::IntegratedGpu
+        );
+        assert_eq!(device_type("ARM", "Mali-G72"), DeviceType::IntegratedGpu);
+        assert_eq!(
+            device_type("VMware, Inc.", "llvmpipe (LLVM 7.0)"),
+            DeviceType::Cpu
+        );
+        assert_eq!(
+            device_type("Google Inc.", "Google SwiftShader"),
+            DeviceType::Cpu
+        );
+        assert_eq!(device_type("Red Hat", "virgl"), DeviceType::VirtualGpu);
+    }
+
+    #[test]
+    fn test_device_type_with_memory() {
+        use crate::ext::MesaQueryRenderer;
+
+        // `GLX_RENDERER_UNIFIED_MEMORY_ARCHITECTURE_MESA`
+        assert_eq!(MesaQueryRenderer::UNIFIED_MEMORY_ARCHITECTURE, 0x8188);
+
+        let device_type = |vendor, renderer, unified_memory| {
+            PlatformName { vendor, renderer }.device_type_with_memory(unified_memory)
+        };
+        let radeon = ("X.Org", "AMD Radeon RX 480 (POLARIS10)");
+        let intel = ("Intel Open Source Technology Center", "Mesa DRI Intel(R)");
+        assert_eq!(
+            device_type(radeon.0, radeon.1, None),
+            DeviceType::DiscreteGpu
+        );
+        assert_eq!(
+            device_type(radeon.0, radeon.1, Some(false)),
+            DeviceType::DiscreteGpu
+        );
+        assert_eq!(
+            device_type(radeon.0, radeon.1, Some(true)),
+            DeviceType::IntegratedGpu
+        );
+        assert_eq!(
+            device_type(intel.0, intel.1, Some(true)),
+            DeviceType::IntegratedGpu
+        );
+        assert_eq!(
+            device_type(intel.0, intel.1, Some(false)),
+            DeviceType::DiscreteGpu
+        );
+        assert_eq!(
+            device_type("VMware, Inc.", "llvmpipe (LLVM 7.0)", Some(true)),
+            DeviceType::Cpu
+        );
+    }
+
+    #[test]
+    fn test_vendor_id() {
+        let vendor_id = |vendor, renderer| PlatformName { vendor, renderer }.vendor_id();
+        assert_eq!(vendor_id("NVIDIA Corporation", "GeForce GTX 1080"), 0x10DE);
+        assert_eq!(vendor_id("ATI Technologies Inc.", "Radeon RX 580"), 0x1002);
+        assert_eq!(
+            vendor_id("Intel Open Source Technology Center", "Mesa DRI Intel(R)"),
+            0x8086
+        );
+        assert_eq!(vendor_id("X.Org", "AMD Radeon RX 480 (POLARIS10)"), 0x1002);
+        assert_eq!(vendor_id("Qualcomm", "Adreno (TM) 540"), 0x5143);
+        assert_eq!(vendor_id("Unknown", "Software Rasterizer"), 0);
+    }
 
     #[test]
     fn test_version_parse() {
@@ -503,5 +1160,17 @@ mod tests {
             Version::parse("GLSL ES 1.1"),
             Ok(Version::new_embedded(1, 1, ""))
         );
+        assert_eq!(
+            Version::parse("WebGL 1.0"),
+            Ok(Version::new_embedded(2, 0, ""))
+        );
+        assert_eq!(
+            Version::parse("WebGL 1.0 (OpenGL ES 2.0 Chromium)"),
+            Ok(Version::new_embedded(2, 0, "Chromium)"))
+        );
+        assert_eq!(
+            Version::parse("WebGL GLSL ES 1.0 (OpenGL ES GLSL ES 1.0 Chromium)"),
+            Ok(Version::new_embedded(1, 0, "Chromium)"))
+        );
     }
 }
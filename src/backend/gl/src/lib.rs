@@ -7,34 +7,70 @@
 extern crate bitflags;
 #[macro_use]
 extern crate log;
-extern crate gfx_gl as gl;
+#[cfg(feature = "trace")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "trace")]
+extern crate bincode;
+pub extern crate gfx_gl as gl;
 extern crate gfx_hal as hal;
 #[cfg(feature = "glutin")]
 pub extern crate glutin;
+#[cfg(feature = "testing")]
+extern crate osmesa_sys;
+extern crate range_alloc;
 extern crate smallvec;
 extern crate spirv_cross;
 
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::fmt;
 use std::ops::Deref;
 use std::sync::{Arc, Weak};
 use std::thread::{self, ThreadId};
 
+use crate::hal::backend::FastHashMap;
 use crate::hal::queue::{QueueFamilyId, Queues};
 use crate::hal::{error, image, pso};
 
-pub use self::device::Device;
-pub use self::info::{Info, PlatformName, Version};
-
+#[cfg(feature = "call-log")]
+pub use self::call_log::CallCategories;
+pub use self::device::{Device, PrewarmedPipelines};
+pub use self::diagnostics::{Diagnostics, Outcome};
+pub use self::info::{
+    AdapterOptions, DownlevelProperties, DriverVersion, Info, PlatformName, Support, Version,
+};
+pub use self::native::{BindlessTable, BindlessTableError, Timeline};
+pub use self::queue::FrameStatistics;
+#[cfg(feature = "trace")]
+pub use self::trace::Trace;
+pub use self::worker::Worker;
+
+#[cfg(feature = "call-log")]
+mod call_log;
 mod command;
 mod conv;
+mod debug;
 mod device;
+mod diagnostics;
+mod ext;
 mod info;
+#[cfg(test)]
+mod mock;
 mod native;
 mod pool;
+mod program_cache;
 mod queue;
+mod quirks;
+#[cfg(feature = "renderdoc")]
+mod renderdoc;
+mod ring;
 mod state;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "trace")]
+mod trace;
 mod window;
+mod worker;
 
 #[cfg(feature = "glutin")]
 pub use crate::window::glutin::{config_context, Headless, Surface, Swapchain};
@@ -105,6 +141,7 @@ pub enum Error {
     InvalidOperation,
     InvalidFramebufferOperation,
     OutOfMemory,
+    ContextLost,
     UnknownError,
 }
 
@@ -117,11 +154,60 @@ impl Error {
             gl::INVALID_OPERATION => Error::InvalidOperation,
             gl::INVALID_FRAMEBUFFER_OPERATION => Error::InvalidFramebufferOperation,
             gl::OUT_OF_MEMORY => Error::OutOfMemory,
+            gl::CONTEXT_LOST => Error::ContextLost,
             _ => Error::UnknownError,
         }
     }
 }
 
+/// How often the implementation's error flag is queried.
+///
+/// Querying it with `glGetError` forces a synchronization with the driver on
+/// most implementations, which can be costly.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum ErrorCheck {
+    /// Never check for errors.
+    Off,
+    /// Check for errors once per queue submission.
+    Submit,
+    /// Check for errors after each GL call that may fail, panicking on
+    /// errors while executing commands.
+    Call,
+}
+
+impl Default for ErrorCheck {
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            ErrorCheck::Call
+        } else {
+            ErrorCheck::Off
+        }
+    }
+}
+
+/// Options applied when opening a logical device.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceOptions {
+    /// Level of error checking, defaults to checking every call in debug
+    /// builds and no checking in release builds.
+    pub error_check: ErrorCheck,
+    /// File to record the submitted command buffers to, see `Trace`.
+    #[cfg(feature = "trace")]
+    pub trace_path: Option<std::path::PathBuf>,
+    /// Directory to cache linked program binaries in, speeding up pipeline
+    /// creation across runs. Ignored if the context can't retrieve program
+    /// binaries.
+    pub program_cache_dir: Option<std::path::PathBuf>,
+    /// Validate the commands when recording them, logging the invalid ones.
+    ///
+    /// GL reports errors when the commands are executed, far from the
+    /// cause, so this helps when debugging rendering issues.
+    pub validation: bool,
+    /// Categories of GL calls to log, see `CallCategories`.
+    #[cfg(feature = "call-log")]
+    pub call_log: CallCategories,
+}
+
 /// Internal struct of shared data between the physical and logical device.
 struct Share {
     context: GlContainer,
@@ -130,14 +216,57 @@ struct Share {
     legacy_features: info::LegacyFeatures,
     limits: hal::Limits,
     private_caps: info::PrivateCaps,
+    ext: ext::Extensions,
     // Indicates if there is an active logical device.
     open: Cell<bool>,
+    // Level of error checking of the active logical device.
+    error_check: Cell<ErrorCheck>,
+    // Validation of the recorded commands, for the active logical device.
+    validation: Cell<bool>,
+    // Set once a GPU reset has been detected.
+    lost: Cell<bool>,
+    // Statistics of the frame being recorded, and of the last presented one.
+    frame_statistics: Cell<queue::FrameStatistics>,
+    last_frame_statistics: Cell<queue::FrameStatistics>,
+    // Vertex layouts of the graphics pipelines, with the number of pipelines
+    // using each of them. IDs aren't reused, so the queues can tell which of
+    // their vertex arrays belong to destroyed layouts.
+    vertex_layouts: RefCell<FastHashMap<native::VertexLayoutId, (native::VertexLayout, usize)>>,
+    next_vertex_layout: Cell<native::VertexLayoutId>,
+    // Incremented when buffers or vertex layouts are destroyed, making the
+    // queues update the vertex arrays they cached.
+    vertex_array_epoch: Cell<usize>,
+    // Sampler objects, deduplicated by their info.
+    samplers: RefCell<device::SamplerCache>,
+    // Program binary cache of the active logical device.
+    program_cache: RefCell<Option<program_cache::ProgramCache>>,
+    // Host copies of the buffers which can't be read back by the context,
+    // emulated uniform buffers and transfer sources without pixel buffers
+    // or buffer copies. Members of the uniform blocks of each program, if
+    // uniform buffers are emulated with plain uniforms.
+    buffer_shadows: RefCell<FastHashMap<native::RawBuffer, Vec<u8>>>,
+    uniform_block_members: RefCell<FastHashMap<native::Program, Vec<native::UniformBinding>>>,
+    // Resolve attachments of the framebuffers of render passes with any.
+    resolve_targets: RefCell<FastHashMap<native::FrameBuffer, native::ResolveTargets>>,
+    // Resident bindless texture handles of all the tables.
+    bindless_handles: RefCell<FastHashMap<u64, native::BindlessHandle>>,
+    // Queue which last submitted work to the context. All queues are
+    // serialized onto the same context, so any other queue has to
+    // invalidate its state cache before submitting.
+    last_queue: Cell<Option<usize>>,
 }
 
 impl Share {
-    /// Fails during a debug build if the implementation's error flag was set.
+    /// Fails if the implementation's error flag was set, when checking
+    /// errors on every call.
     fn check(&self) -> Result<(), Error> {
-        if cfg!(debug_assertions) {
+        self.check_at(ErrorCheck::Call)
+    }
+
+    /// Fails if the implementation's error flag was set, when the error check
+    /// level is at least `level`.
+    fn check_at(&self, level: ErrorCheck) -> Result<(), Error> {
+        if self.error_check.get() >= level {
             let gl = &self.context;
             let err = Error::from_error_code(unsafe { gl.GetError() });
             if err != Error::NoError {
@@ -146,6 +275,53 @@ impl Share {
         }
         Ok(())
     }
+
+    /// Check if the context has been lost due to a GPU reset.
+    ///
+    /// Resets are only reported on robust contexts created with the
+    /// lose-context-on-reset notification strategy.
+    fn is_lost(&self) -> bool {
+        if !self.lost.get() && self.private_caps.reset_notification {
+            let status = unsafe { self.context.GetGraphicsResetStatus() };
+            if status != gl::NO_ERROR {
+                error!("Context lost due to a GPU reset: {:#x}", status);
+                self.lost.set(true);
+            }
+        }
+        self.lost.get()
+    }
+
+    /// Start a new frame, keeping the statistics of the previous one.
+    fn end_frame(&self) {
+        self.last_frame_statistics
+            .set(self.frame_statistics.replace(Default::default()));
+    }
+
+    /// Block until all the commands previously submitted to the context
+    /// have completed execution.
+    ///
+    /// The context is only current on the thread owning it, which is enforced
+    /// by `Starc` when accessing the shared data.
+    fn wait_idle(&self) -> Result<(), error::HostExecutionError> {
+        if self.is_lost() {
+            return Err(error::HostExecutionError::DeviceLost);
+        }
+        let gl = &self.context;
+        unsafe { gl.Finish() };
+        // Errors might have been deferred until now.
+        match Error::from_error_code(unsafe { gl.GetError() }) {
+            Error::NoError => Ok(()),
+            Error::OutOfMemory => Err(error::HostExecutionError::OutOfDeviceMemory),
+            Error::ContextLost => {
+                self.lost.set(true);
+                Err(error::HostExecutionError::DeviceLost)
+            }
+            err => {
+                error!("Error while waiting for the context to idle: {:?}", err);
+                Ok(())
+            }
+        }
+    }
 }
 
 /// Single-threaded `Arc`.
@@ -209,6 +385,7 @@ unsafe impl<T: ?Sized> Sync for Starc<T> {}
 
 impl<T: ?Sized> Deref for Starc<T> {
     type Target = T;
+    #[inline]
     fn deref(&self) -> &T {
         assert_eq!(thread::current().id(), self.thread);
         &*self.arc
@@ -235,16 +412,26 @@ unsafe impl<T: ?Sized> Sync for Wstarc<T> {}
 pub struct PhysicalDevice(Starc<Share>);
 
 impl PhysicalDevice {
-    fn new_adapter<F>(fn_proc: F) -> hal::Adapter<Backend>
+    /// Create the adapter of the current context, created through
+    /// `platform`.
+    fn new_adapter<F>(
+        mut fn_proc: F,
+        platform: ext::Platform,
+        options: &AdapterOptions,
+    ) -> Result<hal::Adapter<Backend>, Error>
     where
         F: FnMut(&str) -> *const std::os::raw::c_void,
     {
-        let gl = GlContainer {
-            context: gl::Gl::load_with(fn_proc),
-        };
+        #[cfg(not(feature = "call-log"))]
+        let context = gl::Gl::load_with(&mut fn_proc);
+        #[cfg(feature = "call-log")]
+        let context = gl::Gl::load_with(|name| call_log::wrap(name, fn_proc(name)));
+        let gl = GlContainer { context };
 
         // query information
-        let (info, features, legacy_features, limits, private_caps) = info::query_all(&gl);
+        let (info, features, legacy_features, limits, private_caps) = info::query_all(&gl, options);
+        let ext = ext::Extensions::load(&info, platform, fn_proc);
+        debug::register(&gl, &info);
         info!("Vendor: {:?}", info.platform_name.vendor);
         info!("Renderer: {:?}", info.platform_name.renderer);
         info!("Version: {:?}", info.version);
@@ -256,8 +443,6 @@ impl PhysicalDevice {
             debug!("- {}", *extension);
         }
         let name = info.platform_name.renderer.into();
-        let vendor: std::string::String = info.platform_name.vendor.into();
-        let renderer: std::string::String = info.platform_name.renderer.into();
 
         // create the shared context
         let share = Share {
@@ -267,117 +452,161 @@ impl PhysicalDevice {
             legacy_features,
             limits,
             private_caps,
+            ext,
             open: Cell::new(false),
+            error_check: Cell::new(ErrorCheck::default()),
+            validation: Cell::new(false),
+            lost: Cell::new(false),
+            frame_statistics: Cell::default(),
+            last_frame_statistics: Cell::default(),
+            vertex_layouts: RefCell::default(),
+            next_vertex_layout: Cell::new(0),
+            vertex_array_epoch: Cell::new(0),
+            samplers: RefCell::default(),
+            program_cache: RefCell::new(None),
+            buffer_shadows: RefCell::default(),
+            uniform_block_members: RefCell::default(),
+            resolve_targets: RefCell::default(),
+            bindless_handles: RefCell::default(),
+            last_queue: Cell::new(None),
         };
         if let Err(err) = share.check() {
-            panic!("Error querying info: {:?}", err);
+            error!("Error querying info: {:?}", err);
+            return Err(err);
+        }
+        // Let the driver pick the number of threads compiling shaders.
+        if let Some(parallel) = share.ext.parallel_shader_compile {
+            unsafe { (parallel.max_shader_compiler_threads)(!0) };
         }
 
-        // opengl has no way to discern device_type, so we can try to infer it from the renderer string
-        let vendor_lower = vendor.to_lowercase();
-        let renderer_lower = renderer.to_lowercase();
-        let strings_that_imply_integrated = [
-            " xpress", // space here is on purpose so we don't match express
-            "radeon hd 4200",
-            "radeon hd 4250",
-            "radeon hd 4290",
-            "radeon hd 4270",
-            "radeon hd 4225",
-            "radeon hd 3100",
-            "radeon hd 3200",
-            "radeon hd 3000",
-            "radeon hd 3300",
-            "radeon(tm) r4 graphics",
-            "radeon(tm) r5 graphics",
-            "radeon(tm) r6 graphics",
-            "radeon(tm) r7 graphics",
-            "radeon r7 graphics",
-            "nforce", // all nvidia nforce are integrated
-            "tegra",  // all nvidia tegra are integrated
-            "shield", // all nvidia shield are integrated
-            "igp",
-            "mali",
-            "intel",
-        ];
-        // todo: Intel will release a discrete gpu soon, and we will need to update this logic when they do
-        let inferred_device_type = if vendor_lower.contains("qualcomm")
-            || vendor_lower.contains("intel")
-            || strings_that_imply_integrated
-                .into_iter()
-                .any(|&s| renderer_lower.contains(s))
-        {
-            hal::adapter::DeviceType::IntegratedGpu
-        } else {
-            hal::adapter::DeviceType::DiscreteGpu
-        };
-
-        // source: Sascha Willems at Vulkan
-        let vendor_id = if vendor_lower.contains("amd") {
-            0x1002
-        } else if vendor_lower.contains("imgtec") {
-            0x1010
-        } else if vendor_lower.contains("nvidia") {
-            0x10DE
-        } else if vendor_lower.contains("arm") {
-            0x13B5
-        } else if vendor_lower.contains("qualcomm") {
-            0x5143
-        } else if vendor_lower.contains("intel") {
-            0x8086
-        } else {
-            0
-        };
-
-        hal::Adapter {
+        // Prefer the IDs reported by the driver, if any.
+        let query_renderer = share.ext.mesa_query_renderer;
+        let vendor_id = query_renderer
+            .and_then(|query| query.vendor_id())
+            .unwrap_or_else(|| share.info.platform_name.vendor_id());
+        let device_id = query_renderer
+            .and_then(|query| query.device_id())
+            .unwrap_or(0);
+        // A unified memory architecture is a good hint for integrated GPUs.
+        let device_type = share
+            .info
+            .platform_name
+            .device_type_with_memory(query_renderer.and_then(|query| query.unified_memory()));
+
+        Ok(hal::Adapter {
             info: hal::AdapterInfo {
                 name,
                 vendor: vendor_id,
-                device: 0,
-                device_type: inferred_device_type,
+                device: device_id,
+                device_type,
             },
             physical_device: PhysicalDevice(Starc::new(share)),
-            queue_families: vec![QueueFamily],
-        }
+            queue_families: vec![QueueFamily::General, QueueFamily::Transfer],
+        })
     }
 
     /// Get GL-specific legacy feature flags.
     pub fn legacy_features(&self) -> &info::LegacyFeatures {
         &self.0.legacy_features
     }
-}
 
-impl hal::PhysicalDevice<Backend> for PhysicalDevice {
-    unsafe fn open(
+    /// Get the HAL operations which are emulated or unsupported by the
+    /// implementation.
+    pub fn downlevel_properties(&self) -> DownlevelProperties {
+        DownlevelProperties::new(
+            self.0.features,
+            &self.0.limits,
+            self.0.legacy_features,
+            &self.0.private_caps,
+        )
+    }
+
+    /// Run a self-test of the implementation, reporting what works, what
+    /// is emulated and what fails on the current driver.
+    ///
+    /// The report is meant to be attached to bug reports. The checks run on
+    /// the context, so it has to be current on the calling thread.
+    pub fn diagnostics(&self) -> Diagnostics {
+        let report = diagnostics::run(&self.0, self.downlevel_properties());
+        // The checks changed the bindings behind the back of the queues.
+        self.0.last_queue.set(None);
+        report
+    }
+
+    /// Open the device with the subset of the requested features supported by
+    /// the adapter, instead of failing with `MissingFeature`.
+    ///
+    /// Returns the features which had to be dropped along with the device.
+    pub unsafe fn open_with_downgrade(
+        &self,
+        families: &[(&QueueFamily, &[hal::QueuePriority])],
+        requested_features: hal::Features,
+    ) -> Result<(hal::Gpu<Backend>, hal::Features), error::DeviceCreationError> {
+        let features = requested_features & hal::PhysicalDevice::features(self);
+        let dropped = requested_features - features;
+        if !dropped.is_empty() {
+            warn!(
+                "Opening device without the unsupported features {:?}",
+                dropped
+            );
+        }
+        hal::PhysicalDevice::open(self, families, features).map(|gpu| (gpu, dropped))
+    }
+
+    /// Open a logical device, with additional backend specific options.
+    pub unsafe fn open_with_options(
         &self,
         families: &[(&QueueFamily, &[hal::QueuePriority])],
         requested_features: hal::Features,
+        options: &DeviceOptions,
     ) -> Result<hal::Gpu<Backend>, error::DeviceCreationError> {
         // Can't have multiple logical devices at the same time
         // as they would share the same context.
         if self.0.open.get() {
             return Err(error::DeviceCreationError::TooManyObjects);
         }
-        self.0.open.set(true);
 
         // TODO: Check for support in the LeagcyFeatures struct too
-        if !self.features().contains(requested_features) {
+        if !hal::PhysicalDevice::features(self).contains(requested_features) {
             return Err(error::DeviceCreationError::MissingFeature);
         }
-
-        // initialize permanent states
-        let gl = &self.0.context;
-        if self
-            .0
-            .legacy_features
-            .contains(info::LegacyFeatures::SRGB_COLOR)
+        if let Some(&(family, priorities)) = families
+            .iter()
+            .find(|&&(_, priorities)| priorities.len() > MAX_QUEUES)
         {
-            // TODO: Find way to emulate this on older Opengl versions.
-
-            gl.Enable(gl::FRAMEBUFFER_SRGB);
+            error!(
+                "Requested {} queues of family {:?}, at most {} are supported",
+                priorities.len(),
+                hal::QueueFamily::id(family),
+                MAX_QUEUES
+            );
+            return Err(error::DeviceCreationError::InitializationFailed);
         }
 
-        gl.PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        self.0.error_check.set(options.error_check);
+        self.0.validation.set(options.validation);
+        #[cfg(feature = "call-log")]
+        call_log::set_categories(options.call_log);
+        #[cfg(feature = "trace")]
+        let recorder = options.trace_path.as_ref().and_then(|path| {
+            trace::Recorder::create(path)
+                .map_err(|err| error!("Failed to create trace {:?}: {}", path, err))
+                .map(|recorder| Starc::new(RefCell::new(recorder)))
+                .ok()
+        });
+        *self.0.program_cache.borrow_mut() = match options.program_cache_dir {
+            Some(ref dir) if self.0.private_caps.program_binary => {
+                Some(program_cache::ProgramCache::new(dir.clone(), &self.0.info))
+            }
+            Some(_) => {
+                warn!("Program binaries are not supported, disabling the program cache");
+                None
+            }
+            None => None,
+        };
 
+        // initialize permanent states
+        let gl = &self.0.context;
         if !self.0.info.version.is_embedded {
             gl.Enable(gl::PROGRAM_POINT_SIZE);
         }
@@ -390,19 +619,32 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
         }
 
         if let Err(err) = self.0.check() {
-            panic!("Error opening adapter: {:?}", err);
+            error!("Error opening adapter: {:?}", err);
+            if self.0.private_caps.vertex_array {
+                gl.DeleteVertexArrays(1, &vao);
+            }
+            return Err(match err {
+                Error::OutOfMemory => error::DeviceCreationError::OutOfDeviceMemory,
+                _ => error::DeviceCreationError::InitializationFailed,
+            });
         }
+        self.0.open.set(true);
 
+        let mut queue_id = 0;
         Ok(hal::Gpu {
             device: Device::new(self.0.clone()),
             queues: Queues::new(
                 families
                     .into_iter()
                     .map(|&(proto_family, priorities)| {
-                        assert_eq!(priorities.len(), 1);
                         let mut family = hal::backend::RawQueueGroup::new(proto_family.clone());
-                        let queue = queue::CommandQueue::new(&self.0, vao);
-                        family.add_queue(queue);
+                        for _ in priorities {
+                            let queue = queue::CommandQueue::new(&self.0, queue_id, vao);
+                            #[cfg(feature = "trace")]
+                            let queue = queue.with_recorder(recorder.clone());
+                            family.add_queue(queue);
+                            queue_id += 1;
+                        }
                         family
                     })
                     .collect(),
@@ -410,6 +652,21 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
         })
     }
 
+    /// Get the version of the driver, if it was exposed in the version string.
+    pub fn driver_version(&self) -> Option<info::DriverVersion> {
+        self.0.info.driver_version
+    }
+}
+
+impl hal::PhysicalDevice<Backend> for PhysicalDevice {
+    unsafe fn open(
+        &self,
+        families: &[(&QueueFamily, &[hal::QueuePriority])],
+        requested_features: hal::Features,
+    ) -> Result<hal::Gpu<Backend>, error::DeviceCreationError> {
+        self.open_with_options(families, requested_features, &DeviceOptions::default())
+    }
+
     fn format_properties(&self, _: Option<hal::format::Format>) -> hal::format::Properties {
         unimplemented!()
     }
@@ -471,17 +728,36 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct QueueFamily;
+/// Maximum number of queues exposed per family.
+///
+/// There is only a single context, so queues don't run in parallel but
+/// have their submissions serialized onto it.
+const MAX_QUEUES: usize = 16;
+
+/// Queue families exposed by the adapter.
+///
+/// Submissions of all families end up on the same context, the transfer
+/// family only exists to support applications doing asynchronous uploads.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueueFamily {
+    General,
+    Transfer,
+}
 
 impl hal::QueueFamily for QueueFamily {
     fn queue_type(&self) -> hal::QueueType {
-        hal::QueueType::General
+        match *self {
+            QueueFamily::General => hal::QueueType::General,
+            QueueFamily::Transfer => hal::QueueType::Transfer,
+        }
     }
     fn max_queues(&self) -> usize {
-        1
+        MAX_QUEUES
     }
     fn id(&self) -> QueueFamilyId {
-        QueueFamilyId(0)
+        match *self {
+            QueueFamily::General => QueueFamilyId(0),
+            QueueFamily::Transfer => QueueFamilyId(1),
+        }
     }
 }
@@ -0,0 +1,170 @@
+//! Mock GL function table, for unit testing the command translation.
+//!
+//! The mock reports a GL 4.5 core context without any extension. Calls
+//! changing the state or drawing are recorded on the calling thread with
+//! their arguments, which lets tests check the GL calls a command buffer
+//! translates into without a driver. Queries return fixed values and the
+//! objects created get increasing names.
+//!
+//! Functions missing from the table panic when called, the table has to be
+//! extended along with the tests.
+
+#![allow(non_snake_case)]
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::os::raw::c_void;
+use std::ptr;
+
+use crate::gl::{self, types::*};
+use crate::hal;
+use crate::{ext::Platform, AdapterOptions, Backend, Device, PhysicalDevice};
+
+thread_local! {
+    static CALLS: RefCell<Vec<String>> = RefCell::new(Vec::new());
+    static NEXT_NAME: Cell<GLuint> = Cell::new(1);
+}
+
+fn record(name: &str, args: &[&dyn fmt::Debug]) {
+    let args = args
+        .iter()
+        .map(|arg| format!("{:?}", arg))
+        .collect::<Vec<_>>()
+        .join(", ");
+    CALLS.with(|calls| calls.borrow_mut().push(format!("{}({})", name, args)));
+}
+
+/// Take the calls recorded on this thread since the last call, formatted as
+/// `Name(arg0, arg1, ..)` without the `gl` prefix.
+pub(crate) fn take_calls() -> Vec<String> {
+    CALLS.with(|calls| calls.replace(Vec::new()))
+}
+
+macro_rules! recorded {
+    ($($name:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            extern "system" fn $name($($arg: $ty),*) {
+                record(stringify!($name), &[$(&$arg),*]);
+            }
+        )*
+
+        fn load_recorded(name: &str) -> *const c_void {
+            match name {
+                $(concat!("gl", stringify!($name)) => $name as *const c_void,)*
+                _ => ptr::null(),
+            }
+        }
+    };
+}
+
+recorded! {
+    Enable(cap: GLenum);
+    Disable(cap: GLenum);
+    PixelStorei(pname: GLenum, param: GLint);
+    BindVertexArray(array: GLuint);
+    DeleteVertexArrays(n: GLsizei, arrays: *const GLuint);
+    BindBuffer(target: GLenum, buffer: GLuint);
+    DeleteBuffers(n: GLsizei, buffers: *const GLuint);
+    BindFramebuffer(target: GLenum, framebuffer: GLuint);
+    DeleteFramebuffers(n: GLsizei, framebuffers: *const GLuint);
+    Viewport(x: GLint, y: GLint, width: GLsizei, height: GLsizei);
+    ViewportArrayv(first: GLuint, count: GLsizei, v: *const GLfloat);
+    DepthRange(near: GLdouble, far: GLdouble);
+    DepthRangeArrayv(first: GLuint, count: GLsizei, v: *const GLdouble);
+    Scissor(x: GLint, y: GLint, width: GLsizei, height: GLsizei);
+    ScissorArrayv(first: GLuint, count: GLsizei, v: *const GLint);
+    BlendColor(red: GLfloat, green: GLfloat, blue: GLfloat, alpha: GLfloat);
+    DrawArrays(mode: GLenum, first: GLint, count: GLsizei);
+    DrawArraysInstanced(mode: GLenum, first: GLint, count: GLsizei, instancecount: GLsizei);
+    DrawElements(mode: GLenum, count: GLsizei, type_: GLenum, indices: *const c_void);
+    Flush();
+    Finish();
+}
+
+extern "system" fn GetError() -> GLenum {
+    gl::NO_ERROR
+}
+
+extern "system" fn GetGraphicsResetStatus() -> GLenum {
+    gl::NO_ERROR
+}
+
+extern "system" fn GetString(name: GLenum) -> *const GLubyte {
+    let string: &'static [u8] = match name {
+        gl::VENDOR => b"Mock\0",
+        gl::RENDERER => b"Mock GL\0",
+        gl::VERSION => b"4.5.0 Mock\0",
+        gl::SHADING_LANGUAGE_VERSION => b"4.50 Mock\0",
+        _ => return ptr::null(),
+    };
+    string.as_ptr()
+}
+
+extern "system" fn GetStringi(_name: GLenum, _index: GLuint) -> *const GLubyte {
+    // No extension is exposed.
+    ptr::null()
+}
+
+extern "system" fn GetIntegerv(pname: GLenum, data: *mut GLint) {
+    let value = match pname {
+        gl::MAX_TEXTURE_SIZE => 4096,
+        gl::MAX_ARRAY_TEXTURE_LAYERS => 256,
+        gl::MAX_TEXTURE_BUFFER_SIZE => 1 << 16,
+        gl::MAX_PATCH_VERTICES => 32,
+        gl::MAX_VIEWPORTS => 16,
+        gl::RESET_NOTIFICATION_STRATEGY => gl::NO_RESET_NOTIFICATION as _,
+        _ => 0,
+    };
+    unsafe { *data = value };
+}
+
+extern "system" fn GetIntegeri_v(_target: GLenum, _index: GLuint, data: *mut GLint) {
+    unsafe { *data = 0 };
+}
+
+extern "system" fn GetFloatv(pname: GLenum, data: *mut GLfloat) {
+    let value = match pname {
+        gl::MAX_TEXTURE_LOD_BIAS => 16.0,
+        _ => 0.0,
+    };
+    unsafe { *data = value };
+}
+
+extern "system" fn GenNames(n: GLsizei, names: *mut GLuint) {
+    for i in 0..n as usize {
+        let name = NEXT_NAME.with(|next| next.replace(next.get() + 1));
+        unsafe { *names.add(i) = name };
+    }
+}
+
+/// Function loader of the mock, to be passed to `gl::Gl::load_with`.
+pub(crate) fn load(name: &str) -> *const c_void {
+    match name {
+        "glGetError" => GetError as *const c_void,
+        "glGetGraphicsResetStatus" => GetGraphicsResetStatus as *const c_void,
+        "glGetString" => GetString as *const c_void,
+        "glGetStringi" => GetStringi as *const c_void,
+        "glGetIntegerv" => GetIntegerv as *const c_void,
+        "glGetIntegeri_v" => GetIntegeri_v as *const c_void,
+        "glGetFloatv" => GetFloatv as *const c_void,
+        "glGenBuffers"
+        | "glCreateBuffers"
+        | "glGenFramebuffers"
+        | "glCreateFramebuffers"
+        | "glGenVertexArrays"
+        | "glCreateVertexArrays"
+        | "glGenTextures"
+        | "glGenSamplers"
+        | "glCreateSamplers" => GenNames as *const c_void,
+        _ => load_recorded(name),
+    }
+}
+
+/// Open a device on the mock, with a single queue of the general family.
+pub(crate) fn open() -> (Device, hal::QueueGroup<Backend, hal::Graphics>) {
+    let options = AdapterOptions::default();
+    let adapter = PhysicalDevice::new_adapter(load, Platform::Other, &options).unwrap();
+    let (device, queue_group) = adapter.open_with::<_, hal::Graphics>(1, |_| true).unwrap();
+    take_calls();
+    (device, queue_group)
+}
@@ -1,12 +1,18 @@
 use std::cell::Cell;
+use std::collections::VecDeque;
+use std::mem;
+use std::ops::Range;
+use std::os::raw::{c_int, c_void};
 use std::sync::{Arc, Mutex, RwLock};
 
 use crate::hal::backend::FastHashMap;
+use crate::hal::device::OutOfMemory;
 use crate::hal::memory::{Properties, Requirements};
 use crate::hal::{format, image as i, pass, pso};
 
 use crate::gl;
-use crate::Backend;
+use crate::range_alloc::RangeAllocator;
+use crate::{state, Backend};
 
 pub type RawBuffer = gl::types::GLuint;
 pub type Shader = gl::types::GLuint;
@@ -16,6 +22,34 @@ pub type Surface = gl::types::GLuint;
 pub type Texture = gl::types::GLuint;
 pub type Sampler = gl::types::GLuint;
 
+/// `EGLDisplay` handle.
+pub type EglDisplay = *mut c_void;
+/// `EGLContext` handle.
+pub type EglContext = *mut c_void;
+/// `EGLImageKHR` handle.
+pub type EglImage = *mut c_void;
+
+/// Memory imported from another API, see `Device::import_memory_fd`.
+///
+/// Images and buffers can be created on it, and have to be destroyed before
+/// the memory.
+#[derive(Debug)]
+pub struct ExternalMemory {
+    pub(crate) raw: gl::types::GLuint,
+    pub(crate) size: u64,
+}
+
+/// Plane of a Linux dma-buf, see `Device::import_dma_buf`.
+#[derive(Clone, Copy, Debug)]
+pub struct DmaBufPlane {
+    /// File descriptor of the dma-buf, which isn't closed by the import.
+    pub fd: c_int,
+    /// Offset of the plane in the dma-buf, in bytes.
+    pub offset: u32,
+    /// Size of a row of the plane, in bytes.
+    pub pitch: u32,
+}
+
 pub type DescriptorSetLayout = Vec<pso::DescriptorSetLayoutBinding>;
 
 pub const DEFAULT_FRAMEBUFFER: FrameBuffer = 0;
@@ -25,26 +59,73 @@ pub struct Buffer {
     pub(crate) raw: RawBuffer,
     pub(crate) target: gl::types::GLenum,
     pub(crate) requirements: Requirements,
+    // Imported objects are left alive on destruction.
+    pub(crate) owned: bool,
+}
+
+impl Buffer {
+    /// Name of the GL buffer object.
+    pub fn raw(&self) -> RawBuffer {
+        self.raw
+    }
+
+    /// Target the buffer object is bound to when accessing its data.
+    pub fn target(&self) -> gl::types::GLenum {
+        self.target
+    }
 }
 
 #[derive(Debug)]
 pub struct BufferView;
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum FenceState {
+    /// Not signalled, and no submission will signal it.
+    Idle,
+    /// Signalled once the GPU reaches the sync object.
+    Pending(gl::types::GLsync),
+    /// Signalled, the sync object has already been released.
+    Signalled,
+}
+
 #[derive(Debug)]
-pub struct Fence(pub(crate) Cell<gl::types::GLsync>);
+pub struct Fence(pub(crate) Cell<FenceState>);
 unsafe impl Send for Fence {}
 unsafe impl Sync for Fence {}
 
 impl Fence {
-    pub(crate) fn new(sync: gl::types::GLsync) -> Self {
-        Fence(Cell::new(sync))
+    pub(crate) fn new(state: FenceState) -> Self {
+        Fence(Cell::new(state))
     }
 }
 
+/// Monotonically increasing counter, advanced by queue submissions.
+///
+/// Each signalled value is backed by a sync object. Work submitted to the
+/// context completes in order, so reaching a value implies reaching all the
+/// values signalled before it.
+#[derive(Debug)]
+pub struct Timeline(pub(crate) Mutex<TimelineState>);
+// Sync objects are shared by all the contexts of the device, and are only
+// accessed with the lock held.
+unsafe impl Send for Timeline {}
+unsafe impl Sync for Timeline {}
+
+#[derive(Debug)]
+pub(crate) struct TimelineState {
+    // Last value known to be reached.
+    pub signalled: u64,
+    // Last value a signal was submitted for.
+    pub submitted: u64,
+    // Values not known to be reached yet, in increasing order.
+    pub pending: VecDeque<(u64, gl::types::GLsync)>,
+}
+
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 pub enum BindingTypes {
     Images,
     UniformBuffers,
+    StorageImages,
 }
 
 #[derive(Clone, Debug)]
@@ -66,6 +147,15 @@ pub struct DescRemapData {
         ),
     >,
     next_binding: FastHashMap<BindingTypes, pso::DescriptorBinding>,
+    /// Units assigned to the combinations of separate images and samplers,
+    /// keyed by the (set, binding) of the image and of the sampler.
+    combined: FastHashMap<
+        (
+            (pso::DescriptorSetIndex, pso::DescriptorBinding),
+            (pso::DescriptorSetIndex, pso::DescriptorBinding),
+        ),
+        pso::DescriptorBinding,
+    >,
 }
 
 /// Stores where the descriptor bindings have been remaped too.
@@ -77,6 +167,7 @@ impl DescRemapData {
             bindings: FastHashMap::default(),
             names: FastHashMap::default(),
             next_binding: FastHashMap::default(),
+            combined: FastHashMap::default(),
         }
     }
 
@@ -117,6 +208,23 @@ impl DescRemapData {
         &*val
     }
 
+    /// Get the unit shared by a separate image and sampler pair, assigning
+    /// a new one if the pair hasn't been seen by any shader stage yet.
+    pub fn combine_image_sampler(
+        &mut self,
+        image: (pso::DescriptorSetIndex, pso::DescriptorBinding),
+        sampler: (pso::DescriptorSetIndex, pso::DescriptorBinding),
+    ) -> pso::DescriptorBinding {
+        if let Some(&nb) = self.combined.get(&(image, sampler)) {
+            return nb;
+        }
+        let nb = self.reserve_binding(BindingTypes::Images);
+        self.insert_missing_binding(nb, BindingTypes::Images, image.0, image.1);
+        self.insert_missing_binding(nb, BindingTypes::Images, sampler.0, sampler.1);
+        self.combined.insert((image, sampler), nb);
+        nb
+    }
+
     pub fn get_binding(
         &self,
         btype: BindingTypes,
@@ -127,14 +235,87 @@ impl DescRemapData {
     }
 }
 
+/// Error creating a `BindlessTable`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BindlessTableError {
+    /// Bindless textures are not supported by the implementation.
+    Unsupported,
+    /// Out of memory for the buffer of the table.
+    OutOfMemory(OutOfMemory),
+    /// The entry is out of the range of the table.
+    IndexOutOfRange,
+    /// The view can't be sampled, its image is a render target only.
+    InvalidView,
+}
+
+/// Table of resident bindless texture handles (`GL_ARB_bindless_texture`),
+/// stored in a uniform buffer that can be bound as a regular
+/// `DescriptorType::UniformBuffer` descriptor.
+///
+/// Each entry is a `uvec4` with the 64-bit handle in `xy`, matching the std140
+/// array stride. Shaders have to be written in GLSL with the extension enabled:
+///
+/// ```glsl
+/// layout(std140) uniform Textures { uvec4 handles[N]; };
+/// vec4 color = texture(sampler2D(handles[index].xy), uv);
+/// ```
+#[derive(Debug)]
+pub struct BindlessTable {
+    pub(crate) buffer: Buffer,
+    pub(crate) entries: Vec<Option<u64>>,
+}
+
+/// Resident bindless texture handle, released once no table entry references
+/// it anymore or its texture or sampler is destroyed.
+#[derive(Debug)]
+pub(crate) struct BindlessHandle {
+    pub(crate) texture: Texture,
+    pub(crate) sampler: Option<Sampler>,
+    /// Number of table entries referencing the handle.
+    pub(crate) entries: usize,
+}
+
+impl BindlessTable {
+    /// The uniform buffer holding the handles.
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    /// Number of entries in the table.
+    pub fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct GraphicsPipeline {
     pub(crate) program: Program,
     pub(crate) primitive: gl::types::GLenum,
     pub(crate) patch_size: Option<gl::types::GLint>,
-    pub(crate) blend_targets: Vec<pso::ColorBlendDesc>,
-    pub(crate) attributes: Vec<AttributeDesc>,
-    pub(crate) vertex_buffers: Vec<Option<pso::VertexBufferDesc>>,
+    pub(crate) rasterizer: state::RasterizerState,
+    pub(crate) depth: state::DepthState,
+    // Stencil state is only translated once the dynamic references are known.
+    pub(crate) stencil: pso::StencilTest,
+    pub(crate) cull_face: pso::Face,
+    pub(crate) blend_targets: Vec<state::BlendSlotState>,
+    // Static blend constants, the ones set dynamically are used otherwise.
+    pub(crate) blend_color: Option<pso::ColorValue>,
+    // Static depth bounds, the ones set dynamically are used otherwise.
+    pub(crate) depth_bounds: Option<Range<f32>>,
+    pub(crate) vertex_layout: VertexLayoutId,
+    // Vertex buffer binding of each attribute of the layout.
+    pub(crate) vertex_bindings: Vec<gl::types::GLuint>,
+    // Formats of the color attachments of the subpass, and number of
+    // descriptor sets of the layout, checked by the validation.
+    pub(crate) color_formats: Vec<Option<format::Format>>,
+    pub(crate) num_sets: usize,
+}
+
+impl GraphicsPipeline {
+    /// Name of the GL program object.
+    pub fn raw_program(&self) -> Program {
+        self.program
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -142,15 +323,77 @@ pub struct ComputePipeline {
     pub(crate) program: Program,
 }
 
+impl ComputePipeline {
+    /// Name of the GL program object.
+    pub fn raw_program(&self) -> Program {
+        self.program
+    }
+}
+
+/// Binding of a uniform or uniform block, resolved when linking programs
+/// whose shaders don't specify their bindings.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum UniformBinding {
+    /// Sampler or image uniform, assigned to a texture or image unit.
+    Uniform {
+        location: gl::types::GLint,
+        binding: pso::DescriptorBinding,
+    },
+    /// Uniform block, assigned to a uniform buffer binding point.
+    Block {
+        index: gl::types::GLuint,
+        binding: pso::DescriptorBinding,
+    },
+    /// Member of a uniform block emulated with a plain uniform, loaded from
+    /// `offset` in the uniform buffer bound at `binding`.
+    BlockMember {
+        location: gl::types::GLint,
+        binding: pso::DescriptorBinding,
+        offset: u32,
+        ty: gl::types::GLenum,
+        count: gl::types::GLint,
+    },
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct Image {
     pub(crate) kind: ImageKind,
+    // Extent and number of layers, to compute subresource footprints.
+    pub(crate) dimensions: i::Kind,
     // Required for clearing operations
     pub(crate) channel: format::ChannelType,
+    pub(crate) format: format::Format,
     pub(crate) requirements: Requirements,
+    // Imported objects are left alive on destruction.
+    pub(crate) owned: bool,
+}
+
+impl Image {
+    /// GL object backing the image.
+    pub fn raw(&self) -> ImageKind {
+        self.kind
+    }
+
+    /// Name of the GL texture object, if the image is backed by a texture.
+    pub fn raw_texture(&self) -> Option<Texture> {
+        match self.kind {
+            ImageKind::Texture(texture) => Some(texture),
+            ImageKind::Surface(_) => None,
+        }
+    }
+
+    /// Name of the GL renderbuffer object, if the image is backed by a
+    /// renderbuffer.
+    pub fn raw_renderbuffer(&self) -> Option<Surface> {
+        match self.kind {
+            ImageKind::Surface(surface) => Some(surface),
+            ImageKind::Texture(_) => None,
+        }
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "trace", derive(Serialize, Deserialize))]
 pub enum ImageKind {
     Surface(Surface),
     Texture(Texture),
@@ -165,55 +408,337 @@ pub enum FatSampler {
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "trace", derive(Serialize, Deserialize))]
 pub enum ImageView {
     Surface(Surface),
-    Texture(Texture, i::Level),
-    TextureLayer(Texture, i::Level, i::Layer),
+    Texture(Texture, i::Level, format::Format),
+    TextureLayer(Texture, i::Level, i::Layer, format::Format),
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
-pub(crate) enum DescSetBindings {
-    Buffer {
-        ty: BindingTypes,
+/// Range of a buffer bound to a descriptor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct BufferBinding {
+    pub buffer: RawBuffer,
+    pub offset: gl::types::GLintptr,
+    pub size: gl::types::GLsizeiptr,
+}
+
+/// Texture level bound to a storage image descriptor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct StorageImageBinding {
+    pub texture: Texture,
+    pub level: i::Level,
+    pub layer: Option<i::Layer>,
+    pub format: gl::types::GLenum,
+}
+
+/// Descriptors written to a set, resolved into flat tables of GL objects
+/// keyed by their binding, so that binding the set is a loop over them.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DescSetBindings {
+    pub uniform_buffers: Vec<(pso::DescriptorBinding, BufferBinding)>,
+    pub textures: Vec<(pso::DescriptorBinding, Texture)>,
+    pub storage_images: Vec<(pso::DescriptorBinding, StorageImageBinding)>,
+    pub samplers: Vec<(pso::DescriptorBinding, Sampler)>,
+    pub sampler_infos: Vec<(pso::DescriptorBinding, i::SamplerInfo)>,
+}
+
+// Set the entry of a binding, replacing the previous one.
+fn write_entry<T>(
+    table: &mut Vec<(pso::DescriptorBinding, T)>,
+    binding: pso::DescriptorBinding,
+    value: T,
+) {
+    match table.iter_mut().find(|&&mut (b, _)| b == binding) {
+        Some(entry) => entry.1 = value,
+        None => table.push((binding, value)),
+    }
+}
+
+// Remove the entry of a binding, if any.
+fn clear_entry<T>(table: &mut Vec<(pso::DescriptorBinding, T)>, binding: pso::DescriptorBinding) {
+    table.retain(|&(b, _)| b != binding);
+}
+
+// Copy the entry of the `src` binding to the `dst` binding of another table.
+fn copy_entry<T: Clone>(
+    table: &mut Vec<(pso::DescriptorBinding, T)>,
+    src_table: &[(pso::DescriptorBinding, T)],
+    src: pso::DescriptorBinding,
+    dst: pso::DescriptorBinding,
+) {
+    if let Some(&(_, ref value)) = src_table.iter().find(|&&(b, _)| b == src) {
+        write_entry(table, dst, value.clone());
+    }
+}
+
+impl DescSetBindings {
+    pub(crate) fn write_uniform_buffer(
+        &mut self,
         binding: pso::DescriptorBinding,
-        buffer: RawBuffer,
-        offset: gl::types::GLintptr,
-        size: gl::types::GLsizeiptr,
-    },
-    Texture(pso::DescriptorBinding, Texture),
-    Sampler(pso::DescriptorBinding, Sampler),
-    SamplerInfo(pso::DescriptorBinding, i::SamplerInfo),
+        buffer: BufferBinding,
+    ) {
+        write_entry(&mut self.uniform_buffers, binding, buffer);
+    }
+
+    pub(crate) fn write_texture(&mut self, binding: pso::DescriptorBinding, texture: Texture) {
+        write_entry(&mut self.textures, binding, texture);
+    }
+
+    pub(crate) fn write_storage_image(
+        &mut self,
+        binding: pso::DescriptorBinding,
+        image: StorageImageBinding,
+    ) {
+        write_entry(&mut self.storage_images, binding, image);
+    }
+
+    pub(crate) fn write_sampler(&mut self, binding: pso::DescriptorBinding, sampler: &FatSampler) {
+        match *sampler {
+            FatSampler::Sampler(sampler) => {
+                write_entry(&mut self.samplers, binding, sampler);
+                clear_entry(&mut self.sampler_infos, binding);
+            }
+            FatSampler::Info(ref info) => {
+                write_entry(&mut self.sampler_infos, binding, info.clone());
+                clear_entry(&mut self.samplers, binding);
+            }
+        }
+    }
+
+    /// Remove the descriptors of a binding from all the tables.
+    pub(crate) fn clear_binding(&mut self, binding: pso::DescriptorBinding) {
+        clear_entry(&mut self.uniform_buffers, binding);
+        clear_entry(&mut self.textures, binding);
+        clear_entry(&mut self.storage_images, binding);
+        clear_entry(&mut self.samplers, binding);
+        clear_entry(&mut self.sampler_infos, binding);
+    }
+
+    /// Copy the descriptors of the `src` binding of another set to the
+    /// `dst` binding of this one, replacing whatever `dst` held.
+    pub(crate) fn copy_binding(
+        &mut self,
+        other: &DescSetBindings,
+        src: pso::DescriptorBinding,
+        dst: pso::DescriptorBinding,
+    ) {
+        self.clear_binding(dst);
+        copy_entry(&mut self.uniform_buffers, &other.uniform_buffers, src, dst);
+        copy_entry(&mut self.textures, &other.textures, src, dst);
+        copy_entry(&mut self.storage_images, &other.storage_images, src, dst);
+        copy_entry(&mut self.samplers, &other.samplers, src, dst);
+        copy_entry(&mut self.sampler_infos, &other.sampler_infos, src, dst);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.uniform_buffers.clear();
+        self.textures.clear();
+        self.storage_images.clear();
+        self.samplers.clear();
+        self.sampler_infos.clear();
+    }
+}
+
+/// Descriptor amounts, split by the kind of GL object they end up bound as.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct DescriptorCounts<T> {
+    pub samplers: T,
+    pub textures: T,
+    pub buffers: T,
+}
+
+impl DescriptorCounts<usize> {
+    pub(crate) fn add(&mut self, ty: pso::DescriptorType, count: usize) {
+        use crate::hal::pso::DescriptorType as Dt;
+        match ty {
+            Dt::Sampler => self.samplers += count,
+            Dt::CombinedImageSampler => {
+                self.samplers += count;
+                self.textures += count;
+            }
+            Dt::SampledImage
+            | Dt::StorageImage
+            | Dt::InputAttachment
+            | Dt::UniformTexelBuffer
+            | Dt::StorageTexelBuffer => self.textures += count,
+            Dt::UniformBuffer
+            | Dt::StorageBuffer
+            | Dt::UniformBufferDynamic
+            | Dt::StorageBufferDynamic => self.buffers += count,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct DescriptorSet {
-    layout: DescriptorSetLayout,
-    pub(crate) bindings: Arc<Mutex<Vec<DescSetBindings>>>,
+    pub(crate) layout: DescriptorSetLayout,
+    /// Ranges of the pool capacity taken by this set.
+    ranges: DescriptorCounts<Range<usize>>,
+    pub(crate) bindings: Arc<Mutex<DescSetBindings>>,
 }
 
+/// OpenGL doesn't have a meaningful concept of a descriptor pool, but we still
+/// track its capacity so that pool sizing bugs surface the same way as they
+/// would on other backends.
 #[derive(Debug)]
-pub struct DescriptorPool {}
+pub struct DescriptorPool {
+    max_sets: usize,
+    flags: pso::DescriptorPoolCreateFlags,
+    capacity: DescriptorCounts<usize>,
+    allocators: DescriptorCounts<RangeAllocator<usize>>,
+    /// Binding storage of the sets currently allocated from this pool.
+    allocated: Vec<Arc<Mutex<DescSetBindings>>>,
+    /// Binding storage of freed sets, ready to be handed out again.
+    recycled: Vec<Arc<Mutex<DescSetBindings>>>,
+}
+
+impl DescriptorPool {
+    pub(crate) fn new(
+        max_sets: usize,
+        capacity: DescriptorCounts<usize>,
+        flags: pso::DescriptorPoolCreateFlags,
+    ) -> Self {
+        DescriptorPool {
+            max_sets,
+            flags,
+            allocators: Self::create_allocators(&capacity),
+            capacity,
+            allocated: Vec::new(),
+            recycled: Vec::new(),
+        }
+    }
+
+    fn create_allocators(
+        capacity: &DescriptorCounts<usize>,
+    ) -> DescriptorCounts<RangeAllocator<usize>> {
+        DescriptorCounts {
+            samplers: RangeAllocator::new(0..capacity.samplers),
+            textures: RangeAllocator::new(0..capacity.textures),
+            buffers: RangeAllocator::new(0..capacity.buffers),
+        }
+    }
+
+    fn recycle(&mut self, bindings: Arc<Mutex<DescSetBindings>>) {
+        bindings.lock().unwrap().clear();
+        self.recycled.push(bindings);
+    }
+
+    fn allocate_range(
+        allocator: &mut RangeAllocator<usize>,
+        count: usize,
+    ) -> Result<Range<usize>, pso::AllocationError> {
+        if count == 0 {
+            return Ok(0..0);
+        }
+        allocator.allocate_range(count).map_err(|e| {
+            if e.fragmented_free_length >= count {
+                pso::AllocationError::FragmentedPool
+            } else {
+                pso::AllocationError::OutOfPoolMemory
+            }
+        })
+    }
+
+    fn free_range(allocator: &mut RangeAllocator<usize>, range: Range<usize>) {
+        if range.start != range.end {
+            allocator.free_range(range);
+        }
+    }
+
+    fn free_ranges(&mut self, ranges: DescriptorCounts<Range<usize>>) {
+        Self::free_range(&mut self.allocators.samplers, ranges.samplers);
+        Self::free_range(&mut self.allocators.textures, ranges.textures);
+        Self::free_range(&mut self.allocators.buffers, ranges.buffers);
+    }
+}
 
 impl pso::DescriptorPool<Backend> for DescriptorPool {
     unsafe fn allocate_set(
         &mut self,
         layout: &DescriptorSetLayout,
     ) -> Result<DescriptorSet, pso::AllocationError> {
+        if self.allocated.len() >= self.max_sets {
+            return Err(pso::AllocationError::OutOfPoolMemory);
+        }
+
+        let mut total = DescriptorCounts::default();
+        for binding in layout {
+            total.add(binding.ty, binding.count);
+        }
+
+        let mut ranges = DescriptorCounts {
+            samplers: 0..0,
+            textures: 0..0,
+            buffers: 0..0,
+        };
+        let result = Self::allocate_range(&mut self.allocators.samplers, total.samplers)
+            .and_then(|range| {
+                ranges.samplers = range;
+                Self::allocate_range(&mut self.allocators.textures, total.textures)
+            })
+            .and_then(|range| {
+                ranges.textures = range;
+                Self::allocate_range(&mut self.allocators.buffers, total.buffers)
+            });
+        match result {
+            Ok(range) => ranges.buffers = range,
+            Err(e) => {
+                self.free_ranges(ranges);
+                return Err(e);
+            }
+        }
+
+        let bindings = self
+            .recycled
+            .pop()
+            .unwrap_or_else(|| Arc::new(Mutex::new(DescSetBindings::default())));
+        self.allocated.push(Arc::clone(&bindings));
         Ok(DescriptorSet {
             layout: layout.clone(),
-            bindings: Arc::new(Mutex::new(Vec::new())),
+            ranges,
+            bindings,
         })
     }
 
-    unsafe fn free_sets<I>(&mut self, _descriptor_sets: I)
+    unsafe fn free_sets<I>(&mut self, descriptor_sets: I)
     where
         I: IntoIterator<Item = DescriptorSet>,
     {
-        // Poof!  Does nothing, because OpenGL doesn't have a meaningful concept of a `DescriptorSet`.
+        if !self
+            .flags
+            .contains(pso::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+        {
+            error!(
+                "Descriptor sets can't be freed from a pool created without `FREE_DESCRIPTOR_SET`"
+            );
+            return;
+        }
+
+        for set in descriptor_sets {
+            let index = match self
+                .allocated
+                .iter()
+                .position(|bindings| Arc::ptr_eq(bindings, &set.bindings))
+            {
+                Some(index) => index,
+                None => {
+                    error!("Descriptor set was not allocated from this pool");
+                    continue;
+                }
+            };
+            let bindings = self.allocated.swap_remove(index);
+            self.free_ranges(set.ranges);
+            self.recycle(bindings);
+        }
     }
 
     unsafe fn reset(&mut self) {
-        // Poof!  Does nothing, because OpenGL doesn't have a meaningful concept of a `DescriptorSet`.
+        self.allocators = Self::create_allocators(&self.capacity);
+        let allocated = mem::replace(&mut self.allocated, Vec::new());
+        for bindings in allocated {
+            self.recycle(bindings);
+        }
     }
 }
 
@@ -262,40 +787,121 @@ pub struct RenderPass {
     pub(crate) subpasses: Vec<SubpassDesc>,
 }
 
+impl RenderPass {
+    /// Check if an attachment is resolved to by any of the sub-passes.
+    pub(crate) fn is_resolve_target(&self, at_id: pass::AttachmentId) -> bool {
+        self.subpasses
+            .iter()
+            .flat_map(|subpass| &subpass.resolve_attachments)
+            .any(|&(_, dst)| dst == at_id)
+    }
+
+    /// Check if an attachment is a color or input attachment of any of the
+    /// sub-passes.
+    pub(crate) fn is_subpass_attachment(&self, at_id: pass::AttachmentId) -> bool {
+        self.subpasses.iter().any(|subpass| {
+            subpass.color_attachments.contains(&at_id) || subpass.input_attachments.contains(&at_id)
+        })
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct SubpassDesc {
     pub(crate) color_attachments: Vec<usize>,
+    pub(crate) input_attachments: Vec<usize>,
+    /// Input attachments which aren't color attachments, bound to the draw
+    /// buffers following the color ones so they can be read with
+    /// framebuffer fetch. Empty if framebuffer fetch isn't used.
+    pub(crate) fetched_attachments: Vec<usize>,
+    /// Color attachments resolved at the end of the sub-pass, along with
+    /// the attachments they are resolved to.
+    pub(crate) resolve_attachments: Vec<(usize, usize)>,
 }
 
 impl SubpassDesc {
     /// Check if an attachment is used by this sub-pass.
     pub(crate) fn is_using(&self, at_id: pass::AttachmentId) -> bool {
-        self.color_attachments.iter().any(|id| *id == at_id)
+        self.color_attachments
+            .iter()
+            .chain(&self.input_attachments)
+            .any(|id| *id == at_id)
+    }
+
+    /// Attachments bound to the draw buffers, in the order of the fragment
+    /// output locations.
+    pub(crate) fn draw_attachments(&self) -> impl Iterator<Item = &usize> {
+        self.color_attachments
+            .iter()
+            .chain(&self.fetched_attachments)
+    }
+
+    /// Fragment output location through which an input attachment can be
+    /// read with framebuffer fetch.
+    pub(crate) fn fetch_location(&self, input_index: usize) -> Option<usize> {
+        let attachment = *self.input_attachments.get(input_index)?;
+        self.draw_attachments().position(|&id| id == attachment)
     }
 }
 
+/// Resolve attachments of a framebuffer, which can't be attached along with
+/// the multisampled ones. Each of them gets a framebuffer of its own, to be
+/// blitted to.
+#[derive(Debug)]
+pub(crate) struct ResolveTargets {
+    pub(crate) extent: i::Extent,
+    pub(crate) framebuffers: Vec<(pass::AttachmentId, FrameBuffer)>,
+}
+
 #[derive(Debug)]
 pub struct PipelineLayout {
     pub(crate) desc_remap_data: Arc<RwLock<DescRemapData>>,
+    pub(crate) num_sets: usize,
 }
 
+/// Semaphores are backed by sync objects inserted into the command stream
+/// on signal, which are then waited upon on the server side.
+///
+/// Semaphores imported from another API are backed by a GL semaphore object
+/// instead, see `Device::import_semaphore_fd`.
 #[derive(Debug)]
-// No inter-queue synchronization required for GL.
-pub struct Semaphore;
+pub struct Semaphore {
+    // `None` if the semaphore is unsignalled, or sync objects aren't
+    // supported.
+    pub(crate) sync: Mutex<Option<gl::types::GLsync>>,
+    pub(crate) external: Option<gl::types::GLuint>,
+}
+// Sync objects are shared by all the contexts of the device, and queues on
+// different threads only access them with the lock held.
+unsafe impl Send for Semaphore {}
+unsafe impl Sync for Semaphore {}
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct AttributeDesc {
     pub(crate) location: gl::types::GLuint,
     pub(crate) offset: u32,
-    pub(crate) binding: gl::types::GLuint,
     pub(crate) size: gl::types::GLint,
     pub(crate) format: gl::types::GLenum,
     pub(crate) vertex_attrib_fn: VertexAttribFunction,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum VertexAttribFunction {
     Float,   // glVertexAttribPointer
     Integer, // glVertexAttribIPointer
     Double,  // glVertexAttribLPointer
 }
+
+/// Vertex attribute, along with the layout of the buffer it is sourced from.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VertexAttribute {
+    pub(crate) desc: AttributeDesc,
+    pub(crate) stride: gl::types::GLsizei,
+    pub(crate) rate: gl::types::GLuint,
+}
+
+/// Vertex attributes of a graphics pipeline.
+pub type VertexLayout = Vec<VertexAttribute>;
+
+/// Index of a vertex layout registered in the device. Pipelines with
+/// identical layouts share the same index, and thus the same vertex arrays.
+pub type VertexLayoutId = u32;
@@ -54,6 +54,7 @@ pub enum BufferMemory {
 pub struct RawCommandPool {
     pub(crate) fbo: Option<n::FrameBuffer>,
     pub(crate) limits: command::Limits,
+    pub(crate) validation: bool,
     pub(crate) memory: Arc<Mutex<BufferMemory>>,
 }
 
@@ -80,7 +81,7 @@ impl pool::RawCommandPool<Backend> for RawCommandPool {
 
     fn allocate_one(&mut self, _level: hal::command::RawLevel) -> RawCommandBuffer {
         // TODO: Implement secondary buffers
-        RawCommandBuffer::new(self.fbo, self.limits, self.memory.clone())
+        RawCommandBuffer::new(self.fbo, self.limits, self.validation, self.memory.clone())
     }
 
     unsafe fn free<I>(&mut self, buffers: I)
@@ -0,0 +1,144 @@
+//! On-disk cache of linked program binaries.
+//!
+//! Programs are keyed by a hash of their translated shaders, which covers the
+//! SPIR-V modules as well as the specialization and the remapped bindings,
+//! and of the renderer and driver version strings: binaries are only valid for
+//! the implementation which produced them.
+
+use std::fs;
+use std::path::PathBuf;
+
+use crate::gl;
+use crate::hal::pso;
+use crate::info::Info;
+use crate::native as n;
+use crate::GlContainer;
+
+/// FNV-1a, stable across runs and toolchains unlike the std hasher.
+#[derive(Clone, Copy)]
+struct Hasher(u64);
+
+impl Hasher {
+    fn new() -> Self {
+        Hasher(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        // Separate consecutive writes.
+        self.0 ^= 0xff;
+        self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ProgramCache {
+    dir: PathBuf,
+    // Hash of the implementation strings, the base of every key.
+    driver_hash: u64,
+}
+
+impl ProgramCache {
+    pub(crate) fn new(dir: PathBuf, info: &Info) -> Self {
+        let mut hasher = Hasher::new();
+        hasher.write(info.platform_name.vendor.as_bytes());
+        hasher.write(info.platform_name.renderer.as_bytes());
+        hasher.write(format!("{:?}", info.version).as_bytes());
+        hasher.write(format!("{:?}", info.shading_language).as_bytes());
+        if let Err(err) = fs::create_dir_all(&dir) {
+            warn!(
+                "Failed to create program cache directory {:?}: {}",
+                dir, err
+            );
+        }
+        ProgramCache {
+            dir,
+            driver_hash: hasher.0,
+        }
+    }
+
+    /// Key of a program made of the given shader sources.
+    pub(crate) fn key<'a, I>(&self, sources: I) -> u64
+    where
+        I: IntoIterator<Item = (pso::Stage, &'a str)>,
+    {
+        let mut hasher = Hasher(self.driver_hash);
+        for (stage, source) in sources {
+            hasher.write(&[stage as u8]);
+            hasher.write(source.as_bytes());
+        }
+        hasher.0
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.bin", key))
+    }
+
+    /// Try loading the binary of a program, returns `true` if the program
+    /// was successfully linked from it.
+    pub(crate) unsafe fn load(&self, gl: &GlContainer, program: n::Program, key: u64) -> bool {
+        let data = match fs::read(self.path(key)) {
+            Ok(data) => data,
+            Err(_) => return false,
+        };
+        if data.len() < 4 {
+            return false;
+        }
+        let mut format = [0; 4];
+        format.copy_from_slice(&data[..4]);
+        let binary = &data[4..];
+        gl.ProgramBinary(
+            program,
+            u32::from_le_bytes(format),
+            binary.as_ptr() as *const _,
+            binary.len() as _,
+        );
+
+        let mut status = 0;
+        gl.GetProgramiv(program, gl::LINK_STATUS, &mut status);
+        // Loading might fail after a driver update or when the binary was
+        // corrupted, the program is compiled from source again then.
+        if status == 0 {
+            debug!("Stale program binary {:016x}", key);
+            let _ = gl.GetError();
+            return false;
+        }
+        debug!("Loaded program binary {:016x}", key);
+        true
+    }
+
+    /// Store the binary of a linked program.
+    pub(crate) unsafe fn store(&self, gl: &GlContainer, program: n::Program, key: u64) {
+        let mut length = 0;
+        gl.GetProgramiv(program, gl::PROGRAM_BINARY_LENGTH, &mut length);
+        if length <= 0 {
+            return;
+        }
+        let mut data = vec![0u8; 4 + length as usize];
+        let mut format = 0;
+        let mut written = 0;
+        gl.GetProgramBinary(
+            program,
+            length,
+            &mut written,
+            &mut format,
+            data[4..].as_mut_ptr() as *mut _,
+        );
+        if written <= 0 {
+            return;
+        }
+        data.truncate(4 + written as usize);
+        data[..4].copy_from_slice(&format.to_le_bytes());
+
+        // Write to a temporary file first, so other instances of the
+        // application never load a partially written binary.
+        let path = self.path(key);
+        let temp = path.with_extension("tmp");
+        if let Err(err) = fs::write(&temp, &data).and_then(|()| fs::rename(&temp, &path)) {
+            warn!("Failed to store program binary {:?}: {}", path, err);
+        }
+    }
+}
@@ -3,16 +3,71 @@ use std::{mem, ptr, slice};
 use crate::Starc;
 
 use crate::hal;
+use crate::hal::backend::FastHashMap;
 use crate::hal::error;
 
 use crate::gl;
 use smallvec::SmallVec;
 
 use crate::info::LegacyFeatures;
-use crate::{command as com, device, native, state, window};
-use crate::{Backend, Share};
+#[cfg(feature = "trace")]
+use crate::trace;
+use crate::{command as com, conv, device, native, ring, state, window, worker};
+use crate::{Backend, ErrorCheck, GlContainer, Share};
+#[cfg(feature = "trace")]
+use std::cell::RefCell;
 
 pub type ArrayBuffer = gl::types::GLuint;
+type BufferRange = (
+    native::RawBuffer,
+    gl::types::GLintptr,
+    gl::types::GLsizeiptr,
+);
+
+/// Counters of the work executed by the queues during a frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameStatistics {
+    /// Draw calls, indexed or not.
+    pub draw_calls: u32,
+    /// Compute dispatches, direct or indirect.
+    pub dispatches: u32,
+    /// Changes of the fixed-function state or of the bound program.
+    pub state_changes: u32,
+    /// Vertex, index and uniform buffer binds.
+    pub buffer_binds: u32,
+    /// Texture, image and sampler binds.
+    pub texture_binds: u32,
+    /// Binds and state changes skipped because the resource was already
+    /// bound or the state already set.
+    pub redundant_binds: u32,
+    /// Framebuffer binds.
+    pub framebuffer_binds: u32,
+    /// Bytes copied into buffers and textures.
+    pub bytes_uploaded: u64,
+}
+
+// Vertex array object, caching the attribute pointers of a vertex layout.
+struct VertexArray {
+    // Zero if vertex array objects are not supported.
+    name: ArrayBuffer,
+    // Location of each attribute of the layout.
+    locations: Vec<gl::types::GLuint>,
+    // Buffer each attribute of the layout points to.
+    // None denotes that we don't know where it points to.
+    buffers: Vec<Option<gl::types::GLuint>>,
+    // Index buffer bound to the vertex array.
+    // None denotes that we don't know what is currently bound.
+    index_buffer: Option<gl::types::GLuint>,
+}
+
+impl VertexArray {
+    fn invalidate(&mut self) {
+        for buffer in &mut self.buffers {
+            *buffer = None;
+        }
+        self.index_buffer = None;
+    }
+}
 
 // State caching system for command queue.
 //
@@ -22,16 +77,38 @@ pub type ArrayBuffer = gl::types::GLuint;
 // This allows us to minimize additional driver calls to
 // ensure that command buffers are handled isolated of each other.
 struct State {
-    // Indicate if the vertex array object is bound.
-    // If VAOs are not supported, this will be also set to true.
-    vao: bool,
-    // Currently bound index/element buffer.
+    // Currently bound vertex array object.
     // None denotes that we don't know what is currently bound.
+    // If VAOs are not supported, this will be set to zero.
+    vertex_array: Option<ArrayBuffer>,
+    // Vertex arrays of each vertex layout used by the queue.
+    vertex_arrays: FastHashMap<native::VertexLayoutId, VertexArray>,
+    // Value of `Share::vertex_array_epoch` the vertex arrays are up to date
+    // with.
+    vertex_array_epoch: usize,
+    // Vertex layout whose attribute arrays are enabled, if VAOs are not
+    // supported and the arrays are part of the context state.
+    legacy_layout: Option<native::VertexLayoutId>,
+    // Index buffer used by the following indexed draws, which gets bound
+    // along with the vertex array.
     index_buffer: Option<gl::types::GLuint>,
+    // Fixed-function state set by the pipelines.
+    shadow: state::Shadow,
     // Currently set viewports.
     num_viewports: usize,
     // Currently set scissor rects.
     num_scissors: usize,
+    // Currently active texture unit.
+    // None denotes that we don't know which unit is active.
+    active_texture: Option<gl::types::GLuint>,
+    // Textures bound to the `TEXTURE_2D` target of each texture unit.
+    textures: Vec<Option<native::Texture>>,
+    // Sampler objects bound to each texture unit.
+    samplers: Vec<Option<native::Sampler>>,
+    // Buffer ranges bound to each indexed uniform buffer binding.
+    uniform_buffers: Vec<Option<BufferRange>>,
+    // Program used by the following draws.
+    program: Option<native::Program>,
 }
 
 impl State {
@@ -39,46 +116,141 @@ impl State {
     // as exposed by OpenGL.
     fn new() -> Self {
         State {
-            vao: false,
+            vertex_array: None,
+            vertex_arrays: FastHashMap::default(),
+            vertex_array_epoch: 0,
+            legacy_layout: None,
             index_buffer: None,
+            shadow: state::Shadow::default(),
             num_viewports: 0,
             num_scissors: 0,
+            active_texture: None,
+            textures: Vec::new(),
+            samplers: Vec::new(),
+            uniform_buffers: Vec::new(),
+            program: None,
         }
     }
 
     // Invalidate the current state, forcing a complete reset.
     // Required if we allow users to manually inject OpenGL calls.
     fn flush(&mut self) {
-        self.vao = false;
+        self.vertex_array = None;
+        self.legacy_layout = None;
         self.index_buffer = None;
+        self.program = None;
+        self.shadow.invalidate();
+        self.flush_bindings();
+        for vertex_array in self.vertex_arrays.values_mut() {
+            vertex_array.invalidate();
+        }
 
         // TOOD: reset viewports and scissors
         //       do we need to clear everything from 0..MAX_VIEWPORTS?
     }
+
+    // Forget about the bound resources.
+    //
+    // Objects get unbound when they are deleted and their names may be
+    // reused afterwards, so the cached bindings are only valid as long as
+    // no resources are destroyed in between. The vertex arrays are updated
+    // separately, when buffers or vertex layouts are destroyed.
+    fn flush_bindings(&mut self) {
+        self.active_texture = None;
+        self.textures.clear();
+        self.samplers.clear();
+        self.uniform_buffers.clear();
+    }
+
+    // Record `value` in the binding slot at `index`, returning whether it
+    // differs from what was bound before.
+    fn update_slot<T: Copy + PartialEq>(
+        slots: &mut Vec<Option<T>>,
+        index: gl::types::GLuint,
+        value: T,
+    ) -> bool {
+        let index = index as usize;
+        if slots.len() <= index {
+            slots.resize(index + 1, None);
+        }
+        if slots[index] == Some(value) {
+            false
+        } else {
+            slots[index] = Some(value);
+            true
+        }
+    }
 }
 
 pub struct CommandQueue {
     pub(crate) share: Starc<Share>,
+    // Unique among the queues of the device.
+    id: usize,
+    // Worker owning the context, if the queue was created on one.
+    worker: Option<worker::Handle>,
     vao: ArrayBuffer,
     state: State,
+    // Ring buffer for the buffer updates, created on first use.
+    ring: Option<ring::RingBuffer>,
+    // Trace of the device, shared by all its queues.
+    #[cfg(feature = "trace")]
+    recorder: Option<Starc<RefCell<trace::Recorder>>>,
+}
+
+impl Drop for CommandQueue {
+    fn drop(&mut self) {
+        let share = &self.share;
+        let ring = self.ring.take();
+        let vertex_arrays = &self.state.vertex_arrays;
+        let destroy = || unsafe {
+            if let Some(ring) = ring {
+                ring.destroy(&share.context);
+            }
+            for vertex_array in vertex_arrays.values() {
+                if vertex_array.name != 0 {
+                    share.context.DeleteVertexArrays(1, &vertex_array.name);
+                }
+            }
+        };
+        match self.worker {
+            Some(ref worker) if !worker.is_current() => unsafe { worker.run_unchecked(destroy) },
+            _ => destroy(),
+        }
+    }
 }
 
 impl CommandQueue {
     /// Create a new command queue.
-    pub(crate) fn new(share: &Starc<Share>, vao: ArrayBuffer) -> Self {
+    pub(crate) fn new(share: &Starc<Share>, id: usize, vao: ArrayBuffer) -> Self {
         CommandQueue {
             share: share.clone(),
+            id,
+            worker: worker::Handle::current(),
             vao,
             state: State::new(),
+            ring: None,
+            #[cfg(feature = "trace")]
+            recorder: None,
         }
     }
 
+    /// Record the submissions to the trace of the device.
+    #[cfg(feature = "trace")]
+    pub(crate) fn with_recorder(
+        mut self,
+        recorder: Option<Starc<RefCell<trace::Recorder>>>,
+    ) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
     /// Access the OpenGL directly via a closure. OpenGL types and enumerations
     /// can be found in the `gl` crate.
     ///
     /// > Note: Calling this function can have a noticeable impact on the performance
     ///         because the internal state cache will flushed.
     pub unsafe fn with_gl<F: FnMut(&gl::Gl)>(&mut self, mut fun: F) {
+        self.acquire_context();
         self.reset_state();
         fun(&self.share.context);
         // Flush the state to enforce a reset once a new command buffer
@@ -86,6 +258,101 @@ impl CommandQueue {
         self.state.flush();
     }
 
+    /// Advance the timeline to `value` once all the work previously
+    /// submitted to the queue has completed.
+    ///
+    /// `value` has to be greater than any value previously signalled.
+    pub unsafe fn signal_timeline(&mut self, timeline: &native::Timeline, value: u64) {
+        if let Some(worker) = self.worker.clone() {
+            if !worker.is_current() {
+                return worker.run_unchecked(|| self.signal_timeline(timeline, value));
+            }
+        }
+        let mut state = timeline.0.lock().unwrap();
+        assert!(
+            value > state.submitted,
+            "Timeline values have to be strictly increasing"
+        );
+        state.submitted = value;
+        if self.share.private_caps.sync {
+            let gl = &self.share.context;
+            let sync = gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+            state.pending.push_back((value, sync));
+            // Make sure the sync object reaches the GPU, as it may be polled
+            // before the next submission.
+            gl.Flush();
+        } else {
+            // Same as for fences, no way to track completion.
+            state.signalled = value;
+        }
+    }
+
+    /// Write the last image presented to a swapchain with readback enabled
+    /// into `buffer` at `offset`, returning its extent.
+    ///
+    /// Rows are written top to bottom, tightly packed, with the texels in
+    /// the `Rgba8Unorm` layout whatever the format of the swapchain.
+    /// Returns `None` if no image has been read back yet, or if the buffer
+    /// is too small to hold it.
+    #[cfg(feature = "glutin")]
+    pub unsafe fn read_presented_image(
+        &mut self,
+        swapchain: &window::glutin::Swapchain,
+        buffer: &native::Buffer,
+        offset: hal::buffer::Offset,
+    ) -> Option<hal::image::Extent> {
+        if let Some(worker) = self.worker.clone() {
+            if !worker.is_current() {
+                return worker
+                    .run_unchecked(|| self.read_presented_image(swapchain, buffer, offset));
+            }
+        }
+        self.acquire_context();
+        let presented = swapchain.presented.lock().unwrap();
+        let (extent, ref texels) = *presented.as_ref()?;
+        if offset + texels.len() as u64 > buffer.requirements.size {
+            error!(
+                "Buffer {} is too small for a presented image of {:?}",
+                buffer.raw, extent
+            );
+            return None;
+        }
+        self.update_buffer(buffer.raw, buffer.target, offset, texels);
+        Some(extent)
+    }
+
+    /// Read back the image about to be presented to a swapchain.
+    #[cfg(feature = "glutin")]
+    fn read_back(&mut self, swapchain: &window::glutin::Swapchain) {
+        let extent = swapchain.extent();
+        let row_size = extent.width as usize * 4;
+        let mut texels = vec![0u8; row_size * extent.height as usize];
+        let changed = self.state.shadow.set_pixel_store(
+            &self.share.context,
+            true,
+            0,
+            self.share.private_caps.pixel_store_layout,
+        );
+        self.count_state_change(changed);
+        let point = self.framebuffer_point(gl::READ_FRAMEBUFFER);
+        let gl = &self.share.context;
+        unsafe {
+            gl.BindFramebuffer(point, 0);
+            gl.ReadPixels(
+                0,
+                0,
+                extent.width as _,
+                extent.height as _,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                texels.as_mut_ptr() as *mut _,
+            );
+        }
+        // The rows of the default framebuffer go from the bottom up.
+        let texels = texels.chunks(row_size).rev().flatten().cloned().collect();
+        *swapchain.presented.lock().unwrap() = Some((extent, texels));
+    }
+
     /*
     fn bind_attribute(&mut self, slot: hal::AttributeSlot, buffer: n::Buffer, bel: BufferElement) {
         use core::format::SurfaceType as S;
@@ -158,22 +425,22 @@ impl CommandQueue {
         view: &native::ImageView,
     ) {
         let gl = &self.share.context;
-        match view {
-            &native::ImageView::Surface(surface) => unsafe {
-                gl.FramebufferRenderbuffer(point, attachment, gl::RENDERBUFFER, surface);
-            },
-            &native::ImageView::Texture(texture, level) => unsafe {
-                gl.FramebufferTexture(point, attachment, texture, level as gl::types::GLint);
-            },
-            &native::ImageView::TextureLayer(texture, level, layer) => unsafe {
-                gl.FramebufferTextureLayer(
-                    point,
-                    attachment,
-                    texture,
-                    level as gl::types::GLint,
-                    layer as gl::types::GLint,
-                );
-            },
+        if self.share.private_caps.framebuffer_texture {
+            device::Device::bind_target(gl, point, attachment, view);
+        } else {
+            device::Device::bind_target_compat(gl, point, attachment, view);
+            // The texture got bound to the active unit.
+            self.state.textures.clear();
+        }
+    }
+
+    // Framebuffer target to bind to, read and draw framebuffers being the
+    // same on ES 2.0.
+    fn framebuffer_point(&self, point: gl::types::GLenum) -> gl::types::GLenum {
+        if self.share.private_caps.read_draw_framebuffer {
+            point
+        } else {
+            gl::FRAMEBUFFER
         }
     }
 
@@ -196,17 +463,316 @@ impl CommandQueue {
         &data[ptr.offset as usize..(ptr.offset + ptr.size) as usize]
     }
 
+    fn set_active_texture(&mut self, unit: gl::types::GLuint) {
+        if self.state.active_texture != Some(unit) {
+            unsafe { self.share.context.ActiveTexture(gl::TEXTURE0 + unit) };
+            self.state.active_texture = Some(unit);
+        }
+    }
+
+    fn bind_texture(&mut self, unit: gl::types::GLuint, texture: native::Texture) {
+        if State::update_slot(&mut self.state.textures, unit, texture) {
+            self.set_active_texture(unit);
+            unsafe { self.share.context.BindTexture(gl::TEXTURE_2D, texture) };
+            self.count(|stats| stats.texture_binds += 1);
+        } else {
+            self.count(|stats| stats.redundant_binds += 1);
+        }
+    }
+
+    // Write data to a buffer, going through the ring buffer if possible.
+    fn update_buffer(
+        &mut self,
+        buffer: native::RawBuffer,
+        target: gl::types::GLenum,
+        offset: u64,
+        data: &[u8],
+    ) {
+        if let Some(shadow) = self.share.buffer_shadows.borrow_mut().get_mut(&buffer) {
+            shadow[offset as usize..offset as usize + data.len()].copy_from_slice(data);
+        }
+        if self.ring.is_none() {
+            self.ring = ring::RingBuffer::new(&self.share);
+        }
+        let gl = &self.share.context;
+        let ring_offset = match self.ring {
+            Some(ref mut ring) => ring.write(gl, data).map(|offset| (ring.raw(), offset)),
+            None => None,
+        };
+        unsafe {
+            match ring_offset {
+                Some((ring, ring_offset)) => {
+                    gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, ring);
+                    gl.BindBuffer(gl::PIXEL_PACK_BUFFER, buffer);
+                    gl.CopyBufferSubData(
+                        gl::PIXEL_UNPACK_BUFFER,
+                        gl::PIXEL_PACK_BUFFER,
+                        ring_offset as _,
+                        offset as _,
+                        data.len() as _,
+                    );
+                    gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+                    gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+                }
+                None => {
+                    // Without pixel buffers, the buffer can only be bound to
+                    // the target it was created for.
+                    let target = if self.share.private_caps.pixel_buffer {
+                        gl::PIXEL_PACK_BUFFER
+                    } else {
+                        target
+                    };
+                    gl.BindBuffer(target, buffer);
+                    gl.BufferSubData(
+                        target,
+                        offset as _,
+                        data.len() as _,
+                        data.as_ptr() as *const _,
+                    );
+                    gl.BindBuffer(target, 0);
+                    if target == gl::ELEMENT_ARRAY_BUFFER {
+                        // The index buffer binding is part of the vertex
+                        // array state.
+                        for vertex_array in self.state.vertex_arrays.values_mut() {
+                            vertex_array.index_buffer = None;
+                        }
+                    }
+                }
+            }
+        }
+        self.count(|stats| stats.bytes_uploaded += data.len() as u64);
+    }
+
+    // Catch up with the buffers and vertex layouts destroyed since the vertex
+    // arrays were last used: the arrays of destroyed layouts are deleted, and
+    // the buffer bindings of the others forgotten, as buffer names get reused.
+    fn update_vertex_arrays(&mut self) {
+        let share = &self.share;
+        let state = &mut self.state;
+        let epoch = share.vertex_array_epoch.get();
+        if state.vertex_array_epoch == epoch {
+            return;
+        }
+        state.vertex_array_epoch = epoch;
+
+        let gl = &share.context;
+        let layouts = share.vertex_layouts.borrow();
+        let legacy_layout = &mut state.legacy_layout;
+        let bound = &mut state.vertex_array;
+        state.vertex_arrays.retain(|layout, vertex_array| {
+            if layouts.contains_key(layout) {
+                vertex_array.invalidate();
+                return true;
+            }
+            if vertex_array.name != 0 {
+                unsafe { gl.DeleteVertexArrays(1, &vertex_array.name) };
+                if *bound == Some(vertex_array.name) {
+                    *bound = None;
+                }
+            } else if *legacy_layout == Some(*layout) {
+                for &location in &vertex_array.locations {
+                    unsafe { gl.DisableVertexAttribArray(location) };
+                }
+                *legacy_layout = None;
+            }
+            false
+        });
+    }
+
+    // Bind the vertex array of a layout, only updating the attribute pointers
+    // and the index buffer binding if they changed since it was last used.
+    fn bind_vertex_array(&mut self, layout: native::VertexLayoutId, buffers: &[gl::types::GLuint]) {
+        use crate::native::VertexAttribFunction::*;
+
+        self.update_vertex_arrays();
+        let share = &self.share;
+        let gl = &share.context;
+        let state = &mut self.state;
+        let layouts = share.vertex_layouts.borrow();
+        let attributes = &layouts[&layout].0;
+        let instancing = share
+            .legacy_features
+            .contains(LegacyFeatures::INSTANCED_ATTRIBUTE_BINDING);
+        let (mut binds, mut redundant_binds) = (0, 0);
+
+        let created = !state.vertex_arrays.contains_key(&layout);
+        if created {
+            let mut name = 0;
+            if share.private_caps.vertex_array {
+                unsafe { gl.GenVertexArrays(1, &mut name) };
+            }
+            state.vertex_arrays.insert(
+                layout,
+                VertexArray {
+                    name,
+                    locations: attributes.iter().map(|a| a.desc.location).collect(),
+                    buffers: vec![None; attributes.len()],
+                    index_buffer: None,
+                },
+            );
+        }
+
+        let enable_arrays = if share.private_caps.vertex_array {
+            let name = state.vertex_arrays[&layout].name;
+            if state.vertex_array != Some(name) {
+                unsafe { gl.BindVertexArray(name) };
+                state.vertex_array = Some(name);
+            }
+            created
+        } else if state.legacy_layout != Some(layout) {
+            // The attribute arrays of the previous layout are still enabled.
+            if let Some(previous) = state.legacy_layout {
+                for &location in &state.vertex_arrays[&previous].locations {
+                    unsafe { gl.DisableVertexAttribArray(location) };
+                }
+            }
+            state.legacy_layout = Some(layout);
+            state.vertex_arrays.get_mut(&layout).unwrap().invalidate();
+            true
+        } else {
+            false
+        };
+        let vertex_array = state.vertex_arrays.get_mut(&layout).unwrap();
+
+        if enable_arrays {
+            for attribute in attributes {
+                let location = attribute.desc.location;
+                unsafe { gl.EnableVertexAttribArray(location) };
+                if instancing {
+                    unsafe { gl.VertexAttribDivisor(location, attribute.rate) };
+                } else if attribute.rate != 0 {
+                    error!("Binding attribute with instanced input rate is not supported");
+                }
+            }
+        }
+
+        if let Some(index_buffer) = state.index_buffer {
+            if vertex_array.index_buffer != Some(index_buffer) {
+                unsafe { gl.BindBuffer(gl::ELEMENT_ARRAY_BUFFER, index_buffer) };
+                vertex_array.index_buffer = Some(index_buffer);
+                binds += 1;
+            } else {
+                redundant_binds += 1;
+            }
+        }
+
+        let mut bound_array_buffer = false;
+        for ((attribute, &buffer), current) in attributes
+            .iter()
+            .zip(buffers)
+            .zip(&mut vertex_array.buffers)
+        {
+            if *current == Some(buffer) {
+                redundant_binds += 1;
+                continue;
+            }
+            let native::AttributeDesc {
+                location,
+                size,
+                format,
+                offset,
+                vertex_attrib_fn,
+            } = attribute.desc;
+            let offset = offset as *const gl::types::GLvoid;
+            let stride = attribute.stride;
+            unsafe {
+                gl.BindBuffer(gl::ARRAY_BUFFER, buffer);
+                match vertex_attrib_fn {
+                    Float => {
+                        gl.VertexAttribPointer(location, size, format, gl::FALSE, stride, offset)
+                    }
+                    Integer => gl.VertexAttribIPointer(location, size, format, stride, offset),
+                    Double => gl.VertexAttribLPointer(location, size, format, stride, offset),
+                }
+            }
+            *current = Some(buffer);
+            bound_array_buffer = true;
+            binds += 1;
+        }
+        if bound_array_buffer {
+            unsafe { gl.BindBuffer(gl::ARRAY_BUFFER, 0) };
+        }
+
+        self.count(|stats| {
+            stats.buffer_binds += binds;
+            stats.redundant_binds += redundant_binds;
+        });
+    }
+
+    // Update the statistics of the current frame.
+    fn count<F: FnOnce(&mut FrameStatistics)>(&self, update: F) {
+        let mut stats = self.share.frame_statistics.get();
+        update(&mut stats);
+        self.share.frame_statistics.set(stats);
+    }
+
+    // Count a state change, or a redundant one which was skipped.
+    fn count_state_change(&self, changed: bool) {
+        if changed {
+            self.count(|stats| stats.state_changes += 1);
+        } else {
+            self.count(|stats| stats.redundant_binds += 1);
+        }
+    }
+
+    // Describe the buffer side of a copy between a buffer and a texture to
+    // `glPixelStorei`, returning the format and type of the texels and the
+    // byte pitch of the rows in the buffer.
+    //
+    // Without `pixel_store_layout` rows are always tightly packed, the
+    // callers repack the host copies of the buffers instead.
+    fn set_pixel_store(
+        &mut self,
+        pack: bool,
+        format: hal::format::Format,
+        r: &hal::command::BufferImageCopy,
+    ) -> (gl::types::GLenum, gl::types::GLenum, usize) {
+        let (tex_format, ty, pitch) = pixel_layout(format, r);
+        let layout = self.share.private_caps.pixel_store_layout;
+        let row_length = r.buffer_width as gl::types::GLint;
+        if !layout && row_length != 0 && row_length != r.image_extent.width as _ {
+            debug!("Rows of {} texels repacked on the host", row_length);
+        }
+        let changed =
+            self.state
+                .shadow
+                .set_pixel_store(&self.share.context, pack, row_length, layout);
+        self.count_state_change(changed);
+        (tex_format, ty, pitch)
+    }
+
+    // Set the width of the rasterized lines, clamped to the supported range.
+    fn set_line_width(&mut self, width: f32) {
+        let width = if self.share.features.contains(hal::Features::LINE_WIDTH) {
+            let [min, max] = self.share.limits.line_width_range;
+            width.max(min).min(max)
+        } else {
+            1.0
+        };
+        let changed = self.state.shadow.set_line_width(&self.share.context, width);
+        self.count_state_change(changed);
+    }
+
+    // Take over the context from the queue which submitted last,
+    // as the state it left behind doesn't match our cache.
+    fn acquire_context(&mut self) {
+        if self.share.last_queue.get() != Some(self.id) {
+            self.state.flush();
+            self.share.last_queue.set(Some(self.id));
+        }
+    }
+
     // Reset the state to match our _expected_ state before executing
     // a command buffer.
     fn reset_state(&mut self) {
         let gl = &self.share.context;
 
         // Bind default VAO
-        if !self.state.vao {
+        if self.state.vertex_array.is_none() {
             if self.share.private_caps.vertex_array {
                 unsafe { gl.BindVertexArray(self.vao) };
             }
-            self.state.vao = true
+            self.state.vertex_array = Some(self.vao);
         }
 
         // Reset indirect draw buffer
@@ -218,19 +784,13 @@ impl CommandQueue {
             unsafe { gl.BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0) };
         }
 
-        // Unbind index buffers
-        match self.state.index_buffer {
-            Some(0) => (), // Nothing to do
-            Some(_) | None => {
-                unsafe { gl.BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0) };
-                self.state.index_buffer = Some(0);
-            }
-        }
+        // Index buffers are only bound along with the vertex arrays.
+        self.state.index_buffer = None;
 
         // Reset viewports
         if self.state.num_viewports == 1 {
             unsafe { gl.Viewport(0, 0, 0, 0) };
-            unsafe { gl.DepthRange(0.0, 1.0) };
+            set_depth_range(&self.share, 0.0, 1.0);
         } else if self.state.num_viewports > 1 {
             // 16 viewports is a common limit set in drivers.
             let viewports: SmallVec<[[f32; 4]; 16]> = (0..self.state.num_viewports)
@@ -259,12 +819,79 @@ impl CommandQueue {
         }
     }
 
-    fn process(&mut self, cmd: &com::Command, data_buf: &[u8]) {
+    /// Load the members of the emulated uniform blocks of the current
+    /// program from the host copies of the bound uniform buffers.
+    fn load_uniform_block_members(&mut self) {
+        let program = match self.state.program {
+            Some(program) => program,
+            None => return,
+        };
+        let block_members = self.share.uniform_block_members.borrow();
+        let members = match block_members.get(&program) {
+            Some(members) => members,
+            None => return,
+        };
+        let shadows = self.share.buffer_shadows.borrow();
+        for member in members {
+            let (location, binding, offset, ty, count) = match *member {
+                native::UniformBinding::BlockMember {
+                    location,
+                    binding,
+                    offset,
+                    ty,
+                    count,
+                } => (location, binding, offset, ty, count),
+                _ => continue,
+            };
+            let range = self
+                .state
+                .uniform_buffers
+                .get(binding as usize)
+                .and_then(|range| *range);
+            let data = match range.and_then(|(buffer, start, _)| {
+                shadows
+                    .get(&buffer)
+                    .map(|shadow| &shadow[start as usize + offset as usize..])
+            }) {
+                Some(data) => data,
+                None => {
+                    error!("No uniform buffer bound at {} for the draw", binding);
+                    continue;
+                }
+            };
+            load_std140_uniform(&self.share.context, location, ty, count, data);
+        }
+    }
+
+    fn process(&mut self, share: &Share, cmd: &com::Command, data_buf: &[u8]) {
+        match *cmd {
+            com::Command::Draw { .. } | com::Command::DrawIndexed { .. } => {
+                self.count(|stats| stats.draw_calls += 1);
+                if !self
+                    .share
+                    .legacy_features
+                    .contains(LegacyFeatures::CONSTANT_BUFFER)
+                {
+                    self.load_uniform_block_members();
+                }
+            }
+            com::Command::Dispatch(..) | com::Command::DispatchIndirect(..) => {
+                self.count(|stats| stats.dispatches += 1)
+            }
+            com::Command::SetViewports { .. }
+            | com::Command::SetScissors(..)
+            | com::Command::DrawBuffers(..)
+            | com::Command::SetPatchSize(..)
+            | com::Command::BindProgram(..)
+            | com::Command::SetTextureSamplerSettings(..) => {
+                self.count(|stats| stats.state_changes += 1)
+            }
+            _ => {}
+        }
+
         match *cmd {
             com::Command::BindIndexBuffer(buffer) => {
-                let gl = &self.share.context;
                 self.state.index_buffer = Some(buffer);
-                unsafe { gl.BindBuffer(gl::ELEMENT_ARRAY_BUFFER, buffer) };
             }
             //          com::Command::BindVertexBuffers(_data_ptr) =>
             com::Command::Draw {
@@ -272,8 +899,8 @@ impl CommandQueue {
                 ref vertices,
                 ref instances,
             } => {
-                let gl = &self.share.context;
-                let legacy = &self.share.legacy_features;
+                let gl = &share.context;
+                let legacy = &share.legacy_features;
                 if instances == &(0u32..1) {
                     unsafe {
                         gl.DrawArrays(
@@ -318,25 +945,56 @@ impl CommandQueue {
                 index_buffer_offset,
                 base_vertex,
                 ref instances,
+                index_bounds,
             } => {
-                let gl = &self.share.context;
-                let legacy = &self.share.legacy_features;
+                let gl = &share.context;
+                let legacy = &share.legacy_features;
                 let offset = index_buffer_offset as *const gl::types::GLvoid;
+                let index_bounds = if share.private_caps.draw_range_elements {
+                    index_bounds
+                } else {
+                    None
+                };
 
                 if instances == &(0u32..1) {
                     if base_vertex == 0 {
-                        unsafe {
-                            gl.DrawElements(primitive, index_count as _, index_type, offset);
+                        match index_bounds {
+                            Some((start, end)) => unsafe {
+                                gl.DrawRangeElements(
+                                    primitive,
+                                    start,
+                                    end,
+                                    index_count as _,
+                                    index_type,
+                                    offset,
+                                );
+                            },
+                            None => unsafe {
+                                gl.DrawElements(primitive, index_count as _, index_type, offset);
+                            },
                         }
                     } else if legacy.contains(LegacyFeatures::DRAW_INDEXED_BASE) {
-                        unsafe {
-                            gl.DrawElementsBaseVertex(
-                                primitive,
-                                index_count as _,
-                                index_type,
-                                offset,
-                                base_vertex as _,
-                            );
+                        match index_bounds {
+                            Some((start, end)) => unsafe {
+                                gl.DrawRangeElementsBaseVertex(
+                                    primitive,
+                                    start,
+                                    end,
+                                    index_count as _,
+                                    index_type,
+                                    offset,
+                                    base_vertex as _,
+                                );
+                            },
+                            None => unsafe {
+                                gl.DrawElementsBaseVertex(
+                                    primitive,
+                                    index_count as _,
+                                    index_type,
+                                    offset,
+                                    base_vertex as _,
+                                );
+                            },
                         }
                     } else {
                         error!("Base vertex with indexed drawing not supported");
@@ -390,14 +1048,14 @@ impl CommandQueue {
                 // Capability support is given by which queue types will be exposed.
                 // If there is no compute support, this pattern should never be reached
                 // because no queue with compute capability can be created.
-                let gl = &self.share.context;
+                let gl = &share.context;
                 unsafe { gl.DispatchCompute(count[0], count[1], count[2]) };
             }
             com::Command::DispatchIndirect(buffer, offset) => {
                 // Capability support is given by which queue types will be exposed.
                 // If there is no compute support, this pattern should never be reached
                 // because no queue with compute capability can be created.
-                let gl = &self.share.context;
+                let gl = &share.context;
                 unsafe {
                     gl.BindBuffer(gl::DRAW_INDIRECT_BUFFER, buffer);
                     // TODO: possible integer conversion issue
@@ -409,13 +1067,13 @@ impl CommandQueue {
                 viewport_ptr,
                 depth_range_ptr,
             } => {
-                let gl = &self.share.context;
+                let gl = &share.context;
                 let viewports = Self::get::<[f32; 4]>(data_buf, viewport_ptr);
                 let depth_ranges = Self::get::<[f64; 2]>(data_buf, depth_range_ptr);
 
                 let num_viewports = viewports.len();
                 assert_eq!(num_viewports, depth_ranges.len());
-                assert!(0 < num_viewports && num_viewports <= self.share.limits.max_viewports);
+                assert!(0 < num_viewports && num_viewports <= share.limits.max_viewports);
 
                 if num_viewports == 1 {
                     let view = viewports[0];
@@ -428,7 +1086,7 @@ impl CommandQueue {
                             view[3] as i32,
                         )
                     };
-                    unsafe { gl.DepthRange(depth_range[0], depth_range[1]) };
+                    set_depth_range(share, depth_range[0], depth_range[1]);
                 } else if num_viewports > 1 {
                     // Support for these functions is coupled with the support
                     // of multiple viewports.
@@ -449,10 +1107,10 @@ impl CommandQueue {
                 }
             }
             com::Command::SetScissors(first_scissor, data_ptr) => {
-                let gl = &self.share.context;
+                let gl = &share.context;
                 let scissors = Self::get::<[i32; 4]>(data_buf, data_ptr);
                 let num_scissors = scissors.len();
-                assert!(0 < num_scissors && num_scissors <= self.share.limits.max_viewports);
+                assert!(0 < num_scissors && num_scissors <= share.limits.max_viewports);
 
                 if num_scissors == 1 {
                     let scissor = scissors[0];
@@ -470,20 +1128,66 @@ impl CommandQueue {
                 }
             }
             com::Command::SetBlendColor(color) => {
-                state::set_blend_color(&self.share.context, color);
+                let changed = self.state.shadow.set_blend_color(&share.context, color);
+                self.count_state_change(changed);
             }
+            com::Command::ClearBufferColorF(draw_buffer, cv)
+                if !share.private_caps.clear_buffer =>
+            unsafe {
+                // Only the first draw buffer can be cleared on its own.
+                debug_assert_eq!(draw_buffer, 0);
+                let gl = &share.context;
+                self.state.shadow.unlock_color_mask(gl, 0);
+                gl.ClearColor(cv[0], cv[1], cv[2], cv[3]);
+                gl.Clear(gl::COLOR_BUFFER_BIT);
+            },
+            com::Command::ClearBufferColorU(..) | com::Command::ClearBufferColorI(..)
+                if !share.private_caps.clear_buffer =>
+            {
+                error!("Integer color targets can't be cleared without glClearBuffer");
+            }
+            com::Command::ClearBufferDepthStencil(depth, stencil)
+                if !share.private_caps.clear_buffer =>
+            unsafe {
+                let gl = &share.context;
+                let mut mask = 0;
+                if let Some(depth) = depth {
+                    self.state.shadow.unlock_depth_mask(gl);
+                    if share.info.version.is_embedded {
+                        gl.ClearDepthf(depth);
+                    } else {
+                        gl.ClearDepth(depth as _);
+                    }
+                    mask |= gl::DEPTH_BUFFER_BIT;
+                }
+                if let Some(stencil) = stencil {
+                    self.state.shadow.unlock_stencil_mask(gl);
+                    gl.ClearStencil(stencil as _);
+                    mask |= gl::STENCIL_BUFFER_BIT;
+                }
+                gl.Clear(mask);
+            },
             com::Command::ClearBufferColorF(draw_buffer, cv) => unsafe {
-                self.share
+                self.state
+                    .shadow
+                    .unlock_color_mask(&share.context, draw_buffer as _);
+                share
                     .context
                     .ClearBufferfv(gl::COLOR, draw_buffer, cv.as_ptr());
             },
             com::Command::ClearBufferColorU(draw_buffer, cv) => unsafe {
-                self.share
+                self.state
+                    .shadow
+                    .unlock_color_mask(&share.context, draw_buffer as _);
+                share
                     .context
                     .ClearBufferuiv(gl::COLOR, draw_buffer, cv.as_ptr());
             },
             com::Command::ClearBufferColorI(draw_buffer, cv) => unsafe {
-                self.share
+                self.state
+                    .shadow
+                    .unlock_color_mask(&share.context, draw_buffer as _);
+                share
                     .context
                     .ClearBufferiv(gl::COLOR, draw_buffer, cv.as_ptr());
             },
@@ -494,86 +1198,198 @@ impl CommandQueue {
                     (None, Some(stencil)) => (gl::STENCIL, 0.0, stencil),
                     _ => unreachable!(),
                 };
+                if target != gl::STENCIL {
+                    self.state.shadow.unlock_depth_mask(&share.context);
+                }
+                if target != gl::DEPTH {
+                    self.state.shadow.unlock_stencil_mask(&share.context);
+                }
 
-                self.share
-                    .context
-                    .ClearBufferfi(target, 0, depth, stencil as _);
+                share.context.ClearBufferfi(target, 0, depth, stencil as _);
             },
             com::Command::ClearTexture(_color) => unimplemented!(),
+            com::Command::DrawBuffers(draw_buffers) if !share.private_caps.draw_buffers => {
+                // Only the first color attachment is ever written.
+                let draw_buffers = Self::get::<gl::types::GLenum>(data_buf, draw_buffers);
+                if draw_buffers
+                    .iter()
+                    .any(|&buffer| buffer != gl::COLOR_ATTACHMENT0)
+                {
+                    error!("Drawing to multiple color attachments is not supported");
+                }
+            }
             com::Command::DrawBuffers(draw_buffers) => unsafe {
                 let draw_buffers = Self::get::<gl::types::GLenum>(data_buf, draw_buffers);
-                self.share
+                share
                     .context
                     .DrawBuffers(draw_buffers.len() as _, draw_buffers.as_ptr());
             },
             com::Command::BindFrameBuffer(point, frame_buffer) => {
-                if self.share.private_caps.framebuffer {
-                    let gl = &self.share.context;
+                if share.private_caps.framebuffer {
+                    let gl = &share.context;
+                    let point = self.framebuffer_point(point);
                     unsafe { gl.BindFramebuffer(point, frame_buffer) };
+                    self.count(|stats| stats.framebuffer_binds += 1);
                 } else if frame_buffer != 0 {
                     error!("Tried to bind FBO {} without FBO support!", frame_buffer);
                 }
             }
             com::Command::BindTargetView(point, attachment, view) => {
+                let point = self.framebuffer_point(point);
                 self.bind_target(point, attachment, &view)
             }
-            com::Command::SetDrawColorBuffers(num) => {
-                state::bind_draw_color_buffers(&self.share.context, num);
+            com::Command::ResolveAttachment(framebuffer, src, dst) => {
+                let targets = share.resolve_targets.borrow();
+                let target = targets.get(&framebuffer).and_then(|targets| {
+                    let &(_, name) = targets.framebuffers.iter().find(|&&(id, _)| id == dst)?;
+                    Some((name, targets.extent))
+                });
+                match target {
+                    Some((name, extent)) if share.private_caps.read_draw_framebuffer => unsafe {
+                        let gl = &share.context;
+                        let (width, height) = (extent.width as i32, extent.height as i32);
+                        gl.BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer);
+                        gl.ReadBuffer(gl::COLOR_ATTACHMENT0 + src as gl::types::GLenum);
+                        gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, name);
+                        gl.BlitFramebuffer(
+                            0,
+                            0,
+                            width,
+                            height,
+                            0,
+                            0,
+                            width,
+                            height,
+                            gl::COLOR_BUFFER_BIT,
+                            gl::NEAREST,
+                        );
+                        // Following subpasses keep drawing to the framebuffer.
+                        gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, framebuffer);
+                        self.count(|stats| stats.framebuffer_binds += 3);
+                    },
+                    Some(_) => error!("Resolving attachments requires framebuffer blits"),
+                    None => error!(
+                        "Attachment {} of framebuffer {} isn't resolved to {}",
+                        src, framebuffer, dst
+                    ),
+                }
+            }
+            com::Command::InvalidateAttachments(attachments) => {
+                if share.private_caps.invalidate_framebuffer {
+                    let attachments = Self::get::<gl::types::GLenum>(data_buf, attachments);
+                    let point = self.framebuffer_point(gl::DRAW_FRAMEBUFFER);
+                    unsafe {
+                        share.context.InvalidateFramebuffer(
+                            point,
+                            attachments.len() as _,
+                            attachments.as_ptr(),
+                        )
+                    };
+                }
             }
             com::Command::SetPatchSize(num) => unsafe {
-                self.share.context.PatchParameteri(gl::PATCH_VERTICES, num);
+                share.context.PatchParameteri(gl::PATCH_VERTICES, num);
             },
             com::Command::BindProgram(program) => unsafe {
-                self.share.context.UseProgram(program);
+                share.context.UseProgram(program);
+                self.state.program = Some(program);
             },
-            com::Command::BindBlendSlot(slot, ref blend) => {
-                state::bind_blend_slot(&self.share.context, slot, blend);
+            com::Command::SetRasterizer(ref rasterizer) => {
+                let changed = self
+                    .state
+                    .shadow
+                    .bind_rasterizer(&share.context, rasterizer);
+                self.count_state_change(changed);
+                // Pipelines with a static line width override the dynamic one.
+                if let Some(width) = rasterizer.line_width() {
+                    self.set_line_width(width);
+                }
             }
-            com::Command::BindAttribute(ref attribute, handle, stride, rate) => unsafe {
-                use crate::native::VertexAttribFunction::*;
-
-                let &native::AttributeDesc {
-                    location,
-                    size,
-                    format,
-                    offset,
-                    vertex_attrib_fn,
-                    ..
-                } = attribute;
-                let offset = offset as *const gl::types::GLvoid;
-                let gl = &self.share.context;
-
-                gl.BindBuffer(gl::ARRAY_BUFFER, handle);
-
-                match vertex_attrib_fn {
-                    Float => {
-                        gl.VertexAttribPointer(location, size, format, gl::FALSE, stride, offset)
-                    }
-                    Integer => gl.VertexAttribIPointer(location, size, format, stride, offset),
-                    Double => gl.VertexAttribLPointer(location, size, format, stride, offset),
+            com::Command::SetDepthBounds(ref bounds) => {
+                // The test is never enabled without the extension.
+                if let Some(ref ext) = share.ext.depth_bounds_test {
+                    let changed = self
+                        .state
+                        .shadow
+                        .set_depth_bounds(ext, bounds.start, bounds.end);
+                    self.count_state_change(changed);
                 }
-
-                if rate != 0 {
-                    if self
-                        .share
-                        .legacy_features
-                        .contains(LegacyFeatures::INSTANCED_ATTRIBUTE_BINDING)
-                    {
-                        gl.VertexAttribDivisor(location, rate);
-                    } else {
-                        error!("Binding attribute with instanced input rate is not supported");
-                    }
+            }
+            com::Command::SetFramebufferSrgb(enable) => {
+                // TODO: Find way to emulate this on older Opengl versions.
+                if self
+                    .share
+                    .legacy_features
+                    .contains(LegacyFeatures::SRGB_COLOR)
+                {
+                    let changed = self
+                        .state
+                        .shadow
+                        .set_framebuffer_srgb(&share.context, enable);
+                    self.count_state_change(changed);
                 }
-
-                gl.EnableVertexAttribArray(location);
-                gl.BindBuffer(gl::ARRAY_BUFFER, 0);
-            },
+            }
+            com::Command::SetLineWidth(width) => self.set_line_width(width),
+            com::Command::SetLineSmooth(enable) => {
+                if !share.info.version.is_embedded {
+                    let changed = self.state.shadow.set_line_smooth(&share.context, enable);
+                    self.count_state_change(changed);
+                }
+            }
+            com::Command::SetDepthState(ref depth) => {
+                let changed = self.state.shadow.bind_depth(&share.context, depth);
+                self.count_state_change(changed);
+            }
+            com::Command::SetStencilState(ref stencil) => {
+                let changed = self.state.shadow.bind_stencil(&share.context, stencil);
+                self.count_state_change(changed);
+            }
+            com::Command::BindBlendSlot(slot, ref blend) => {
+                let changed = self
+                    .state
+                    .shadow
+                    .bind_blend_slot(&share.context, slot, blend);
+                self.count_state_change(changed);
+            }
+            com::Command::BindVertexArray { layout, buffers } => {
+                let buffers = Self::get::<gl::types::GLuint>(data_buf, buffers);
+                self.bind_vertex_array(layout, buffers);
+            }
             /*
             com::Command::UnbindAttribute(ref attribute) => unsafe {
-                self.share.context.DisableVertexAttribArray(attribute.location);
+                share.context.DisableVertexAttribArray(attribute.location);
             }*/
-            com::Command::CopyBufferToBuffer(src, dst, ref r) => unsafe {
-                let gl = &self.share.context;
+            com::Command::CopyBufferToBuffer(src, dst, _, ref r)
+                if share.private_caps.direct_state_access =>
+            unsafe {
+                let gl = &share.context;
+                gl.CopyNamedBufferSubData(src, dst, r.src as _, r.dst as _, r.size as _);
+                self.count(|stats| stats.bytes_uploaded += r.size);
+            },
+            com::Command::CopyBufferToBuffer(src, dst, target, ref r)
+                if !self
+                    .share
+                    .legacy_features
+                    .contains(LegacyFeatures::COPY_BUFFER) =>
+            {
+                // Write the host copy of the source to the destination.
+                let range = r.src as usize..(r.src + r.size) as usize;
+                let data = self
+                    .share
+                    .buffer_shadows
+                    .borrow()
+                    .get(&src)
+                    .map(|shadow| shadow[range].to_vec());
+                match data {
+                    Some(data) => self.update_buffer(dst, target, r.dst, &data),
+                    None => error!(
+                        "Buffer {} can't be copied from without the TRANSFER_SRC usage",
+                        src
+                    ),
+                }
+            }
+            com::Command::CopyBufferToBuffer(src, dst, _, ref r) => unsafe {
+                let gl = &share.context;
                 gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, src);
                 gl.BindBuffer(gl::PIXEL_PACK_BUFFER, dst);
                 gl.CopyBufferSubData(
@@ -585,47 +1401,206 @@ impl CommandQueue {
                 );
                 gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
                 gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+                self.count(|stats| stats.bytes_uploaded += r.size);
             },
-            com::Command::CopyBufferToTexture(buffer, texture, ref r) => unsafe {
-                // TODO: Fix format and active texture
+            com::Command::UpdateBuffer {
+                buffer,
+                target,
+                offset,
+                data,
+            } => {
+                let data = Self::get_raw(data_buf, data);
+                self.update_buffer(buffer, target, offset, data);
+            }
+            com::Command::CopyBufferToTexture(buffer, texture, format, ref r)
+                if !share.private_caps.pixel_buffer =>
+            unsafe {
+                // Upload the texels from the host copy of the buffer.
+                assert_eq!(r.image_offset.z, 0);
+                let (tex_format, ty, pitch) = self.set_pixel_store(false, format, r);
+                let row_size = r.image_extent.width as usize * texel_size(format);
+                let rows = r.image_extent.height as usize;
+                let repack = pitch != row_size && !share.private_caps.pixel_store_layout;
+                self.bind_texture(0, texture);
+                let uploaded = match share.buffer_shadows.borrow().get(&buffer) {
+                    Some(shadow) => {
+                        let data = &shadow[r.buffer_offset as usize..];
+                        let packed;
+                        let data = if repack {
+                            packed = pack_rows(data, pitch, row_size, rows);
+                            &packed[..]
+                        } else {
+                            data
+                        };
+                        share.context.TexSubImage2D(
+                            gl::TEXTURE_2D,
+                            r.image_layers.level as _,
+                            r.image_offset.x,
+                            r.image_offset.y,
+                            r.image_extent.width as _,
+                            r.image_extent.height as _,
+                            tex_format,
+                            ty,
+                            data.as_ptr() as *const _,
+                        );
+                        true
+                    }
+                    None => false,
+                };
+                if uploaded {
+                    let size = copy_size(format, r);
+                    self.count(|stats| stats.bytes_uploaded += size);
+                } else {
+                    error!(
+                        "Buffer {} can't be copied from without the TRANSFER_SRC usage",
+                        buffer
+                    );
+                }
+            },
+            com::Command::CopyBufferToTexture(buffer, texture, format, ref r) => unsafe {
+                // TODO: Fix active texture
                 assert_eq!(r.image_offset.z, 0);
-                let gl = &self.share.context;
-                gl.ActiveTexture(gl::TEXTURE0);
+                let (tex_format, ty, _) = self.set_pixel_store(false, format, r);
+                let dsa = share.private_caps.direct_state_access;
+                if !dsa {
+                    self.bind_texture(0, texture);
+                }
+                let gl = &share.context;
                 gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, buffer);
-                gl.BindTexture(gl::TEXTURE_2D, texture);
-                gl.TexSubImage2D(
-                    gl::TEXTURE_2D,
-                    r.image_layers.level as _,
-                    r.image_offset.x,
-                    r.image_offset.y,
-                    r.image_extent.width as _,
-                    r.image_extent.height as _,
-                    gl::RGBA,
-                    gl::UNSIGNED_BYTE,
-                    ptr::null(),
-                );
+                if dsa {
+                    gl.TextureSubImage2D(
+                        texture,
+                        r.image_layers.level as _,
+                        r.image_offset.x,
+                        r.image_offset.y,
+                        r.image_extent.width as _,
+                        r.image_extent.height as _,
+                        tex_format,
+                        ty,
+                        r.buffer_offset as *const _,
+                    );
+                } else {
+                    gl.TexSubImage2D(
+                        gl::TEXTURE_2D,
+                        r.image_layers.level as _,
+                        r.image_offset.x,
+                        r.image_offset.y,
+                        r.image_extent.width as _,
+                        r.image_extent.height as _,
+                        tex_format,
+                        ty,
+                        r.buffer_offset as *const _,
+                    );
+                }
                 gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+                let size = copy_size(format, r);
+                self.count(|stats| stats.bytes_uploaded += size);
             },
             com::Command::CopyBufferToSurface(..) => {
                 unimplemented!() //TODO: use FBO
             }
-            com::Command::CopyTextureToBuffer(texture, buffer, ref r) => unsafe {
-                // TODO: Fix format and active texture
-                // TODO: handle partial copies gracefully
-                assert_eq!(r.image_offset, hal::image::Offset { x: 0, y: 0, z: 0 });
-                let gl = &self.share.context;
-                gl.ActiveTexture(gl::TEXTURE0);
-                gl.BindBuffer(gl::PIXEL_PACK_BUFFER, buffer);
-                gl.BindTexture(gl::TEXTURE_2D, texture);
-                gl.GetTexImage(
+            com::Command::CopyTextureToBuffer(texture, buffer, target, format, ref r)
+                if share.info.version.is_embedded =>
+            unsafe {
+                // There is no `glGetTexImage`, read the level attached to a
+                // temporary framebuffer instead.
+                let point = self.framebuffer_point(gl::READ_FRAMEBUFFER);
+                let host = !share.private_caps.pixel_buffer;
+                // Host reads are tightly packed, then written row by row.
+                let (tex_format, ty, pitch) = if host {
+                    let tight = hal::command::BufferImageCopy {
+                        buffer_width: 0,
+                        ..r.clone()
+                    };
+                    let (tex_format, ty, _) = self.set_pixel_store(true, format, &tight);
+                    let (_, _, pitch) = pixel_layout(format, r);
+                    (tex_format, ty, pitch)
+                } else {
+                    self.set_pixel_store(true, format, r)
+                };
+                let gl = &share.context;
+                let mut framebuffer = 0;
+                gl.GenFramebuffers(1, &mut framebuffer);
+                gl.BindFramebuffer(point, framebuffer);
+                gl.FramebufferTexture2D(
+                    point,
+                    gl::COLOR_ATTACHMENT0,
                     gl::TEXTURE_2D,
+                    texture,
                     r.image_layers.level as _,
-                    //r.image_offset.x, r.image_offset.y,
-                    //r.image_extent.width as _, r.image_extent.height as _,
-                    gl::RGBA,
-                    gl::UNSIGNED_BYTE,
-                    ptr::null_mut(),
                 );
+                let (x, y) = (r.image_offset.x, r.image_offset.y);
+                let (width, height) = (r.image_extent.width, r.image_extent.height);
+                let row_size = width as usize * texel_size(format);
+                let pixels = if host {
+                    let mut pixels = vec![0u8; row_size * height as usize];
+                    gl.ReadPixels(
+                        x,
+                        y,
+                        width as _,
+                        height as _,
+                        tex_format,
+                        ty,
+                        pixels.as_mut_ptr() as *mut _,
+                    );
+                    Some(pixels)
+                } else {
+                    gl.BindBuffer(gl::PIXEL_PACK_BUFFER, buffer);
+                    gl.ReadPixels(
+                        x,
+                        y,
+                        width as _,
+                        height as _,
+                        tex_format,
+                        ty,
+                        r.buffer_offset as *mut _,
+                    );
+                    gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+                    None
+                };
+                gl.BindFramebuffer(point, 0);
+                gl.DeleteFramebuffers(1, &framebuffer);
+                if let Some(pixels) = pixels {
+                    if pitch == row_size {
+                        self.update_buffer(buffer, target, r.buffer_offset, &pixels);
+                    } else {
+                        for (i, row) in pixels.chunks(row_size).enumerate() {
+                            let offset = r.buffer_offset + (i * pitch) as hal::buffer::Offset;
+                            self.update_buffer(buffer, target, offset, row);
+                        }
+                    }
+                }
+            },
+            com::Command::CopyTextureToBuffer(texture, buffer, _, format, ref r) => unsafe {
+                // TODO: Fix active texture
+                // TODO: handle partial copies gracefully
+                assert_eq!(r.image_offset, hal::image::Offset { x: 0, y: 0, z: 0 });
+                let (tex_format, ty, pitch) = self.set_pixel_store(true, format, r);
+                let dsa = share.private_caps.direct_state_access;
+                if !dsa {
+                    self.bind_texture(0, texture);
+                }
+                let gl = &share.context;
+                gl.BindBuffer(gl::PIXEL_PACK_BUFFER, buffer);
+                if dsa {
+                    let size = pitch * r.image_extent.height as usize;
+                    gl.GetTextureImage(
+                        texture,
+                        r.image_layers.level as _,
+                        tex_format,
+                        ty,
+                        size as _,
+                        r.buffer_offset as *mut _,
+                    );
+                } else {
+                    gl.GetTexImage(
+                        gl::TEXTURE_2D,
+                        r.image_layers.level as _,
+                        tex_format,
+                        ty,
+                        r.buffer_offset as *mut _,
+                    );
+                }
                 gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
             },
             com::Command::CopySurfaceToBuffer(..) => {
@@ -637,27 +1612,79 @@ impl CommandQueue {
             com::Command::CopyImageToSurface(..) => {
                 unimplemented!() //TODO: use FBO
             }
+            com::Command::BindBufferRange(gl::UNIFORM_BUFFER, index, buffer, offset, size)
+                if !self
+                    .share
+                    .legacy_features
+                    .contains(LegacyFeatures::CONSTANT_BUFFER) =>
+            {
+                // Read from the host copy of the buffer before draws.
+                State::update_slot(
+                    &mut self.state.uniform_buffers,
+                    index,
+                    (buffer, offset, size),
+                );
+            }
             com::Command::BindBufferRange(target, index, buffer, offset, size) => unsafe {
-                let gl = &self.share.context;
-                gl.BindBufferRange(target, index, buffer, offset, size);
+                if target != gl::UNIFORM_BUFFER
+                    || State::update_slot(
+                        &mut self.state.uniform_buffers,
+                        index,
+                        (buffer, offset, size),
+                    )
+                {
+                    let gl = &share.context;
+                    gl.BindBufferRange(target, index, buffer, offset, size);
+                    self.count(|stats| stats.buffer_binds += 1);
+                } else {
+                    self.count(|stats| stats.redundant_binds += 1);
+                }
             },
-            com::Command::BindTexture(index, texture) => unsafe {
-                let gl = &self.share.context;
-                gl.ActiveTexture(gl::TEXTURE0 + index);
-                gl.BindTexture(gl::TEXTURE_2D, texture);
+            com::Command::BindTexture(index, texture) => {
+                self.bind_texture(index, texture);
+            }
+            com::Command::BindImageTexture {
+                unit,
+                texture,
+                level,
+                layer,
+                access,
+                format,
+            } => unsafe {
+                let gl = &share.context;
+                gl.BindImageTexture(
+                    unit,
+                    texture,
+                    level as _,
+                    if layer.is_some() { gl::FALSE } else { gl::TRUE },
+                    layer.unwrap_or(0) as _,
+                    access,
+                    format,
+                );
+                self.count(|stats| stats.texture_binds += 1);
             },
             com::Command::BindSampler(index, sampler) => unsafe {
-                let gl = &self.share.context;
-                gl.BindSampler(index, sampler);
+                if State::update_slot(&mut self.state.samplers, index, sampler) {
+                    let gl = &share.context;
+                    gl.BindSampler(index, sampler);
+                    self.count(|stats| stats.texture_binds += 1);
+                } else {
+                    self.count(|stats| stats.redundant_binds += 1);
+                }
             },
+            com::Command::TextureBarrier => {
+                if share.private_caps.texture_barrier {
+                    unsafe { share.context.TextureBarrier() };
+                }
+            }
             com::Command::SetTextureSamplerSettings(index, texture, ref sinfo) => unsafe {
-                let gl = &self.share.context;
-                gl.ActiveTexture(gl::TEXTURE0 + index);
-                gl.BindTexture(gl::TEXTURE_2D, texture);
+                self.bind_texture(index, texture);
+                self.set_active_texture(index);
+                let gl = &share.context;
 
                 // TODO: Optimization: only change texture properties that have changed.
                 device::set_sampler_info(
-                    &self.share,
+                    share,
                     &sinfo,
                     |a, b| gl.TexParameterf(gl::TEXTURE_2D, a, b),
                     |a, b| gl.TexParameterfv(gl::TEXTURE_2D, a, &b[0]),
@@ -665,22 +1692,22 @@ impl CommandQueue {
                 );
             }, /*
                com::Command::BindConstantBuffer(pso::ConstantBufferParam(buffer, _, slot)) => unsafe {
-                   self.share.context.BindBufferBase(gl::UNIFORM_BUFFER, slot as gl::types::GLuint, buffer);
+                   share.context.BindBufferBase(gl::UNIFORM_BUFFER, slot as gl::types::GLuint, buffer);
                },
                com::Command::BindResourceView(pso::ResourceViewParam(view, _, slot)) => unsafe {
-                   self.share.context.ActiveTexture(gl::TEXTURE0 + slot as gl::types::GLenum);
-                   self.share.context.BindTexture(view.bind, view.object);
+                   share.context.ActiveTexture(gl::TEXTURE0 + slot as gl::types::GLenum);
+                   share.context.BindTexture(view.bind, view.object);
                },
                com::Command::BindUnorderedView(_uav) => unimplemented!(),
                com::Command::BindSampler(pso::SamplerParam(sampler, _, slot), bind_opt) => {
-                   let gl = &self.share.context;
-                   if self.share.private_caps.sampler_objects {
+                   let gl = &share.context;
+                   if share.private_caps.sampler_objects {
                        unsafe { gl.BindSampler(slot as gl::types::GLuint, sampler.object) };
                    } else {
                        assert!(hal::MAX_SAMPLERS <= hal::MAX_RESOURCE_VIEWS);
                        debug_assert_eq!(sampler.object, 0);
                        if let Some(bind) = bind_opt {
-                           tex::bind_sampler(gl, bind, &sampler.info, &self.share.private_caps);
+                           tex::bind_sampler(gl, bind, &sampler.info, &share.private_caps);
                        }else {
                            error!("Trying to bind a sampler to slot {}, when sampler objects are not supported, and no texture is bound there", slot);
                        }
@@ -707,35 +1734,35 @@ impl CommandQueue {
                    self.bind_attribute(slot, buffer, bel);
                },
                com::Command::UnbindAttribute(slot) => unsafe {
-                   self.share.context.DisableVertexAttribArray(slot as gl::types::GLuint);
+                   share.context.DisableVertexAttribArray(slot as gl::types::GLuint);
                },
                com::Command::BindUniform(loc, uniform) => {
-                   let gl = &self.share.context;
+                   let gl = &share.context;
                    shade::bind_uniform(gl, loc as gl::types::GLint, uniform);
                },
                com::Command::SetRasterizer(rast) => {
-                   state::bind_rasterizer(&self.share.context, &rast, self.share.info.version.is_embedded);
+                   state::bind_rasterizer(&share.context, &rast, share.info.version.is_embedded);
                },
                com::Command::SetDepthState(depth) => {
-                   state::bind_depth(&self.share.context, &depth);
+                   state::bind_depth(&share.context, &depth);
                },
                com::Command::SetStencilState(stencil, refs, cull) => {
-                   state::bind_stencil(&self.share.context, &stencil, refs, cull);
+                   state::bind_stencil(&share.context, &stencil, refs, cull);
                },
                com::Command::SetBlendState(slot, color) => {
-                   if self.share.capabilities.separate_blending_slots {
-                       state::bind_blend_slot(&self.share.context, slot, color);
+                   if share.capabilities.separate_blending_slots {
+                       state::bind_blend_slot(&share.context, slot, color);
                    }else if slot == 0 {
                        //self.temp.color = color; //TODO
-                       state::bind_blend(&self.share.context, color);
+                       state::bind_blend(&share.context, color);
                    }else if false {
                        error!("Separate blending slots are not supported");
                    }
                },
                com::Command::CopyBuffer(src, dst, src_offset, dst_offset, size) => {
-                   let gl = &self.share.context;
+                   let gl = &share.context;
 
-                   if self.share.capabilities.copy_buffer {
+                   if share.capabilities.copy_buffer {
                        unsafe {
                            gl.BindBuffer(gl::COPY_READ_BUFFER, src);
                            gl.BindBuffer(gl::COPY_WRITE_BUFFER, dst);
@@ -746,7 +1773,7 @@ impl CommandQueue {
                                                size);
                        }
                    } else {
-                       debug_assert!(self.share.private_caps.buffer_storage == false);
+                       debug_assert!(share.private_caps.buffer_storage == false);
 
                        unsafe {
                            let mut src_ptr = 0 as *mut ::std::os::raw::c_void;
@@ -766,21 +1793,182 @@ impl CommandQueue {
                },
                */
         }
-        if let Err(err) = self.share.check() {
+        if let Err(err) = share.check() {
             panic!("Error {:?} executing command: {:?}", err, cmd)
         }
     }
 
+    /// Insert a sync object into the command stream, which will be waited
+    /// upon by the next submission using the semaphore.
+    fn signal_semaphore(&mut self, semaphore: &native::Semaphore) {
+        if let Some(external) = semaphore.external {
+            let signal = self.share.ext.semaphore.unwrap().signal_semaphore;
+            unsafe { signal(external, 0, ptr::null(), 0, ptr::null(), ptr::null()) };
+            return;
+        }
+        if !self.share.private_caps.sync {
+            return;
+        }
+        let gl = &self.share.context;
+        unsafe {
+            let sync = gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+            if let Some(old) = semaphore.sync.lock().unwrap().replace(sync) {
+                gl.DeleteSync(old);
+            }
+        }
+    }
+
+    /// Make the server wait on the semaphore before executing any further
+    /// commands, which unsignals it.
+    fn wait_semaphore(&mut self, semaphore: &native::Semaphore) {
+        if let Some(external) = semaphore.external {
+            let wait = self.share.ext.semaphore.unwrap().wait_semaphore;
+            unsafe { wait(external, 0, ptr::null(), 0, ptr::null(), ptr::null()) };
+            return;
+        }
+        if let Some(sync) = semaphore.sync.lock().unwrap().take() {
+            let gl = &self.share.context;
+            unsafe {
+                gl.WaitSync(sync, 0, gl::TIMEOUT_IGNORED);
+                // The sync object stays alive until the wait is done.
+                gl.DeleteSync(sync);
+            }
+        }
+    }
+
     fn signal_fence(&mut self, fence: &native::Fence) {
-        if self.share.private_caps.sync {
-            let sync = if self.share.private_caps.sync {
-                let gl = &self.share.context;
-                unsafe { gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0) }
-            } else {
-                ptr::null()
-            };
+        let gl = &self.share.context;
+        if let native::FenceState::Pending(sync) = fence.0.get() {
+            // The fence wasn't reset since its last submission.
+            unsafe { gl.DeleteSync(sync) };
+        }
+        let state = if self.share.private_caps.sync {
+            let sync = unsafe { gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0) };
+            native::FenceState::Pending(sync)
+        } else {
+            // Without sync objects there is no way to track completion.
+            // Any access to the resources from the host side is implicitly
+            // synchronized by the driver, so consider the work done.
+            native::FenceState::Signalled
+        };
+        fence.0.set(state);
+    }
+}
+
+/// Size in bytes of the texels of a format, or of the blocks of compressed
+/// ones.
+fn texel_size(format: hal::format::Format) -> usize {
+    format.surface_desc().bits as usize / 8
+}
 
-            fence.0.set(sync);
+/// Bytes of texels transferred by a copy between a buffer and a texture,
+/// counting whole blocks of compressed formats.
+fn copy_size(format: hal::format::Format, r: &hal::command::BufferImageCopy) -> u64 {
+    let (block_width, block_height) = format.surface_desc().dim;
+    let extent = r.image_extent;
+    // Copies are never empty.
+    let blocks_x = (extent.width - 1) / block_width as u32 + 1;
+    let blocks_y = (extent.height - 1) / block_height as u32 + 1;
+    blocks_x as u64 * blocks_y as u64 * extent.depth as u64 * texel_size(format) as u64
+}
+
+/// Format and type of the texels of a copy between a buffer and a texture,
+/// and the byte pitch of its rows in the buffer.
+fn pixel_layout(
+    format: hal::format::Format,
+    r: &hal::command::BufferImageCopy,
+) -> (gl::types::GLenum, gl::types::GLenum, usize) {
+    // Imported images may have formats the backend doesn't create.
+    let (_, tex_format, ty) = conv::format_to_gl_texture_format(format).unwrap_or_else(|| {
+        error!("Unsupported format {:?} for copies", format);
+        (gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE)
+    });
+    let width = if r.buffer_width != 0 {
+        r.buffer_width
+    } else {
+        r.image_extent.width
+    };
+    (tex_format, ty, width as usize * texel_size(format))
+}
+
+/// Gather `rows` rows of `row_size` bytes, `pitch` bytes apart in `data`.
+fn pack_rows(data: &[u8], pitch: usize, row_size: usize, rows: usize) -> Vec<u8> {
+    let mut packed = Vec::with_capacity(row_size * rows);
+    for row in data.chunks(pitch).take(rows) {
+        packed.extend_from_slice(&row[..row_size]);
+    }
+    packed
+}
+
+// Set the depth range of the first viewport, ES only having the single
+// precision variant.
+fn set_depth_range(share: &Share, near: f64, far: f64) {
+    let gl = &share.context;
+    if share.info.version.is_embedded {
+        unsafe { gl.DepthRangef(near as _, far as _) };
+    } else {
+        unsafe { gl.DepthRange(near, far) };
+    }
+}
+
+/// Load a uniform from data laid out following the std140 rules, where
+/// each array element and matrix column is aligned to 16 bytes.
+fn load_std140_uniform(
+    gl: &GlContainer,
+    location: gl::types::GLint,
+    ty: gl::types::GLenum,
+    count: gl::types::GLint,
+    data: &[u8],
+) {
+    let (columns, rows, integer) = match ty {
+        gl::FLOAT => (1, 1, false),
+        gl::FLOAT_VEC2 => (1, 2, false),
+        gl::FLOAT_VEC3 => (1, 3, false),
+        gl::FLOAT_VEC4 => (1, 4, false),
+        gl::FLOAT_MAT2 => (2, 2, false),
+        gl::FLOAT_MAT3 => (3, 3, false),
+        gl::FLOAT_MAT4 => (4, 4, false),
+        gl::INT | gl::BOOL => (1, 1, true),
+        gl::INT_VEC2 | gl::BOOL_VEC2 => (1, 2, true),
+        gl::INT_VEC3 | gl::BOOL_VEC3 => (1, 3, true),
+        gl::INT_VEC4 | gl::BOOL_VEC4 => (1, 4, true),
+        _ => {
+            warn!(
+                "Unsupported type {:#x} of an emulated uniform block member",
+                ty
+            );
+            return;
+        }
+    };
+    let num_columns = count as usize * columns;
+    if data.len() < (num_columns - 1) * 16 + rows * 4 {
+        error!("Uniform buffer range too small for its block");
+        return;
+    }
+    let mut values = Vec::<u32>::with_capacity(num_columns * rows);
+    for column in 0..num_columns {
+        for row in 0..rows {
+            let start = column * 16 + row * 4;
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&data[start..start + 4]);
+            values.push(u32::from_ne_bytes(bytes));
+        }
+    }
+    let ints = values.as_ptr() as *const gl::types::GLint;
+    let floats = values.as_ptr() as *const gl::types::GLfloat;
+    unsafe {
+        match (columns, rows, integer) {
+            (1, 1, true) => gl.Uniform1iv(location, count, ints),
+            (1, 2, true) => gl.Uniform2iv(location, count, ints),
+            (1, 3, true) => gl.Uniform3iv(location, count, ints),
+            (1, 4, true) => gl.Uniform4iv(location, count, ints),
+            (1, 1, false) => gl.Uniform1fv(location, count, floats),
+            (1, 2, false) => gl.Uniform2fv(location, count, floats),
+            (1, 3, false) => gl.Uniform3fv(location, count, floats),
+            (1, 4, false) => gl.Uniform4fv(location, count, floats),
+            (2, _, _) => gl.UniformMatrix2fv(location, count, gl::FALSE, floats),
+            (3, _, _) => gl.UniformMatrix3fv(location, count, gl::FALSE, floats),
+            _ => gl.UniformMatrix4fv(location, count, gl::FALSE, floats),
         }
     }
 }
@@ -798,7 +1986,29 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
         Is: IntoIterator<Item = &'a S>,
     {
         use crate::pool::BufferMemory;
+        if let Some(worker) = self.worker.clone() {
+            if !worker.is_current() {
+                return worker.run_unchecked(|| self.submit(submit_info, fence));
+            }
+        }
+        if self.share.is_lost() {
+            // Executing anything on a lost context is undefined, fences will
+            // report the loss to the application.
+            warn!("Ignoring submission to a lost device");
+            return;
+        }
+        self.acquire_context();
+        // The thread is checked once, not for every executed command.
+        let share = self.share.clone();
+        let share = &*share;
+        for (semaphore, _) in submit_info.wait_semaphores {
+            self.wait_semaphore(semaphore.borrow());
+        }
         {
+            // Resources might have been destroyed since the last submission.
+            self.state.flush_bindings();
+            // All the command buffers of the submission are executed as a
+            // single stream, without flushing in between.
             for buf in submit_info.command_buffers {
                 let cb = buf.borrow();
                 let memory = cb
@@ -814,20 +2024,48 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
                 assert!(buffer.commands.len() >= (cb.buf.offset + cb.buf.size) as usize);
                 let commands = &buffer.commands
                     [cb.buf.offset as usize..(cb.buf.offset + cb.buf.size) as usize];
+                #[cfg(feature = "trace")]
+                {
+                    if let Some(ref recorder) = self.recorder {
+                        recorder
+                            .borrow_mut()
+                            .record(self.id, commands, &buffer.data);
+                    }
+                }
+                // Command buffers don't inherit the state set by the previous
+                // ones.
                 self.reset_state();
                 for com in commands {
-                    self.process(com, &buffer.data);
+                    self.process(share, com, &buffer.data);
                 }
             }
         }
+        for semaphore in submit_info.signal_semaphores {
+            self.signal_semaphore(semaphore.borrow());
+        }
+        if let Some(ref mut ring) = self.ring {
+            ring.end_submission(&self.share.context);
+        }
         fence.map(|fence| self.signal_fence(fence));
+        #[cfg(feature = "trace")]
+        {
+            if let Some(ref recorder) = self.recorder {
+                recorder.borrow_mut().end_submission();
+            }
+        }
+        if let Err(err) = self.share.check_at(ErrorCheck::Submit) {
+            error!("Error {:?} executing submission", err);
+        }
+        // Single flush for the whole submission, making sure the work
+        // (and the sync objects) reach the GPU.
+        self.share.context.Flush();
     }
 
     #[cfg(feature = "glutin")]
     unsafe fn present<'a, W, Is, S, Iw>(
         &mut self,
         swapchains: Is,
-        _wait_semaphores: Iw,
+        wait_semaphores: Iw,
     ) -> Result<(), ()>
     where
         W: 'a + Borrow<window::glutin::Swapchain>,
@@ -835,17 +2073,85 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
         S: 'a + Borrow<native::Semaphore>,
         Iw: IntoIterator<Item = &'a S>,
     {
-        for swapchain in swapchains {
-            swapchain.0.borrow().window.swap_buffers().unwrap();
+        if let Some(worker) = self.worker.clone() {
+            if !worker.is_current() {
+                return worker.run_unchecked(|| self.present(swapchains, wait_semaphores));
+            }
         }
+        for semaphore in wait_semaphores {
+            self.wait_semaphore(semaphore.borrow());
+        }
+        for (swapchain, _) in swapchains {
+            let swapchain = swapchain.borrow();
+            if swapchain.readback {
+                self.read_back(swapchain);
+            }
+            if let Err(err) = swapchain.present() {
+                error!("Error presenting swapchain: {:?}", err);
+                return Err(());
+            }
+        }
+        self.share.end_frame();
 
         Ok(())
     }
 
     fn wait_idle(&self) -> Result<(), error::HostExecutionError> {
+        match self.worker {
+            Some(ref worker) => unsafe { worker.run_unchecked(|| self.share.wait_idle()) },
+            None => self.share.wait_idle(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter;
+
+    use crate::hal::command::{CommandBuffer, OneShot};
+    use crate::hal::pool::CommandPoolCreateFlags;
+    use crate::hal::pso::{Rect, Viewport};
+    use crate::hal::{Device as _, Graphics};
+    use crate::mock;
+
+    #[test]
+    fn test_viewport_scissor_translation() {
+        let (device, mut queue_group) = mock::open();
+        let mut pool = unsafe {
+            device.create_command_pool_typed(&queue_group, CommandPoolCreateFlags::empty())
+        }
+        .unwrap();
+        let mut cmd_buffer: CommandBuffer<_, Graphics, OneShot> = pool.acquire_command_buffer();
+        let rect = Rect {
+            x: 0,
+            y: 0,
+            w: 4,
+            h: 2,
+        };
+        let viewport = Viewport {
+            rect,
+            depth: 0.0..1.0,
+        };
         unsafe {
-            self.share.context.Finish();
+            cmd_buffer.begin();
+            cmd_buffer.set_viewports(0, iter::once(viewport));
+            cmd_buffer.set_scissors(0, iter::once(rect));
+            cmd_buffer.set_blend_constants([1.0, 0.0, 0.0, 1.0]);
+            cmd_buffer.finish();
+            queue_group.queues[0].submit_nosemaphores(iter::once(&cmd_buffer), None);
         }
-        Ok(())
+
+        assert_eq!(
+            mock::take_calls(),
+            [
+                "BindVertexArray(1)",
+                "BindBuffer(36671, 0)",
+                "Viewport(0, 0, 4, 2)",
+                "DepthRange(0.0, 1.0)",
+                "Scissor(0, 0, 4, 2)",
+                "BlendColor(1.0, 0.0, 0.0, 1.0)",
+                "Flush()",
+            ]
+        );
     }
 }
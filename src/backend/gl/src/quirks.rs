@@ -0,0 +1,83 @@
+//! Known driver bugs, and how to route around them.
+//!
+//! Each quirk matches on the platform strings and the driver version, and
+//! disables the private capabilities whose code paths are broken or
+//! unreasonably slow on the affected drivers. Only issues with a public
+//! report are listed, and each entry links to it.
+
+use std::ops::Range;
+
+use crate::info::{DriverVersion, Info, PrivateCaps};
+
+struct Quirk {
+    /// Logged when the quirk is applied.
+    description: &'static str,
+    /// Bug report or release notes documenting the issue.
+    source: &'static str,
+    /// Lowercase substring of the vendor string, if the quirk is vendor specific.
+    vendor: Option<&'static str>,
+    /// Lowercase substring of the renderer string, if the quirk is renderer specific.
+    renderer: Option<&'static str>,
+    /// Affected driver versions, if the issue has been fixed.
+    driver_versions: Option<Range<DriverVersion>>,
+    apply: fn(&mut PrivateCaps),
+}
+
+impl Quirk {
+    fn matches(&self, vendor: &str, renderer: &str, driver: Option<DriverVersion>) -> bool {
+        self.vendor.map_or(true, |v| vendor.contains(v))
+            && self.renderer.map_or(true, |r| renderer.contains(r))
+            && match (&self.driver_versions, driver) {
+                (Some(range), Some(version)) => range.start <= version && version < range.end,
+                // Assume the worst if the version can't be parsed.
+                (Some(_), None) | (None, _) => true,
+            }
+    }
+}
+
+const QUIRKS: &[Quirk] = &[];
+
+/// Apply the quirks matching the implementation to the capabilities.
+pub(crate) fn apply(info: &Info, caps: &mut PrivateCaps) {
+    let vendor = info.platform_name.vendor.to_lowercase();
+    let renderer = info.platform_name.renderer.to_lowercase();
+    for quirk in QUIRKS {
+        if quirk.matches(&vendor, &renderer, info.driver_version) {
+            info!(
+                "Applying driver quirk: {} ({})",
+                quirk.description, quirk.source
+            );
+            (quirk.apply)(caps);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Quirk;
+    use crate::info::DriverVersion;
+
+    const fn version(major: u32, minor: u32) -> DriverVersion {
+        DriverVersion {
+            major,
+            minor,
+            patch: None,
+        }
+    }
+
+    #[test]
+    fn test_quirk_matches() {
+        let quirk = Quirk {
+            description: "",
+            source: "",
+            vendor: Some("vendor"),
+            renderer: None,
+            driver_versions: Some(version(1, 0)..version(2, 0)),
+            apply: |_| (),
+        };
+        assert!(quirk.matches("vendor", "renderer", Some(version(1, 5))));
+        assert!(quirk.matches("vendor", "renderer", None));
+        assert!(!quirk.matches("vendor", "renderer", Some(version(2, 0))));
+        assert!(!quirk.matches("other", "renderer", Some(version(1, 5))));
+    }
+}
@@ -0,0 +1,122 @@
+//! In-application RenderDoc API, used to trigger frame captures.
+//!
+//! The API is only available when RenderDoc injected itself into the process,
+//! the library is never loaded by the backend.
+
+use std::os::raw::{c_char, c_int, c_void};
+use std::{fmt, ptr};
+
+type GetApi = extern "C" fn(version: u32, out: *mut *mut c_void) -> c_int;
+type StartFrameCapture = extern "C" fn(device: *mut c_void, window: *mut c_void);
+type IsFrameCapturing = extern "C" fn() -> u32;
+type EndFrameCapture = extern "C" fn(device: *mut c_void, window: *mut c_void) -> u32;
+
+const API_VERSION_1_0_0: u32 = 10000;
+
+/// Prefix of `RENDERDOC_API_1_0_0`, up to the frame capture functions.
+#[repr(C)]
+struct Api {
+    _unused: [*const c_void; 19],
+    start_frame_capture: StartFrameCapture,
+    is_frame_capturing: IsFrameCapturing,
+    end_frame_capture: EndFrameCapture,
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+unsafe fn get_api_entry() -> Option<GetApi> {
+    const RTLD_NOW: c_int = 2;
+    const RTLD_NOLOAD: c_int = 4;
+    #[cfg(target_os = "android")]
+    const LIBRARY: &[u8] = b"libVkLayer_GLES_RenderDoc.so\0";
+    #[cfg(not(target_os = "android"))]
+    const LIBRARY: &[u8] = b"librenderdoc.so\0";
+
+    extern "C" {
+        fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+        fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    }
+
+    let library = dlopen(LIBRARY.as_ptr() as *const _, RTLD_NOW | RTLD_NOLOAD);
+    if library.is_null() {
+        return None;
+    }
+    let entry = dlsym(library, b"RENDERDOC_GetAPI\0".as_ptr() as *const _);
+    if entry.is_null() {
+        None
+    } else {
+        Some(std::mem::transmute::<*mut c_void, GetApi>(entry))
+    }
+}
+
+#[cfg(windows)]
+unsafe fn get_api_entry() -> Option<GetApi> {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetModuleHandleA(name: *const c_char) -> *mut c_void;
+        fn GetProcAddress(module: *mut c_void, name: *const c_char) -> *mut c_void;
+    }
+
+    let module = GetModuleHandleA(b"renderdoc.dll\0".as_ptr() as *const _);
+    if module.is_null() {
+        return None;
+    }
+    let entry = GetProcAddress(module, b"RENDERDOC_GetAPI\0".as_ptr() as *const _);
+    if entry.is_null() {
+        None
+    } else {
+        Some(std::mem::transmute::<*mut c_void, GetApi>(entry))
+    }
+}
+
+#[cfg(not(any(windows, all(unix, not(target_os = "macos")))))]
+unsafe fn get_api_entry() -> Option<GetApi> {
+    None
+}
+
+/// Loaded RenderDoc API.
+pub(crate) struct RenderDoc {
+    api: *const Api,
+}
+
+// The API table is immutable and its functions are thread-safe.
+unsafe impl Send for RenderDoc {}
+unsafe impl Sync for RenderDoc {}
+
+impl fmt::Debug for RenderDoc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("RenderDoc")
+    }
+}
+
+impl RenderDoc {
+    /// Get the API of the injected RenderDoc library, if any.
+    pub(crate) fn load() -> Option<Self> {
+        unsafe {
+            let get_api = get_api_entry()?;
+            let mut api = ptr::null_mut();
+            if get_api(API_VERSION_1_0_0, &mut api) != 1 || api.is_null() {
+                warn!("Failed to get the RenderDoc API");
+                return None;
+            }
+            info!("RenderDoc API loaded");
+            Some(RenderDoc {
+                api: api as *const Api,
+            })
+        }
+    }
+
+    // Null device and window pointers match the current context and any
+    // window rendered to by it.
+
+    pub(crate) fn start_capture(&self) {
+        unsafe { ((*self.api).start_frame_capture)(ptr::null_mut(), ptr::null_mut()) }
+    }
+
+    pub(crate) fn is_capturing(&self) -> bool {
+        unsafe { ((*self.api).is_frame_capturing)() != 0 }
+    }
+
+    pub(crate) fn end_capture(&self) -> bool {
+        unsafe { ((*self.api).end_frame_capture)(ptr::null_mut(), ptr::null_mut()) != 0 }
+    }
+}
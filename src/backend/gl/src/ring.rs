@@ -0,0 +1,125 @@
+//! Streaming ring buffer for small buffer updates.
+//!
+//! Updates are written to a persistently mapped buffer and copied to their
+//! destination on the GPU, instead of going through `glBufferSubData` which
+//! makes many drivers synchronize with the GPU when the destination is still
+//! in use. A region of the ring is only overwritten once the fence inserted
+//! after the submission reading it has been signalled.
+//!
+//! Only `update_buffer` streams through the ring. Push constants aren't
+//! emulated by the backend, so there is nothing else to feed it yet.
+
+use std::collections::VecDeque;
+use std::ptr;
+
+use crate::gl::{self, types::*};
+use crate::{GlContainer, Share};
+
+/// Size of the ring, updates bigger than this take the slow path.
+const RING_SIZE: u64 = 4 << 20;
+/// Alignment of the updates within the ring.
+const ALIGNMENT: u64 = 16;
+
+pub(crate) struct RingBuffer {
+    raw: GLuint,
+    ptr: *mut u8,
+    // Total number of bytes allocated, the write offset being `head % RING_SIZE`.
+    head: u64,
+    // Position up to which the GPU is done reading.
+    tail: u64,
+    // Fences of the past submissions, with the head at their end.
+    fences: VecDeque<(GLsync, u64)>,
+}
+
+// The mapping is only accessed by the queue owning the ring.
+unsafe impl Send for RingBuffer {}
+unsafe impl Sync for RingBuffer {}
+
+impl RingBuffer {
+    /// Create the ring, if persistent mappings and fences are supported.
+    pub(crate) fn new(share: &Share) -> Option<Self> {
+        if !share.private_caps.buffer_storage || !share.private_caps.sync {
+            return None;
+        }
+        let gl = &share.context;
+        let flags = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
+        let mut raw = 0;
+        let ptr = unsafe {
+            gl.GenBuffers(1, &mut raw);
+            gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, raw);
+            gl.BufferStorage(gl::PIXEL_UNPACK_BUFFER, RING_SIZE as _, ptr::null(), flags);
+            let ptr = gl.MapBufferRange(gl::PIXEL_UNPACK_BUFFER, 0, RING_SIZE as _, flags);
+            gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+            ptr as *mut u8
+        };
+        if ptr.is_null() || share.check().is_err() {
+            warn!("Failed to create the streaming ring buffer");
+            unsafe { gl.DeleteBuffers(1, &raw) };
+            return None;
+        }
+        Some(RingBuffer {
+            raw,
+            ptr,
+            head: 0,
+            tail: 0,
+            fences: VecDeque::new(),
+        })
+    }
+
+    pub(crate) fn raw(&self) -> GLuint {
+        self.raw
+    }
+
+    /// Write data to the ring, returns its offset in the ring buffer.
+    ///
+    /// Blocks until the GPU is done with the region being reused, returns
+    /// `None` if the data doesn't fit in the ring along with the data
+    /// written by the current submission.
+    pub(crate) fn write(&mut self, gl: &GlContainer, data: &[u8]) -> Option<u64> {
+        let size = data.len() as u64;
+        if size > RING_SIZE {
+            return None;
+        }
+        let mut start = (self.head + ALIGNMENT - 1) & !(ALIGNMENT - 1);
+        // Updates never wrap around the end of the ring.
+        if start % RING_SIZE + size > RING_SIZE {
+            start += RING_SIZE - start % RING_SIZE;
+        }
+        let end = start + size;
+        while end - self.tail > RING_SIZE {
+            let (sync, position) = self.fences.pop_front()?;
+            unsafe {
+                gl.ClientWaitSync(sync, gl::SYNC_FLUSH_COMMANDS_BIT, !0);
+                gl.DeleteSync(sync);
+            }
+            self.tail = position;
+        }
+
+        let offset = start % RING_SIZE;
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), self.ptr.add(offset as usize), data.len());
+        }
+        self.head = end;
+        Some(offset)
+    }
+
+    /// Guard the data written by the current submission.
+    pub(crate) fn end_submission(&mut self, gl: &GlContainer) {
+        let last = self
+            .fences
+            .back()
+            .map_or(self.tail, |&(_, position)| position);
+        if self.head != last {
+            let sync = unsafe { gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0) };
+            self.fences.push_back((sync, self.head));
+        }
+    }
+
+    pub(crate) unsafe fn destroy(self, gl: &GlContainer) {
+        for (sync, _) in self.fences {
+            gl.DeleteSync(sync);
+        }
+        // Deleting the buffer unmaps it.
+        gl.DeleteBuffers(1, &self.raw);
+    }
+}
@@ -2,75 +2,124 @@
 
 use crate::hal::pso;
 use crate::hal::ColorSlot;
-use smallvec::SmallVec;
+
+use crate::ext::DepthBoundsTest;
 use crate::{gl, GlContainer};
 
-pub(crate) fn bind_polygon_mode(
-    gl: &GlContainer,
-    mode: pso::PolygonMode,
-    bias: Option<pso::State<pso::DepthBias>>,
-) {
-    use crate::hal::pso::PolygonMode::*;
+/// Polygon rasterization mode, translated to GL values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "trace", derive(Serialize, Deserialize))]
+pub struct PolygonModeState {
+    mode: gl::types::GLenum,
+    // Polygon offset capability matching the mode.
+    offset: gl::types::GLenum,
+    // Slope and constant factors of the static depth bias.
+    depth_bias: Option<(f32, f32)>,
+}
+
+impl PolygonModeState {
+    pub(crate) fn new(mode: pso::PolygonMode, bias: Option<pso::State<pso::DepthBias>>) -> Self {
+        use crate::hal::pso::PolygonMode::*;
 
-    let (gl_draw, gl_offset) = match mode {
-        Point => (gl::POINT, gl::POLYGON_OFFSET_POINT),
-        Line(width) => {
-            unsafe { gl.LineWidth(width) };
-            (gl::LINE, gl::POLYGON_OFFSET_LINE)
+        let (mode, offset) = match mode {
+            Point => (gl::POINT, gl::POLYGON_OFFSET_POINT),
+            Line(_) => (gl::LINE, gl::POLYGON_OFFSET_LINE),
+            Fill => (gl::FILL, gl::POLYGON_OFFSET_FILL),
+        };
+        let depth_bias = match bias {
+            Some(pso::State::Static(bias)) => Some((bias.slope_factor, bias.const_factor)),
+            _ => None,
+        };
+        PolygonModeState {
+            mode,
+            offset,
+            depth_bias,
         }
-        Fill => (gl::FILL, gl::POLYGON_OFFSET_FILL),
-    };
+    }
 
-    unsafe { gl.PolygonMode(gl::FRONT_AND_BACK, gl_draw) };
+    pub(crate) fn bind(&self, gl: &GlContainer) {
+        unsafe { gl.PolygonMode(gl::FRONT_AND_BACK, self.mode) };
 
-    match bias {
-        Some(pso::State::Static(bias)) => unsafe {
-            gl.Enable(gl_offset);
-            gl.PolygonOffset(bias.slope_factor as _, bias.const_factor as _);
-        },
-        _ => unsafe { gl.Disable(gl_offset) },
+        match self.depth_bias {
+            Some((slope_factor, const_factor)) => unsafe {
+                gl.Enable(self.offset);
+                gl.PolygonOffset(slope_factor, const_factor);
+            },
+            None => unsafe { gl.Disable(self.offset) },
+        }
     }
 }
 
-pub(crate) fn bind_rasterizer(gl: &GlContainer, r: &pso::Rasterizer, is_embedded: bool) {
-    use crate::hal::pso::FrontFace::*;
+/// Rasterizer state, translated to GL values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "trace", derive(Serialize, Deserialize))]
+pub struct RasterizerState {
+    front_face: gl::types::GLenum,
+    // Culled faces, `None` if culling is disabled.
+    cull_face: Option<gl::types::GLenum>,
+    // Not available on GLES.
+    polygon_mode: Option<PolygonModeState>,
+    // Static line width, set apart from the rest of the state as it may
+    // also be set dynamically.
+    line_width: Option<f32>,
+}
 
-    unsafe {
-        gl.FrontFace(match r.front_face {
-            Clockwise => gl::CW,
-            CounterClockwise => gl::CCW,
-        })
-    };
+impl RasterizerState {
+    pub(crate) fn new(r: &pso::Rasterizer, is_embedded: bool) -> Self {
+        use crate::hal::pso::FrontFace::*;
 
-    if !r.cull_face.is_empty() {
-        unsafe {
-            gl.Enable(gl::CULL_FACE);
-            gl.CullFace(match r.cull_face {
-                pso::Face::FRONT => gl::FRONT,
-                pso::Face::BACK => gl::BACK,
-                _ => gl::FRONT_AND_BACK,
-            });
-        }
-    } else {
-        unsafe {
-            gl.Disable(gl::CULL_FACE);
+        RasterizerState {
+            front_face: match r.front_face {
+                Clockwise => gl::CW,
+                CounterClockwise => gl::CCW,
+            },
+            cull_face: if r.cull_face.is_empty() {
+                None
+            } else {
+                Some(match r.cull_face {
+                    pso::Face::FRONT => gl::FRONT,
+                    pso::Face::BACK => gl::BACK,
+                    _ => gl::FRONT_AND_BACK,
+                })
+            },
+            polygon_mode: if is_embedded {
+                None
+            } else {
+                Some(PolygonModeState::new(r.polygon_mode, r.depth_bias))
+            },
+            line_width: match r.polygon_mode {
+                pso::PolygonMode::Line(width) => Some(width),
+                _ => None,
+            },
         }
     }
 
-    if !is_embedded {
-        bind_polygon_mode(gl, r.polygon_mode, r.depth_bias);
-        match false {
-            //TODO
-            true => unsafe { gl.Enable(gl::MULTISAMPLE) },
-            false => unsafe { gl.Disable(gl::MULTISAMPLE) },
-        }
+    pub(crate) fn line_width(&self) -> Option<f32> {
+        self.line_width
     }
-}
 
-pub(crate) fn bind_draw_color_buffers(gl: &GlContainer, num: usize) {
-    let attachments: SmallVec<[gl::types::GLenum; 16]> =
-        (0..num).map(|x| gl::COLOR_ATTACHMENT0 + x as u32).collect();
-    unsafe { gl.DrawBuffers(num as gl::types::GLint, attachments.as_ptr()) };
+    pub(crate) fn bind(&self, gl: &GlContainer) {
+        unsafe { gl.FrontFace(self.front_face) };
+
+        match self.cull_face {
+            Some(face) => unsafe {
+                gl.Enable(gl::CULL_FACE);
+                gl.CullFace(face);
+            },
+            None => unsafe {
+                gl.Disable(gl::CULL_FACE);
+            },
+        }
+
+        if let Some(ref polygon_mode) = self.polygon_mode {
+            polygon_mode.bind(gl);
+            match false {
+                //TODO
+                true => unsafe { gl.Enable(gl::MULTISAMPLE) },
+                false => unsafe { gl.Disable(gl::MULTISAMPLE) },
+            }
+        }
+    }
 }
 
 pub fn map_comparison(cmp: pso::Comparison) -> gl::types::GLenum {
@@ -87,16 +136,43 @@ pub fn map_comparison(cmp: pso::Comparison) -> gl::types::GLenum {
     }
 }
 
-pub(crate) fn bind_depth(gl: &GlContainer, depth: &pso::DepthTest) {
-    match *depth {
-        pso::DepthTest::On { fun, write } => unsafe {
-            gl.Enable(gl::DEPTH_TEST);
-            gl.DepthFunc(map_comparison(fun));
-            gl.DepthMask(write as _);
-        },
-        pso::DepthTest::Off => unsafe {
-            gl.Disable(gl::DEPTH_TEST);
-        },
+/// Depth test state, translated to GL values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "trace", derive(Serialize, Deserialize))]
+pub struct DepthState {
+    // Comparison function and depth write mask, `None` if the test is disabled.
+    test: Option<(gl::types::GLenum, gl::types::GLboolean)>,
+    // Whether the depth bounds test is enabled, `None` if it isn't supported.
+    bounds_test: Option<bool>,
+}
+
+impl DepthState {
+    pub(crate) fn new(depth: &pso::DepthTest, bounds_test: Option<bool>) -> Self {
+        DepthState {
+            test: match *depth {
+                pso::DepthTest::On { fun, write } => Some((map_comparison(fun), write as _)),
+                pso::DepthTest::Off => None,
+            },
+            bounds_test,
+        }
+    }
+
+    pub(crate) fn bind(&self, gl: &GlContainer) {
+        match self.test {
+            Some((fun, write)) => unsafe {
+                gl.Enable(gl::DEPTH_TEST);
+                gl.DepthFunc(fun);
+                gl.DepthMask(write);
+            },
+            None => unsafe {
+                gl.Disable(gl::DEPTH_TEST);
+            },
+        }
+        match self.bounds_test {
+            Some(true) => unsafe { gl.Enable(DepthBoundsTest::DEPTH_BOUNDS_TEST) },
+            Some(false) => unsafe { gl.Disable(DepthBoundsTest::DEPTH_BOUNDS_TEST) },
+            None => {}
+        }
     }
 }
 
@@ -114,55 +190,103 @@ fn map_operation(op: pso::StencilOp) -> gl::types::GLenum {
     }
 }
 
-pub(crate) fn bind_stencil(
-    gl: &GlContainer,
-    stencil: &pso::StencilTest,
-    (ref_front, ref_back): (pso::StencilValue, pso::StencilValue),
-    cull: Option<pso::Face>,
-) {
-    fn bind_side(
-        gl: &GlContainer,
-        face: gl::types::GLenum,
-        side: &pso::StencilFace,
-        ref_value: pso::StencilValue,
-    ) {
-        unsafe {
-            let mr = match side.mask_read {
+/// Stencil state of a face, translated to GL values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "trace", derive(Serialize, Deserialize))]
+pub struct StencilFaceState {
+    fun: gl::types::GLenum,
+    reference: gl::types::GLint,
+    read_mask: gl::types::GLuint,
+    write_mask: gl::types::GLuint,
+    // Operations on stencil fail, depth fail and pass.
+    ops: [gl::types::GLenum; 3],
+}
+
+impl StencilFaceState {
+    fn new(side: &pso::StencilFace, reference: pso::StencilValue) -> Self {
+        StencilFaceState {
+            fun: map_comparison(side.fun),
+            reference: reference as _,
+            read_mask: match side.mask_read {
                 pso::State::Static(v) => v,
                 pso::State::Dynamic => !0,
-            };
-            let mw = match side.mask_write {
+            },
+            write_mask: match side.mask_write {
                 pso::State::Static(v) => v,
                 pso::State::Dynamic => !0,
-            };
-            gl.StencilFuncSeparate(face, map_comparison(side.fun), ref_value as _, mr);
-            gl.StencilMaskSeparate(face, mw);
-            gl.StencilOpSeparate(
-                face,
+            },
+            ops: [
                 map_operation(side.op_fail),
                 map_operation(side.op_depth_fail),
                 map_operation(side.op_pass),
-            );
+            ],
         }
     }
-    match *stencil {
-        pso::StencilTest::On {
-            ref front,
-            ref back,
-        } => {
-            unsafe { gl.Enable(gl::STENCIL_TEST) };
-            if let Some(cf) = cull {
-                if !cf.contains(pso::Face::FRONT) {
-                    bind_side(gl, gl::FRONT, front, ref_front);
-                }
-                if !cf.contains(pso::Face::BACK) {
-                    bind_side(gl, gl::BACK, back, ref_back);
-                }
-            }
+
+    fn bind(&self, gl: &GlContainer, face: gl::types::GLenum) {
+        unsafe {
+            gl.StencilFuncSeparate(face, self.fun, self.reference, self.read_mask);
+            gl.StencilMaskSeparate(face, self.write_mask);
+            gl.StencilOpSeparate(face, self.ops[0], self.ops[1], self.ops[2]);
+        }
+    }
+}
+
+/// Stencil state, translated to GL values along with the dynamic reference
+/// values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "trace", derive(Serialize, Deserialize))]
+pub struct StencilState {
+    enabled: bool,
+    // State of the front and back faces, `None` for the culled ones, which
+    // are left untouched.
+    front: Option<StencilFaceState>,
+    back: Option<StencilFaceState>,
+}
+
+impl StencilState {
+    pub(crate) fn new(
+        stencil: &pso::StencilTest,
+        (ref_front, ref_back): (pso::StencilValue, pso::StencilValue),
+        cull: pso::Face,
+    ) -> Self {
+        match *stencil {
+            pso::StencilTest::On {
+                ref front,
+                ref back,
+            } => StencilState {
+                enabled: true,
+                front: if cull.contains(pso::Face::FRONT) {
+                    None
+                } else {
+                    Some(StencilFaceState::new(front, ref_front))
+                },
+                back: if cull.contains(pso::Face::BACK) {
+                    None
+                } else {
+                    Some(StencilFaceState::new(back, ref_back))
+                },
+            },
+            pso::StencilTest::Off => StencilState {
+                enabled: false,
+                front: None,
+                back: None,
+            },
+        }
+    }
+
+    pub(crate) fn bind(&self, gl: &GlContainer) {
+        if !self.enabled {
+            unsafe { gl.Disable(gl::STENCIL_TEST) };
+            return;
+        }
+        unsafe { gl.Enable(gl::STENCIL_TEST) };
+        if let Some(ref front) = self.front {
+            front.bind(gl, gl::FRONT);
+        }
+        if let Some(ref back) = self.back {
+            back.bind(gl, gl::BACK);
         }
-        pso::StencilTest::Off => unsafe {
-            gl.Disable(gl::STENCIL_TEST);
-        },
     }
 }
 
@@ -231,32 +355,64 @@ pub(crate) fn bind_blend(gl: &GlContainer, desc: &pso::ColorBlendDesc) {
     };
 }
 
-pub(crate) fn bind_blend_slot(gl: &GlContainer, slot: ColorSlot, desc: &pso::ColorBlendDesc) {
-    use crate::hal::pso::ColorMask as Cm;
+/// Blend equations and factors, translated to GL values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "trace", derive(Serialize, Deserialize))]
+pub struct BlendEquation {
+    // Equation, source and destination factors of the color and alpha.
+    color: (gl::types::GLenum, gl::types::GLenum, gl::types::GLenum),
+    alpha: (gl::types::GLenum, gl::types::GLenum, gl::types::GLenum),
+}
 
-    match desc.1 {
-        pso::BlendState::On { color, alpha } => unsafe {
-            let (color_eq, color_src, color_dst) = map_blend_op(color);
-            let (alpha_eq, alpha_src, alpha_dst) = map_blend_op(alpha);
-            //Note: using ARB functions as they are more compatible
-            gl.Enablei(gl::BLEND, slot as _);
-            gl.BlendEquationSeparateiARB(slot as _, color_eq, alpha_eq);
-            gl.BlendFuncSeparateiARB(slot as _, color_src, color_dst, alpha_src, alpha_dst);
-        },
-        pso::BlendState::Off => unsafe {
-            gl.Disablei(gl::BLEND, slot as _);
-        },
-    };
+/// Blend state of a color slot, translated to GL values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "trace", derive(Serialize, Deserialize))]
+pub struct BlendSlotState {
+    // `None` if blending is disabled.
+    blend: Option<BlendEquation>,
+    color_mask: [gl::types::GLboolean; 4],
+}
 
-    unsafe {
-        gl.ColorMaski(
-            slot as _,
-            desc.0.contains(Cm::RED) as _,
-            desc.0.contains(Cm::GREEN) as _,
-            desc.0.contains(Cm::BLUE) as _,
-            desc.0.contains(Cm::ALPHA) as _,
-        )
-    };
+impl BlendSlotState {
+    pub(crate) fn new(desc: &pso::ColorBlendDesc) -> Self {
+        use crate::hal::pso::ColorMask as Cm;
+
+        BlendSlotState {
+            blend: match desc.1 {
+                pso::BlendState::On { color, alpha } => Some(BlendEquation {
+                    color: map_blend_op(color),
+                    alpha: map_blend_op(alpha),
+                }),
+                pso::BlendState::Off => None,
+            },
+            color_mask: [
+                desc.0.contains(Cm::RED) as _,
+                desc.0.contains(Cm::GREEN) as _,
+                desc.0.contains(Cm::BLUE) as _,
+                desc.0.contains(Cm::ALPHA) as _,
+            ],
+        }
+    }
+
+    pub(crate) fn bind(&self, gl: &GlContainer, slot: ColorSlot) {
+        match self.blend {
+            Some(BlendEquation {
+                color: (color_eq, color_src, color_dst),
+                alpha: (alpha_eq, alpha_src, alpha_dst),
+            }) => unsafe {
+                //Note: using ARB functions as they are more compatible
+                gl.Enablei(gl::BLEND, slot as _);
+                gl.BlendEquationSeparateiARB(slot as _, color_eq, alpha_eq);
+                gl.BlendFuncSeparateiARB(slot as _, color_src, color_dst, alpha_src, alpha_dst);
+            },
+            None => unsafe {
+                gl.Disablei(gl::BLEND, slot as _);
+            },
+        };
+
+        let [red, green, blue, alpha] = self.color_mask;
+        unsafe { gl.ColorMaski(slot as _, red, green, blue, alpha) };
+    }
 }
 
 pub(crate) fn unlock_color_mask(gl: &GlContainer) {
@@ -266,3 +422,248 @@ pub(crate) fn unlock_color_mask(gl: &GlContainer) {
 pub(crate) fn set_blend_color(gl: &GlContainer, color: pso::ColorValue) {
     unsafe { gl.BlendColor(color[0], color[1], color[2], color[3]) };
 }
+
+/// Shadow copy of the fixed-function state of the context, used to only
+/// issue GL calls for actual state changes.
+///
+/// `None` denotes that the state is unknown, for example after another
+/// queue submitted work to the context. The `bind_*` methods return
+/// `false` if the state was already set.
+#[derive(Debug, Default)]
+pub(crate) struct Shadow {
+    rasterizer: Option<RasterizerState>,
+    depth: Option<DepthState>,
+    stencil: Option<StencilState>,
+    blend_slots: Vec<Option<BlendSlotState>>,
+    blend_color: Option<pso::ColorValue>,
+    line_width: Option<f32>,
+    line_smooth: Option<bool>,
+    depth_bounds: Option<(f32, f32)>,
+    framebuffer_srgb: Option<bool>,
+    // Row lengths of the pixel transfers from and to client memory.
+    unpack_row_length: Option<gl::types::GLint>,
+    pack_row_length: Option<gl::types::GLint>,
+}
+
+impl Shadow {
+    /// Forget about the current state.
+    pub(crate) fn invalidate(&mut self) {
+        *self = Shadow::default();
+    }
+
+    pub(crate) fn bind_rasterizer(
+        &mut self,
+        gl: &GlContainer,
+        rasterizer: &RasterizerState,
+    ) -> bool {
+        if self.rasterizer.as_ref() == Some(rasterizer) {
+            return false;
+        }
+        rasterizer.bind(gl);
+        self.rasterizer = Some(*rasterizer);
+        true
+    }
+
+    pub(crate) fn bind_depth(&mut self, gl: &GlContainer, depth: &DepthState) -> bool {
+        if self.depth.as_ref() == Some(depth) {
+            return false;
+        }
+        depth.bind(gl);
+        self.depth = Some(*depth);
+        true
+    }
+
+    pub(crate) fn bind_stencil(&mut self, gl: &GlContainer, stencil: &StencilState) -> bool {
+        if self.stencil.as_ref() == Some(stencil) {
+            return false;
+        }
+        stencil.bind(gl);
+        self.stencil = Some(*stencil);
+        true
+    }
+
+    pub(crate) fn bind_blend_slot(
+        &mut self,
+        gl: &GlContainer,
+        slot: ColorSlot,
+        blend: &BlendSlotState,
+    ) -> bool {
+        let index = slot as usize;
+        if self.blend_slots.len() <= index {
+            self.blend_slots.resize(index + 1, None);
+        }
+        if self.blend_slots[index].as_ref() == Some(blend) {
+            return false;
+        }
+        blend.bind(gl, slot);
+        self.blend_slots[index] = Some(*blend);
+        true
+    }
+
+    /// Enable writes to every channel of a color slot, for clears which
+    /// ignore the pipeline masks.
+    pub(crate) fn unlock_color_mask(&mut self, gl: &GlContainer, slot: ColorSlot) -> bool {
+        const FULL: [gl::types::GLboolean; 4] = [gl::TRUE; 4];
+        let index = slot as usize;
+        if self.blend_slots.len() <= index {
+            self.blend_slots.resize(index + 1, None);
+        }
+        let cached = &mut self.blend_slots[index];
+        if cached.map(|blend| blend.color_mask) == Some(FULL) {
+            return false;
+        }
+        unsafe { gl.ColorMaski(slot as _, gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE) };
+        if let Some(ref mut blend) = *cached {
+            blend.color_mask = FULL;
+        }
+        true
+    }
+
+    /// Enable depth writes, for clears which ignore the pipeline mask.
+    pub(crate) fn unlock_depth_mask(&mut self, gl: &GlContainer) -> bool {
+        // The mask of a disabled depth test isn't tracked.
+        if let Some(DepthState {
+            test: Some((_, gl::TRUE)),
+            ..
+        }) = self.depth
+        {
+            return false;
+        }
+        unsafe { gl.DepthMask(gl::TRUE) };
+        if let Some(DepthState {
+            test: Some((_, ref mut write)),
+            ..
+        }) = self.depth
+        {
+            *write = gl::TRUE;
+        }
+        true
+    }
+
+    /// Enable stencil writes on both faces, for clears which ignore the
+    /// pipeline masks.
+    pub(crate) fn unlock_stencil_mask(&mut self, gl: &GlContainer) -> bool {
+        // The masks of disabled tests and culled faces aren't tracked.
+        let unlocked = |face: Option<StencilFaceState>| face.map(|f| f.write_mask) == Some(!0);
+        if let Some(stencil) = self.stencil {
+            if unlocked(stencil.front) && unlocked(stencil.back) {
+                return false;
+            }
+        }
+        unsafe { gl.StencilMaskSeparate(gl::FRONT_AND_BACK, !0) };
+        if let Some(ref mut stencil) = self.stencil {
+            for face in stencil.front.iter_mut().chain(stencil.back.iter_mut()) {
+                face.write_mask = !0;
+            }
+        }
+        true
+    }
+
+    pub(crate) fn set_blend_color(&mut self, gl: &GlContainer, color: pso::ColorValue) -> bool {
+        if self.blend_color == Some(color) {
+            return false;
+        }
+        set_blend_color(gl, color);
+        self.blend_color = Some(color);
+        true
+    }
+
+    pub(crate) fn set_line_width(&mut self, gl: &GlContainer, width: f32) -> bool {
+        if self.line_width == Some(width) {
+            return false;
+        }
+        unsafe { gl.LineWidth(width) };
+        self.line_width = Some(width);
+        true
+    }
+
+    pub(crate) fn set_depth_bounds(&mut self, ext: &DepthBoundsTest, min: f32, max: f32) -> bool {
+        if self.depth_bounds == Some((min, max)) {
+            return false;
+        }
+        unsafe { (ext.depth_bounds)(min as _, max as _) };
+        self.depth_bounds = Some((min, max));
+        true
+    }
+
+    pub(crate) fn set_framebuffer_srgb(&mut self, gl: &GlContainer, enable: bool) -> bool {
+        if self.framebuffer_srgb == Some(enable) {
+            return false;
+        }
+        if enable {
+            unsafe { gl.Enable(gl::FRAMEBUFFER_SRGB) };
+        } else {
+            unsafe { gl.Disable(gl::FRAMEBUFFER_SRGB) };
+        }
+        self.framebuffer_srgb = Some(enable);
+        true
+    }
+
+    /// Set the length of the rows of pixel transfers in client memory, 0 if
+    /// they are tightly packed. Rows are byte aligned, and the first texel
+    /// is addressed by the data pointer rather than by skipping texels.
+    ///
+    /// `layout` tells if the row length and skipped texels can be set, only
+    /// the alignment is otherwise.
+    pub(crate) fn set_pixel_store(
+        &mut self,
+        gl: &GlContainer,
+        pack: bool,
+        row_length: gl::types::GLint,
+        layout: bool,
+    ) -> bool {
+        let (current, names) = if pack {
+            (
+                &mut self.pack_row_length,
+                [
+                    gl::PACK_ALIGNMENT,
+                    gl::PACK_ROW_LENGTH,
+                    gl::PACK_SKIP_PIXELS,
+                    gl::PACK_SKIP_ROWS,
+                ],
+            )
+        } else {
+            (
+                &mut self.unpack_row_length,
+                [
+                    gl::UNPACK_ALIGNMENT,
+                    gl::UNPACK_ROW_LENGTH,
+                    gl::UNPACK_SKIP_PIXELS,
+                    gl::UNPACK_SKIP_ROWS,
+                ],
+            )
+        };
+        if *current == Some(row_length) {
+            return false;
+        }
+        let [alignment, length, skip_pixels, skip_rows] = names;
+        unsafe {
+            // Others may have changed any of the parameters.
+            if current.is_none() {
+                gl.PixelStorei(alignment, 1);
+                if layout {
+                    gl.PixelStorei(skip_pixels, 0);
+                    gl.PixelStorei(skip_rows, 0);
+                }
+            }
+            if layout {
+                gl.PixelStorei(length, row_length);
+            }
+        }
+        *current = Some(row_length);
+        true
+    }
+
+    pub(crate) fn set_line_smooth(&mut self, gl: &GlContainer, enable: bool) -> bool {
+        if self.line_smooth == Some(enable) {
+            return false;
+        }
+        if enable {
+            unsafe { gl.Enable(gl::LINE_SMOOTH) };
+        } else {
+            unsafe { gl.Disable(gl::LINE_SMOOTH) };
+        }
+        self.line_smooth = Some(enable);
+        true
+    }
+}
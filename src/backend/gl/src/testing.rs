@@ -0,0 +1,359 @@
+//! Support for rendering tests, running on a headless OSMesa context.
+//!
+//! The context renders in software, without any window system, so tests can
+//! run on CI machines. `TestContext::new` returns `None` if the OSMesa
+//! library isn't installed, letting tests skip themselves.
+//!
+//! ```no_run
+//! extern crate gfx_backend_gl;
+//! extern crate gfx_hal;
+//!
+//! use gfx_backend_gl::testing::TestContext;
+//! use gfx_hal::command::{ClearColor, ClearValue};
+//! use gfx_hal::format::Format;
+//! use gfx_hal::pso::Rect;
+//!
+//! fn main() {
+//!     let mut test = match TestContext::new() {
+//!         Some(test) => test,
+//!         None => return,
+//!     };
+//!     let target = test.create_target(4, 4, Format::Rgba8Unorm);
+//!     test.record(|cmd_buffer| unsafe {
+//!         let rect = Rect { x: 0, y: 0, w: 4, h: 4 };
+//!         let clear = ClearValue::Color(ClearColor::Float([1.0, 0.0, 0.0, 1.0]));
+//!         cmd_buffer.begin_render_pass_inline(&target.render_pass, &target.framebuffer, rect, &[clear]);
+//!     });
+//!     let pixels = test.read_pixels(&target);
+//!     assert_eq!(&pixels[..4], &[255, 0, 0, 255]);
+//!     test.destroy_target(target);
+//! }
+//! ```
+
+use std::ffi::CString;
+use std::iter;
+use std::os::raw::c_void;
+use std::ptr;
+
+use crate::hal::{self, buffer, command, format, image, memory, pass, pool};
+use crate::hal::{Device as _, PhysicalDevice as _};
+
+use crate::{ext::Platform, gl, native as n, AdapterOptions, Backend as B, Device, PhysicalDevice};
+
+/// Headless OSMesa context, current on the thread which created it.
+struct OsMesaContext {
+    raw: osmesa_sys::OSMesaContext,
+    // OSMesa requires a color buffer, even though rendering only ever
+    // happens to framebuffer objects.
+    buffer: Vec<u32>,
+}
+
+impl OsMesaContext {
+    fn new() -> Option<Self> {
+        if let Err(err) = osmesa_sys::OsMesa::try_loading() {
+            warn!("Failed to load OSMesa: {:?}", err);
+            return None;
+        }
+        let attribs = [
+            osmesa_sys::OSMESA_PROFILE,
+            osmesa_sys::OSMESA_CORE_PROFILE,
+            osmesa_sys::OSMESA_CONTEXT_MAJOR_VERSION,
+            3,
+            osmesa_sys::OSMESA_CONTEXT_MINOR_VERSION,
+            3,
+            0,
+        ];
+        let raw =
+            unsafe { osmesa_sys::OSMesaCreateContextAttribs(attribs.as_ptr(), ptr::null_mut()) };
+        if raw.is_null() {
+            warn!("Failed to create an OSMesa context");
+            return None;
+        }
+        let mut context = OsMesaContext {
+            raw,
+            buffer: vec![0; 1],
+        };
+        let current = unsafe {
+            osmesa_sys::OSMesaMakeCurrent(
+                context.raw,
+                context.buffer.as_mut_ptr() as *mut c_void,
+                gl::UNSIGNED_BYTE,
+                1,
+                1,
+            )
+        };
+        if current == 0 {
+            warn!("Failed to make the OSMesa context current");
+            return None;
+        }
+        Some(context)
+    }
+
+    fn get_proc_address(&self, name: &str) -> *const c_void {
+        let name = CString::new(name).unwrap();
+        match unsafe { osmesa_sys::OSMesaGetProcAddress(name.as_ptr()) } {
+            Some(ptr) => ptr as *const c_void,
+            None => ptr::null(),
+        }
+    }
+}
+
+impl Drop for OsMesaContext {
+    fn drop(&mut self) {
+        unsafe { osmesa_sys::OSMesaDestroyContext(self.raw) };
+    }
+}
+
+/// Single-sampled color image, along with a render pass clearing it and its
+/// framebuffer.
+///
+/// The render pass leaves the image in the `TransferSrcOptimal` layout,
+/// ready to be read back.
+pub struct Target {
+    pub image: n::Image,
+    pub view: n::ImageView,
+    pub render_pass: n::RenderPass,
+    pub framebuffer: n::FrameBuffer,
+    pub format: format::Format,
+    pub extent: image::Extent,
+    memory: n::Memory,
+}
+
+/// Device opened on a headless context, with helpers to render to offscreen
+/// images and read them back.
+pub struct TestContext {
+    pub device: Device,
+    pub queue_group: hal::QueueGroup<B, hal::Graphics>,
+    pub memory_types: Vec<hal::MemoryType>,
+    pub limits: hal::Limits,
+    pub command_pool: hal::CommandPool<B, hal::Graphics>,
+    // Destroyed last, once the objects of the device are gone.
+    _context: OsMesaContext,
+}
+
+impl TestContext {
+    /// Open a device on a new headless context, using the default adapter
+    /// options.
+    ///
+    /// Returns `None` if no context could be created.
+    pub fn new() -> Option<Self> {
+        Self::with_options(&AdapterOptions::default())
+    }
+
+    /// Open a device on a new headless context.
+    ///
+    /// Returns `None` if no context could be created.
+    pub fn with_options(options: &AdapterOptions) -> Option<Self> {
+        let context = OsMesaContext::new()?;
+        let fn_proc = |s: &str| context.get_proc_address(s);
+        let adapter = match PhysicalDevice::new_adapter(fn_proc, Platform::Other, options) {
+            Ok(adapter) => adapter,
+            Err(err) => {
+                warn!("Failed to create the adapter: {:?}", err);
+                return None;
+            }
+        };
+        let memory_types = adapter.physical_device.memory_properties().memory_types;
+        let limits = adapter.physical_device.limits();
+        let (device, queue_group) = match adapter.open_with::<_, hal::Graphics>(1, |_| true) {
+            Ok(gpu) => gpu,
+            Err(err) => {
+                warn!("Failed to open the device: {:?}", err);
+                return None;
+            }
+        };
+        let command_pool = unsafe {
+            device.create_command_pool_typed(&queue_group, pool::CommandPoolCreateFlags::empty())
+        }
+        .ok()?;
+
+        Some(TestContext {
+            device,
+            queue_group,
+            memory_types,
+            limits,
+            command_pool,
+            _context: context,
+        })
+    }
+
+    /// Find a memory type allowed by `type_mask` and having the given
+    /// properties.
+    pub fn memory_type(&self, type_mask: u64, properties: memory::Properties) -> hal::MemoryTypeId {
+        self.memory_types
+            .iter()
+            .enumerate()
+            .find(|&(id, ty)| type_mask & (1 << id) != 0 && ty.properties.contains(properties))
+            .map(|(id, _)| hal::MemoryTypeId(id))
+            .expect("No suitable memory type")
+    }
+
+    /// Create an offscreen color target.
+    pub fn create_target(&self, width: u32, height: u32, format: format::Format) -> Target {
+        let kind = image::Kind::D2(width, height, 1, 1);
+        let range = image::SubresourceRange {
+            aspects: format::Aspects::COLOR,
+            levels: 0..1,
+            layers: 0..1,
+        };
+        let attachment = pass::Attachment {
+            format: Some(format),
+            samples: 1,
+            ops: pass::AttachmentOps::new(
+                pass::AttachmentLoadOp::Clear,
+                pass::AttachmentStoreOp::Store,
+            ),
+            stencil_ops: pass::AttachmentOps::DONT_CARE,
+            layouts: image::Layout::Undefined..image::Layout::TransferSrcOptimal,
+        };
+        let subpass = pass::SubpassDesc {
+            colors: &[(0, image::Layout::ColorAttachmentOptimal)],
+            depth_stencil: None,
+            inputs: &[],
+            resolves: &[],
+            preserves: &[],
+        };
+
+        unsafe {
+            let mut image = self
+                .device
+                .create_image(
+                    kind,
+                    1,
+                    format,
+                    image::Tiling::Optimal,
+                    image::Usage::COLOR_ATTACHMENT | image::Usage::TRANSFER_SRC,
+                    image::ViewCapabilities::empty(),
+                )
+                .unwrap();
+            let requirements = self.device.get_image_requirements(&image);
+            let memory_type =
+                self.memory_type(requirements.type_mask, memory::Properties::DEVICE_LOCAL);
+            let memory = self
+                .device
+                .allocate_memory(memory_type, requirements.size)
+                .unwrap();
+            self.device
+                .bind_image_memory(&memory, 0, &mut image)
+                .unwrap();
+            let view = self
+                .device
+                .create_image_view(
+                    &image,
+                    image::ViewKind::D2,
+                    format,
+                    format::Swizzle::NO,
+                    range,
+                )
+                .unwrap();
+            let render_pass = self
+                .device
+                .create_render_pass(&[attachment], &[subpass], &[])
+                .unwrap();
+            let framebuffer = self
+                .device
+                .create_framebuffer(&render_pass, Some(&view), kind.extent())
+                .unwrap();
+
+            Target {
+                image,
+                view,
+                render_pass,
+                framebuffer,
+                format,
+                extent: kind.extent(),
+                memory,
+            }
+        }
+    }
+
+    pub fn destroy_target(&self, target: Target) {
+        unsafe {
+            self.device.destroy_framebuffer(target.framebuffer);
+            self.device.destroy_render_pass(target.render_pass);
+            self.device.destroy_image_view(target.view);
+            self.device.destroy_image(target.image);
+            self.device.free_memory(target.memory);
+        }
+    }
+
+    /// Record a command buffer with the given function, submit it and wait
+    /// for its completion.
+    pub fn record<F>(&mut self, record: F)
+    where
+        F: FnOnce(&mut command::CommandBuffer<B, hal::Graphics>),
+    {
+        let mut cmd_buffer = self
+            .command_pool
+            .acquire_command_buffer::<command::OneShot>();
+        unsafe {
+            cmd_buffer.begin();
+            record(&mut cmd_buffer);
+            cmd_buffer.finish();
+
+            let fence = self.device.create_fence(false).unwrap();
+            self.queue_group.queues[0].submit_nosemaphores(iter::once(&cmd_buffer), Some(&fence));
+            self.device.wait_for_fence(&fence, !0).unwrap();
+            self.device.destroy_fence(fence);
+            self.command_pool.free(iter::once(cmd_buffer));
+        }
+    }
+
+    /// Read back the texels of a target, with tightly packed rows.
+    ///
+    /// The target has to be in the `TransferSrcOptimal` layout.
+    pub fn read_pixels(&mut self, target: &Target) -> Vec<u8> {
+        let image::Extent { width, height, .. } = target.extent;
+        let texel_size = target.format.surface_desc().bits as u64 / 8;
+        let size = texel_size * width as u64 * height as u64;
+
+        unsafe {
+            let mut buffer = self
+                .device
+                .create_buffer(size, buffer::Usage::TRANSFER_DST)
+                .unwrap();
+            let requirements = self.device.get_buffer_requirements(&buffer);
+            let memory_type =
+                self.memory_type(requirements.type_mask, memory::Properties::CPU_VISIBLE);
+            let memory = self
+                .device
+                .allocate_memory(memory_type, requirements.size)
+                .unwrap();
+            self.device
+                .bind_buffer_memory(&memory, 0, &mut buffer)
+                .unwrap();
+
+            self.record(|cmd_buffer| {
+                let region = command::BufferImageCopy {
+                    buffer_offset: 0,
+                    buffer_width: width,
+                    buffer_height: height,
+                    image_layers: image::SubresourceLayers {
+                        aspects: format::Aspects::COLOR,
+                        level: 0,
+                        layers: 0..1,
+                    },
+                    image_offset: image::Offset::ZERO,
+                    image_extent: target.extent,
+                };
+                cmd_buffer.copy_image_to_buffer(
+                    &target.image,
+                    image::Layout::TransferSrcOptimal,
+                    &buffer,
+                    &[region],
+                );
+            });
+
+            let ptr = self.device.map_memory(&memory, 0..size).unwrap();
+            self.device
+                .invalidate_mapped_memory_ranges(iter::once((&memory, 0..size)))
+                .unwrap();
+            let pixels = std::slice::from_raw_parts(ptr, size as usize).to_vec();
+            self.device.unmap_memory(&memory);
+
+            self.device.destroy_buffer(buffer);
+            self.device.free_memory(memory);
+            pixels
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! Recording of the translated command stream.
+//!
+//! When a trace path is set in the `DeviceOptions`, every command buffer
+//! submitted to the queues of the device is written to the trace file,
+//! together with the data blob its commands refer to. A trace can be loaded
+//! back and dumped, either fully or for a range of submissions, which allows
+//! bisecting the submission triggering an issue.
+//!
+//! Only the command streams are recorded, not the creation of the resources
+//! nor their contents, so traces can't be replayed. GL object names are
+//! recorded as-is.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::command::Command;
+
+const MAGIC: &[u8; 8] = b"GFXGLTRC";
+const VERSION: u32 = 1;
+
+/// Command buffer execution, as written to the trace.
+#[derive(Serialize)]
+struct RecordRef<'a> {
+    queue: usize,
+    submission: usize,
+    commands: &'a [Command],
+    data: &'a [u8],
+}
+
+/// Command buffer execution, as read from the trace.
+#[derive(Debug, Deserialize)]
+struct Record {
+    queue: usize,
+    submission: usize,
+    commands: Vec<Command>,
+    data: Vec<u8>,
+}
+
+/// Writer of a trace file, shared by all the queues of a device.
+pub(crate) struct Recorder {
+    writer: BufWriter<File>,
+    submission: usize,
+}
+
+impl Recorder {
+    pub(crate) fn create(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        info!("Recording trace to {:?}", path);
+        Ok(Recorder {
+            writer,
+            submission: 0,
+        })
+    }
+
+    /// Record the execution of a command buffer in the current submission.
+    pub(crate) fn record(&mut self, queue: usize, commands: &[Command], data: &[u8]) {
+        let record = RecordRef {
+            queue,
+            submission: self.submission,
+            commands,
+            data,
+        };
+        if let Err(err) = bincode::serialize_into(&mut self.writer, &record) {
+            error!("Failed to record command buffer: {}", err);
+        }
+    }
+
+    /// End the current submission, making sure it reached the file in case
+    /// executing it crashes the application.
+    pub(crate) fn end_submission(&mut self) {
+        self.submission += 1;
+        if let Err(err) = self.writer.flush() {
+            error!("Failed to flush trace: {}", err);
+        }
+    }
+}
+
+/// Trace recorded by a device, loaded for inspection.
+#[derive(Debug)]
+pub struct Trace {
+    records: Vec<Record>,
+}
+
+impl Trace {
+    /// Load a trace file.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header = [0; 12];
+        reader.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a GL trace"));
+        }
+        let mut version = [0; 4];
+        version.copy_from_slice(&header[8..]);
+        if u32::from_le_bytes(version) != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Unsupported GL trace version",
+            ));
+        }
+
+        let mut records = Vec::new();
+        loop {
+            match bincode::deserialize_from(&mut reader) {
+                Ok(record) => records.push(record),
+                Err(err) => match *err {
+                    // Traces of crashed applications might be truncated.
+                    bincode::ErrorKind::Io(ref err)
+                        if err.kind() == io::ErrorKind::UnexpectedEof =>
+                    {
+                        break
+                    }
+                    _ => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+                },
+            }
+        }
+        Ok(Trace { records })
+    }
+
+    /// Number of submissions in the trace.
+    pub fn submission_count(&self) -> usize {
+        self.records
+            .last()
+            .map_or(0, |record| record.submission + 1)
+    }
+
+    /// Write the commands of the command buffers executed by a range of
+    /// submissions, in a human readable form.
+    pub fn dump<W: Write>(&self, submissions: Range<usize>, mut writer: W) -> io::Result<()> {
+        let records = self.records.iter().filter(|record| {
+            submissions.start <= record.submission && record.submission < submissions.end
+        });
+        for record in records {
+            writeln!(
+                writer,
+                "Submission {} of queue {}, {} bytes of data:",
+                record.submission,
+                record.queue,
+                record.data.len()
+            )?;
+            for command in &record.commands {
+                writeln!(writer, "    {:?}", command)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -50,12 +50,48 @@ use hal::{
 };
 
 use crate::{
+    ext::Platform,
     native,
-    Backend as B, Device, PhysicalDevice, QueueFamily, Starc
+    AdapterOptions, Backend as B, Device, PhysicalDevice, QueueFamily, Starc
 };
 
 use glutin::{self, GlContext};
 
+use std::sync::Mutex;
+
+/// Window system interface a context was created with.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "openbsd"
+))]
+fn platform(context: &glutin::Context) -> Platform {
+    use glutin::os::{unix::RawHandle, GlContextExt};
+    // OSMesa contexts are reported as EGL ones, which is harmless as EGL
+    // functions can't be found on them.
+    match unsafe { context.raw_handle() } {
+        RawHandle::Glx(_) => Platform::Glx,
+        RawHandle::Egl(_) => Platform::Egl,
+    }
+}
+
+#[cfg(target_os = "android")]
+fn platform(_context: &glutin::Context) -> Platform {
+    Platform::Egl
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "android"
+)))]
+fn platform(_context: &glutin::Context) -> Platform {
+    Platform::Other
+}
+
 fn get_window_extent(window: &glutin::GlWindow) -> image::Extent {
     let px = window
         .get_inner_size()
@@ -71,6 +107,46 @@ fn get_window_extent(window: &glutin::GlWindow) -> image::Extent {
 pub struct Swapchain {
     // Underlying window, required for presentation
     pub(crate) window: Starc<glutin::GlWindow>,
+    // Read back the images before presenting them.
+    pub(crate) readback: bool,
+    // Extent and texels of the last image read back.
+    pub(crate) presented: Mutex<Option<(image::Extent, Vec<u8>)>>,
+}
+
+impl Swapchain {
+    /// Keep a copy of the images presented from now on, to be read with
+    /// `CommandQueue::read_presented_image`.
+    ///
+    /// Once presented, the content of the default framebuffer is undefined,
+    /// so the images are read back to host memory right before the buffers
+    /// are swapped, which stalls every presentation until the GPU is done
+    /// rendering the frame.
+    pub fn set_readback(&mut self, enable: bool) {
+        self.readback = enable;
+        if !enable {
+            *self.presented.lock().unwrap() = None;
+        }
+    }
+
+    /// Extent of the images, following the size of the window.
+    pub(crate) fn extent(&self) -> image::Extent {
+        get_window_extent(&self.window)
+    }
+
+    /// Swap the buffers of the window.
+    ///
+    /// Called by the queue on the thread owning the window, which is the
+    /// worker if the queue was created on one: presenting from any other
+    /// thread forwards the presentation to it, the same way submissions are.
+    /// The context is made current if it isn't yet, which hands it over to
+    /// the owning thread when the window was moved there from the event loop
+    /// thread.
+    pub(crate) fn present(&self) -> Result<(), glutin::ContextError> {
+        if !self.window.is_current() {
+            unsafe { self.window.make_current()? };
+        }
+        self.window.swap_buffers()
+    }
 }
 
 impl hal::Swapchain<B> for Swapchain {
@@ -78,9 +154,14 @@ impl hal::Swapchain<B> for Swapchain {
         &mut self,
         _timeout_ns: u64,
         _semaphore: Option<&native::Semaphore>,
-        _fence: Option<&native::Fence>,
+        fence: Option<&native::Fence>,
     ) -> Result<hal::SwapImageIndex, hal::AcquireError> {
-        // TODO: sync
+        // Buffer swaps are ordered by the driver, so the image is available
+        // as soon as we return it. The semaphore is left unsignalled, which
+        // makes waiting on it a no-op.
+        if let Some(fence) = fence {
+            fence.0.set(native::FenceState::Signalled);
+        }
         Ok(0)
     }
 }
@@ -162,8 +243,8 @@ impl hal::Surface<B> for Surface {
         (caps, Some(self.swapchain_formats()), present_modes)
     }
 
-    fn supports_queue_family(&self, _: &QueueFamily) -> bool {
-        true
+    fn supports_queue_family(&self, family: &QueueFamily) -> bool {
+        *family == QueueFamily::General
     }
 }
 
@@ -175,21 +256,47 @@ impl Device {
     ) -> (Swapchain, hal::Backbuffer<B>) {
         let swapchain = Swapchain {
             window: surface.window.clone(),
+            readback: false,
+            presented: Mutex::new(None),
         };
         let backbuffer = hal::Backbuffer::Framebuffer(0);
         (swapchain, backbuffer)
     }
 }
 
+impl Surface {
+    /// Enumerate the adapters, created with the given options.
+    ///
+    /// Contrary to `enumerate_adapters`, the environment isn't looked up.
+    pub fn enumerate_adapters_with_options(
+        &self,
+        options: &AdapterOptions,
+    ) -> Vec<hal::Adapter<B>> {
+        if let Err(err) = unsafe { self.window.make_current() } {
+            error!("Failed to make the context current: {:?}", err);
+            return Vec::new();
+        }
+        let platform = platform(self.window.context());
+        let fn_proc = |s: &str| self.window.get_proc_address(s) as *const _;
+        PhysicalDevice::new_adapter(fn_proc, platform, options)
+            .into_iter()
+            .collect()
+    }
+}
+
 impl hal::Instance for Surface {
     type Backend = B;
     fn enumerate_adapters(&self) -> Vec<hal::Adapter<B>> {
-        unsafe { self.window.make_current().unwrap() };
-        let adapter = PhysicalDevice::new_adapter(|s| self.window.get_proc_address(s) as *const _);
-        vec![adapter]
+        self.enumerate_adapters_with_options(&AdapterOptions::from_env())
     }
 }
 
+/// Configure a context for a swapchain of the given formats.
+///
+/// Unless the builder already has a robustness other than the default
+/// `NotRobust`, robust access is requested when available, so
+/// `Features::ROBUST_BUFFER_ACCESS` can be supported, along with contexts
+/// lost on GPU resets, which are then reported.
 pub fn config_context(
     builder: glutin::ContextBuilder,
     color_format: f::Format,
@@ -201,11 +308,16 @@ pub fn config_context(
         Some(fm) => fm.base_format().0.describe_bits(),
         None => f::BITS_ZERO,
     };
-    builder
+    let builder = builder
         .with_depth_buffer(depth_bits.depth)
         .with_stencil_buffer(depth_bits.stencil)
         .with_pixel_format(color_bits.color, color_bits.alpha)
-        .with_srgb(color_base.1 == f::ChannelType::Srgb)
+        .with_srgb(color_base.1 == f::ChannelType::Srgb);
+    if builder.gl_attr.robustness == glutin::Robustness::NotRobust {
+        builder.with_gl_robustness(glutin::Robustness::TryRobustLoseContextOnReset)
+    } else {
+        builder
+    }
 }
 
 pub struct Headless(pub glutin::Context);
@@ -213,11 +325,29 @@ pub struct Headless(pub glutin::Context);
 unsafe impl Send for Headless {}
 unsafe impl Sync for Headless {}
 
+impl Headless {
+    /// Enumerate the adapters, created with the given options.
+    ///
+    /// Contrary to `enumerate_adapters`, the environment isn't looked up.
+    pub fn enumerate_adapters_with_options(
+        &self,
+        options: &AdapterOptions,
+    ) -> Vec<hal::Adapter<B>> {
+        if let Err(err) = unsafe { self.0.make_current() } {
+            error!("Failed to make the context current: {:?}", err);
+            return Vec::new();
+        }
+        let platform = platform(&self.0);
+        let fn_proc = |s: &str| self.0.get_proc_address(s) as *const _;
+        PhysicalDevice::new_adapter(fn_proc, platform, options)
+            .into_iter()
+            .collect()
+    }
+}
+
 impl hal::Instance for Headless {
     type Backend = B;
     fn enumerate_adapters(&self) -> Vec<hal::Adapter<B>> {
-        unsafe { self.0.make_current().unwrap() };
-        let adapter = PhysicalDevice::new_adapter(|s| self.0.get_proc_address(s) as *const _);
-        vec![adapter]
+        self.enumerate_adapters_with_options(&AdapterOptions::from_env())
     }
 }
@@ -0,0 +1,242 @@
+//! Dedicated thread owning the GL context.
+//!
+//! An OpenGL context can only be current on a single thread, which is why the
+//! shared data of the backend is only accessible from the thread it was
+//! created on. Running the context on a worker thread allows submitting work
+//! to the queues from any thread: the submissions are forwarded to the worker
+//! and executed there.
+//!
+//! This is also how swapchains are presented from a render thread different
+//! from the one driving the event loop: the window is created on the event
+//! loop thread, without making its context current there, and moved to the
+//! worker. The surface created on the worker makes the context current on it
+//! and presentations are forwarded to it.
+//!
+//! ```no_run
+//! extern crate glutin;
+//! extern crate gfx_backend_gl;
+//! extern crate gfx_hal;
+//!
+//! use gfx_hal::Instance;
+//! use gfx_backend_gl::{Surface, Worker};
+//! use glutin::{ContextBuilder, EventsLoop, GlWindow, WindowBuilder};
+//!
+//! fn main() {
+//!     let events_loop = EventsLoop::new();
+//!     let window = GlWindow::new(WindowBuilder::new(), ContextBuilder::new(), &events_loop)
+//!         .expect("Failed to build window");
+//!     let (worker, (surface, adapters)) = Worker::spawn(move || {
+//!         let surface = Surface::from_window(window);
+//!         let adapters = surface.enumerate_adapters();
+//!         (surface, adapters)
+//!     });
+//!     // The window is owned by the worker from now on.
+//!     worker.run(|| surface.window().resize((640, 480).into()));
+//!     # let _ = adapters;
+//! }
+//! ```
+//!
+//! ```no_run
+//! extern crate glutin;
+//! extern crate gfx_backend_gl;
+//! extern crate gfx_hal;
+//!
+//! use gfx_hal::{Instance, PhysicalDevice};
+//! use gfx_backend_gl::{Headless, Worker};
+//! use glutin::{Context, ContextBuilder, EventsLoop};
+//!
+//! fn main() {
+//!     // The context has to be created on the worker thread.
+//!     let (worker, (headless, mut adapters)) = Worker::spawn(|| {
+//!         let events_loop = EventsLoop::new();
+//!         let context = Context::new(&events_loop, ContextBuilder::new(), false)
+//!             .expect("Failed to build headless context");
+//!         let headless = Headless(context);
+//!         let adapters = headless.enumerate_adapters();
+//!         (headless, adapters)
+//!     });
+//!
+//!     let adapter = adapters.remove(0);
+//!     let _gpu = worker.run(move || unsafe {
+//!         let family = &adapter.queue_families[0];
+//!         adapter.physical_device.open(&[(family, &[1.0])], gfx_hal::Features::empty())
+//!     });
+//!     # let _ = headless;
+//! }
+//! ```
+
+use std::cell::RefCell;
+use std::mem;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle, ThreadId};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+enum Message {
+    Run(Job),
+    Quit,
+}
+
+thread_local! {
+    // Handle to the worker running on the current thread, if any.
+    static CURRENT: RefCell<Option<Handle>> = RefCell::new(None);
+}
+
+/// Handle to the worker thread, used to forward work to it.
+#[derive(Clone, Debug)]
+pub(crate) struct Handle {
+    sender: Arc<Mutex<Sender<Message>>>,
+    thread: ThreadId,
+}
+
+impl Handle {
+    /// Returns the handle of the worker running on the calling thread.
+    pub(crate) fn current() -> Option<Self> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    /// Check if the calling thread is the worker thread.
+    pub(crate) fn is_current(&self) -> bool {
+        thread::current().id() == self.thread
+    }
+
+    /// Execute a function on the worker thread, blocking until it has
+    /// completed. Runs the function directly if called from the worker.
+    ///
+    /// # Safety
+    ///
+    /// The function and its result are sent across threads without
+    /// requiring them to be `Send`.
+    pub(crate) unsafe fn run_unchecked<F, R>(&self, fun: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        if self.is_current() {
+            return fun();
+        }
+
+        struct SendJob(*mut (dyn FnMut() + 'static));
+        unsafe impl Send for SendJob {}
+
+        let mut fun = Some(fun);
+        let mut result = None;
+        {
+            let mut job = || result = Some((fun.take().unwrap())());
+            let job: &mut dyn FnMut() = &mut job;
+            // The borrow outlives the job, as we are blocking until it's done.
+            let job = SendJob(mem::transmute(job));
+            let (done_tx, done_rx) = mpsc::channel();
+            self.sender
+                .lock()
+                .unwrap()
+                .send(Message::Run(Box::new(move || {
+                    let SendJob(job) = job;
+                    (*job)();
+                    let _ = done_tx.send(());
+                })))
+                .expect("GL worker thread has terminated");
+            done_rx.recv().expect("GL worker thread panicked");
+        }
+        result.unwrap()
+    }
+
+    /// Queue a function for execution on the worker thread, without waiting
+    /// for it.
+    ///
+    /// # Safety
+    ///
+    /// The function is sent across threads without requiring it to be
+    /// `Send`, and anything it borrows has to outlive its execution.
+    pub(crate) unsafe fn post_unchecked<'a, F>(&self, fun: F)
+    where
+        F: FnOnce() + 'a,
+    {
+        struct SendJob(Box<dyn FnOnce()>);
+        unsafe impl Send for SendJob {}
+
+        let job: Box<dyn FnOnce() + 'a> = Box::new(fun);
+        // The borrows outlive the job, as required from the caller.
+        let job = mem::transmute::<Box<dyn FnOnce() + 'a>, Box<dyn FnOnce()>>(job);
+        let job = SendJob(job);
+        self.sender
+            .lock()
+            .unwrap()
+            .send(Message::Run(Box::new(move || {
+                let SendJob(job) = job;
+                job();
+            })))
+            .expect("GL worker thread has terminated");
+    }
+}
+
+/// Thread owning the GL context.
+///
+/// Command queues created on the worker forward their submissions and
+/// presentations to it when called from other threads. Any other access to
+/// the device has to happen on the worker, using `run`.
+#[derive(Debug)]
+pub struct Worker {
+    handle: Handle,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    /// Spawn the worker thread, running `init` on it.
+    ///
+    /// `init` is expected to create the context, make it current and
+    /// enumerate the adapters.
+    pub fn spawn<F, T>(init: F) -> (Self, T)
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let (init_tx, init_rx) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("gfx-gl-worker".into())
+            .spawn(move || {
+                let handle = Handle {
+                    sender: Arc::new(Mutex::new(sender)),
+                    thread: thread::current().id(),
+                };
+                CURRENT.with(|current| *current.borrow_mut() = Some(handle.clone()));
+                let value = init();
+                init_tx.send((handle, value)).unwrap();
+
+                for message in receiver {
+                    match message {
+                        Message::Run(job) => job(),
+                        Message::Quit => break,
+                    }
+                }
+                CURRENT.with(|current| *current.borrow_mut() = None);
+            })
+            .expect("Failed to spawn GL worker thread");
+
+        let (handle, value) = init_rx.recv().expect("GL worker initialization failed");
+        let worker = Worker {
+            handle,
+            thread: Some(thread),
+        };
+        (worker, value)
+    }
+
+    /// Execute a function on the worker thread and wait for its result.
+    pub fn run<F, R>(&self, fun: F) -> R
+    where
+        F: FnOnce() -> R + Send,
+        R: Send,
+    {
+        unsafe { self.handle.run_unchecked(fun) }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let _ = self.handle.sender.lock().unwrap().send(Message::Quit);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -457,6 +457,7 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
             // and those need to operate on sizes being multiples of 4.
             non_coherent_atom_size: 4,
             max_sampler_anisotropy: 16.,
+            line_width_range: [1.0, 1.0],
             min_vertex_input_binding_stride_alignment: STRIDE_GRANULARITY as u64,
 
             .. hal::Limits::default() // TODO!
@@ -815,6 +815,7 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
             buffer_image_granularity: limits.buffer_image_granularity,
             non_coherent_atom_size: limits.non_coherent_atom_size as _,
             max_sampler_anisotropy: limits.max_sampler_anisotropy,
+            line_width_range: limits.line_width_range,
             min_vertex_input_binding_stride_alignment: 1,
             .. Limits::default() //TODO: please halp
         }
@@ -342,6 +342,9 @@ pub struct Limits {
     pub max_viewport_dimensions: [image::Size; 2],
     ///
     pub max_framebuffer_extent: image::Extent,
+    /// Range of widths of rasterized lines. Widths other than 1 require
+    /// `Features::LINE_WIDTH`.
+    pub line_width_range: [f32; 2],
 
     ///
     pub min_memory_map_alignment: usize,